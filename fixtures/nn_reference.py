@@ -0,0 +1,125 @@
+#!/usr/bin/env python3
+#
+# Independent reference forward pass for the network in src/nn.rs, written
+# with plain loops in double precision. Regenerate the fixture with:
+#
+#   python3 fixtures/nn_reference.py > fixtures/nn_reference.txt
+
+import math
+
+PLANES = 8 * 14 + 7
+POLICY_SIZE = 73 * 64
+
+FILTERS = 8
+BLOCKS = 2
+POLICY_CHANNELS = 2
+VALUE_CHANNELS = 1
+VALUE_HIDDEN = 16
+
+state = 0x6E63
+
+
+def lcg():
+    global state
+    state = (state * 6364136223846793005 + 1442695040888963407) % (1 << 64)
+    return ((state >> 40) - (1 << 23)) / float(1 << 24)
+
+
+def tensor(n):
+    return [lcg() for _ in range(n)]
+
+
+def conv_bn(cin, cout, k):
+    layer = {
+        "cin": cin,
+        "cout": cout,
+        "k": k,
+        "w": tensor(cout * cin * k * k),
+        "b": tensor(cout),
+        "gamma": tensor(cout),
+        "beta": tensor(cout),
+        "mean": tensor(cout),
+        "var": tensor(cout),
+    }
+    layer["var"] = [1.0 + 2.0 * abs(v) for v in layer["var"]]
+    return layer
+
+
+def dense(cin, cout):
+    return {"cin": cin, "cout": cout, "w": tensor(cout * cin), "b": tensor(cout)}
+
+
+def apply_conv_bn(layer, x):
+    cin, cout, k = layer["cin"], layer["cout"], layer["k"]
+    half = k // 2
+    out = []
+
+    for o in range(cout):
+        plane = []
+        for s in range(64):
+            r, f = divmod(s, 8)
+            acc = layer["b"][o]
+            for c in range(cin):
+                for ky in range(k):
+                    for kx in range(k):
+                        rr, ff = r + ky - half, f + kx - half
+                        if 0 <= rr < 8 and 0 <= ff < 8:
+                            w = layer["w"][((o * cin + c) * k + ky) * k + kx]
+                            acc += w * x[c][rr * 8 + ff]
+            scale = layer["gamma"][o] / math.sqrt(layer["var"][o] + 1e-5)
+            plane.append((acc - layer["mean"][o]) * scale + layer["beta"][o])
+        out.append(plane)
+
+    return out
+
+
+def apply_dense(layer, x):
+    cin = layer["cin"]
+    return [
+        layer["b"][o] + sum(layer["w"][o * cin + i] * x[i] for i in range(cin))
+        for o in range(layer["cout"])
+    ]
+
+
+def relu(x):
+    return [[max(v, 0.0) for v in plane] for plane in x]
+
+
+net = {"input": conv_bn(PLANES, FILTERS, 3)}
+net["blocks"] = [
+    (conv_bn(FILTERS, FILTERS, 3), conv_bn(FILTERS, FILTERS, 3)) for _ in range(BLOCKS)
+]
+net["policy_conv"] = conv_bn(FILTERS, POLICY_CHANNELS, 1)
+net["policy_fc"] = dense(POLICY_CHANNELS * 64, POLICY_SIZE)
+net["value_conv"] = conv_bn(FILTERS, VALUE_CHANNELS, 1)
+net["value_fc1"] = dense(VALUE_CHANNELS * 64, VALUE_HIDDEN)
+net["value_fc2"] = dense(VALUE_HIDDEN, 1)
+
+
+def forward(planes):
+    x = relu(apply_conv_bn(net["input"], planes))
+
+    for a, b in net["blocks"]:
+        y = relu(apply_conv_bn(a, x))
+        y = apply_conv_bn(b, y)
+        x = relu([[u + v for u, v in zip(p, q)] for p, q in zip(y, x)])
+
+    p = relu(apply_conv_bn(net["policy_conv"], x))
+    logits = apply_dense(net["policy_fc"], [v for plane in p for v in plane])
+
+    v = relu(apply_conv_bn(net["value_conv"], x))
+    h = apply_dense(net["value_fc1"], [u for plane in v for u in plane])
+    h = [max(u, 0.0) for u in h]
+    value = math.tanh(apply_dense(net["value_fc2"], h)[0])
+
+    return value, logits
+
+
+for b in range(2):
+    flat = [1.0 if (i * 31 + b * 17) % 11 == 0 else 0.0 for i in range(PLANES * 64)]
+    planes = [flat[c * 64:(c + 1) * 64] for c in range(PLANES)]
+    value, logits = forward(planes)
+
+    print("%.8e" % value)
+    for l in logits:
+        print("%.8e" % l)
@@ -0,0 +1,745 @@
+/*
+ * Minimal reverse-mode automatic differentiation over flat f32 tensors, with
+ * just the operations needed to train the network in nn.rs. Board tensors
+ * are laid out [batch][channel][square] and dense ones [batch][feature].
+ */
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Var(usize);
+
+enum Op {
+    Leaf,
+    Conv {
+        x: Var,
+        w: Var,
+        b: Var,
+        cin: usize,
+        cout: usize,
+        k: usize,
+    },
+    BatchNorm {
+        x: Var,
+        gamma: Var,
+        beta: Var,
+        channels: usize,
+        mean: Vec<f32>,
+        var: Vec<f32>,
+        train: bool, /* Statistics come from the batch rather than fixed values */
+    },
+    Dense {
+        x: Var,
+        w: Var,
+        b: Var,
+        cin: usize,
+        cout: usize,
+    },
+    Relu(Var),
+    Tanh(Var),
+    Add(Var, Var),
+    SoftmaxXent {
+        logits: Var,
+        target: Vec<f32>,
+        classes: usize,
+    },
+    Mse {
+        x: Var,
+        target: Vec<f32>,
+    },
+}
+
+struct Node {
+    value: Vec<f32>,
+    grad: Vec<f32>,
+    op: Op,
+}
+
+pub const BN_EPSILON: f32 = 1e-5;
+
+/* out[m][n] += a[m][k] * b[k][n] */
+fn matmul(a: &[f32], b: &[f32], m: usize, k: usize, n: usize, out: &mut [f32]) {
+    for i in 0..m {
+        for j in 0..k {
+            let w = a[i * k + j];
+
+            for (o, v) in out[i * n..(i + 1) * n]
+                .iter_mut()
+                .zip(&b[j * n..(j + 1) * n])
+            {
+                *o += w * v;
+            }
+        }
+    }
+}
+
+/* out[m][n] += a[m][k] * b[n][k]^T */
+fn matmul_bt(a: &[f32], b: &[f32], m: usize, k: usize, n: usize, out: &mut [f32]) {
+    for i in 0..m {
+        let row = &a[i * k..(i + 1) * k];
+
+        for j in 0..n {
+            out[i * n + j] += row
+                .iter()
+                .zip(&b[j * k..(j + 1) * k])
+                .map(|(x, y)| x * y)
+                .sum::<f32>();
+        }
+    }
+}
+
+/* out[k][n] += a[m][k]^T * b[m][n] */
+fn matmul_at(a: &[f32], b: &[f32], m: usize, k: usize, n: usize, out: &mut [f32]) {
+    for i in 0..m {
+        for j in 0..k {
+            let w = a[i * k + j];
+
+            for (o, v) in out[j * n..(j + 1) * n]
+                .iter_mut()
+                .zip(&b[i * n..(i + 1) * n])
+            {
+                *o += w * v;
+            }
+        }
+    }
+}
+
+/* For each kernel row and output square, the input square it reads (if on the board) */
+fn im2col_map(k: usize) -> Vec<Option<usize>> {
+    let half = (k / 2) as i32;
+    let mut out = Vec::with_capacity(k * k * 64);
+
+    for ky in 0..k {
+        for kx in 0..k {
+            for s in 0..64i32 {
+                let r = s / 8 + ky as i32 - half;
+                let f = s % 8 + kx as i32 - half;
+
+                out.push(if (0..8).contains(&r) && (0..8).contains(&f) {
+                    Some((r * 8 + f) as usize)
+                } else {
+                    None
+                });
+            }
+        }
+    }
+
+    out
+}
+
+/* Expands one board [cin][64] into columns [cin * k * k][64] */
+fn im2col(x: &[f32], cin: usize, k: usize, map: &[Option<usize>]) -> Vec<f32> {
+    let kk = k * k;
+    let mut col = vec![0.0; cin * kk * 64];
+
+    for c in 0..cin {
+        for (i, src) in map.iter().enumerate() {
+            if let Some(s) = src {
+                col[c * kk * 64 + i] = x[c * 64 + s];
+            }
+        }
+    }
+
+    col
+}
+
+#[derive(Default)]
+pub struct Graph {
+    nodes: Vec<Node>,
+}
+
+impl Graph {
+    pub fn new() -> Graph {
+        Graph::default()
+    }
+
+    fn push(&mut self, value: Vec<f32>, op: Op) -> Var {
+        self.nodes.push(Node {
+            value,
+            grad: Vec::new(),
+            op,
+        });
+
+        Var(self.nodes.len() - 1)
+    }
+
+    pub fn leaf(&mut self, value: Vec<f32>) -> Var {
+        self.push(value, Op::Leaf)
+    }
+
+    pub fn value(&self, v: Var) -> &[f32] {
+        &self.nodes[v.0].value
+    }
+
+    /* Gradient of the last `backward` loss; empty if `v` did not contribute */
+    pub fn grad(&self, v: Var) -> &[f32] {
+        &self.nodes[v.0].grad
+    }
+
+    /* Moves a value out of the graph */
+    pub fn take(&mut self, v: Var) -> Vec<f32> {
+        std::mem::take(&mut self.nodes[v.0].value)
+    }
+
+    /* Batch statistics (mean, variance) computed by a training mode batch norm */
+    pub fn batch_stats(&self, v: Var) -> (&[f32], &[f32]) {
+        match &self.nodes[v.0].op {
+            Op::BatchNorm { mean, var, .. } => (mean, var),
+            _ => panic!("not a batch norm"),
+        }
+    }
+
+    /* Zero padded k x k convolution. `w` is [cout][cin][k][k]. */
+    pub fn conv(&mut self, x: Var, w: Var, b: Var, cin: usize, cout: usize, k: usize) -> Var {
+        let n = self.value(x).len() / (cin * 64);
+        let map = im2col_map(k);
+        let mut out = vec![0.0; n * cout * 64];
+
+        for i in 0..n {
+            let col = im2col(
+                &self.value(x)[i * cin * 64..(i + 1) * cin * 64],
+                cin,
+                k,
+                &map,
+            );
+            let y = &mut out[i * cout * 64..(i + 1) * cout * 64];
+
+            for o in 0..cout {
+                for v in y[o * 64..(o + 1) * 64].iter_mut() {
+                    *v = self.value(b)[o];
+                }
+            }
+
+            matmul(self.value(w), &col, cout, cin * k * k, 64, y);
+        }
+
+        self.push(
+            out,
+            Op::Conv {
+                x,
+                w,
+                b,
+                cin,
+                cout,
+                k,
+            },
+        )
+    }
+
+    /*
+     * Per-channel batch norm. With `stats` the given (mean, variance) are
+     * used as in inference; without, they are computed from the batch.
+     */
+    pub fn batch_norm(
+        &mut self,
+        x: Var,
+        gamma: Var,
+        beta: Var,
+        channels: usize,
+        stats: Option<(&[f32], &[f32])>,
+    ) -> Var {
+        let xs = self.value(x);
+        let n = xs.len() / (channels * 64);
+        let count = (n * 64) as f32;
+
+        let (mean, var) = match stats {
+            Some((m, v)) => (m.to_vec(), v.to_vec()),
+            None => {
+                let mut mean = vec![0.0; channels];
+                let mut var = vec![0.0; channels];
+
+                for i in 0..n {
+                    for (c, m) in mean.iter_mut().enumerate() {
+                        *m += xs[(i * channels + c) * 64..][..64].iter().sum::<f32>() / count;
+                    }
+                }
+
+                for i in 0..n {
+                    for (c, v) in var.iter_mut().enumerate() {
+                        for x in xs[(i * channels + c) * 64..][..64].iter() {
+                            *v += (x - mean[c]) * (x - mean[c]) / count;
+                        }
+                    }
+                }
+
+                (mean, var)
+            }
+        };
+
+        let mut out = xs.to_vec();
+
+        for i in 0..n {
+            for c in 0..channels {
+                let inv = 1.0 / (var[c] + BN_EPSILON).sqrt();
+                let (g, b) = (self.value(gamma)[c], self.value(beta)[c]);
+
+                for v in out[(i * channels + c) * 64..][..64].iter_mut() {
+                    *v = g * (*v - mean[c]) * inv + b;
+                }
+            }
+        }
+
+        self.push(
+            out,
+            Op::BatchNorm {
+                x,
+                gamma,
+                beta,
+                channels,
+                mean,
+                var,
+                train: stats.is_none(),
+            },
+        )
+    }
+
+    /* Fully connected layer. `w` is [cout][cin]. */
+    pub fn dense(&mut self, x: Var, w: Var, b: Var, cin: usize, cout: usize) -> Var {
+        let n = self.value(x).len() / cin;
+        let mut out = Vec::with_capacity(n * cout);
+
+        for _ in 0..n {
+            out.extend_from_slice(self.value(b));
+        }
+
+        matmul_bt(self.value(x), self.value(w), n, cin, cout, &mut out);
+
+        self.push(out, Op::Dense { x, w, b, cin, cout })
+    }
+
+    pub fn relu(&mut self, x: Var) -> Var {
+        let out = self.value(x).iter().map(|v| v.max(0.0)).collect();
+        self.push(out, Op::Relu(x))
+    }
+
+    pub fn tanh(&mut self, x: Var) -> Var {
+        let out = self.value(x).iter().map(|v| v.tanh()).collect();
+        self.push(out, Op::Tanh(x))
+    }
+
+    pub fn add(&mut self, a: Var, b: Var) -> Var {
+        let out = self
+            .value(a)
+            .iter()
+            .zip(self.value(b))
+            .map(|(x, y)| x + y)
+            .collect();
+
+        self.push(out, Op::Add(a, b))
+    }
+
+    /* Mean cross-entropy between softmax(logits) and target distributions */
+    pub fn softmax_xent(&mut self, logits: Var, target: Vec<f32>, classes: usize) -> Var {
+        let l = self.value(logits);
+        let n = l.len() / classes;
+        let mut loss = 0.0;
+
+        for i in 0..n {
+            let row = &l[i * classes..(i + 1) * classes];
+            let max = row.iter().cloned().fold(f32::NEG_INFINITY, f32::max);
+            let lse = max + row.iter().map(|v| (v - max).exp()).sum::<f32>().ln();
+
+            for (v, t) in row.iter().zip(&target[i * classes..(i + 1) * classes]) {
+                if *t != 0.0 {
+                    loss -= t * (v - lse);
+                }
+            }
+        }
+
+        self.push(
+            vec![loss / n as f32],
+            Op::SoftmaxXent {
+                logits,
+                target,
+                classes,
+            },
+        )
+    }
+
+    /* Mean squared error over all elements */
+    pub fn mse(&mut self, x: Var, target: Vec<f32>) -> Var {
+        let xs = self.value(x);
+        let loss = xs
+            .iter()
+            .zip(target.iter())
+            .map(|(a, b)| (a - b) * (a - b))
+            .sum::<f32>()
+            / xs.len() as f32;
+
+        self.push(vec![loss], Op::Mse { x, target })
+    }
+
+    fn accumulate(&mut self, v: Var, delta: &[f32]) {
+        let node = &mut self.nodes[v.0];
+
+        if node.grad.is_empty() {
+            node.grad = vec![0.0; node.value.len()];
+        }
+
+        for (g, d) in node.grad.iter_mut().zip(delta) {
+            *g += d;
+        }
+    }
+
+    /* Computes the gradient of the scalar `loss` with respect to every node */
+    pub fn backward(&mut self, loss: Var) {
+        for n in self.nodes.iter_mut() {
+            n.grad.clear();
+        }
+
+        self.nodes[loss.0].grad = vec![1.0];
+
+        for i in (0..=loss.0).rev() {
+            if self.nodes[i].grad.is_empty() {
+                continue;
+            }
+
+            let dy = std::mem::take(&mut self.nodes[i].grad);
+            let op = std::mem::replace(&mut self.nodes[i].op, Op::Leaf);
+
+            self.backward_op(&op, Var(i), &dy);
+
+            self.nodes[i].grad = dy;
+            self.nodes[i].op = op;
+        }
+    }
+
+    fn backward_op(&mut self, op: &Op, y: Var, dy: &[f32]) {
+        match *op {
+            Op::Leaf => (),
+            Op::Conv {
+                x,
+                w,
+                b,
+                cin,
+                cout,
+                k,
+            } => {
+                let n = dy.len() / (cout * 64);
+                let rows = cin * k * k;
+                let map = im2col_map(k);
+
+                let mut dw = vec![0.0; cout * rows];
+                let mut db = vec![0.0; cout];
+                let mut dx = vec![0.0; n * cin * 64];
+
+                for i in 0..n {
+                    let col = im2col(&self.value(x)[i * cin * 64..][..cin * 64], cin, k, &map);
+                    let dyi = &dy[i * cout * 64..][..cout * 64];
+
+                    matmul_bt(dyi, &col, cout, 64, rows, &mut dw);
+
+                    for o in 0..cout {
+                        db[o] += dyi[o * 64..(o + 1) * 64].iter().sum::<f32>();
+                    }
+
+                    let mut dcol = vec![0.0; rows * 64];
+                    matmul_at(self.value(w), dyi, cout, rows, 64, &mut dcol);
+
+                    let dxi = &mut dx[i * cin * 64..][..cin * 64];
+
+                    for c in 0..cin {
+                        for (j, src) in map.iter().enumerate() {
+                            if let Some(s) = src {
+                                dxi[c * 64 + s] += dcol[c * k * k * 64 + j];
+                            }
+                        }
+                    }
+                }
+
+                self.accumulate(w, &dw);
+                self.accumulate(b, &db);
+                self.accumulate(x, &dx);
+            }
+            Op::BatchNorm {
+                x,
+                gamma,
+                beta,
+                channels,
+                ref mean,
+                ref var,
+                train,
+            } => {
+                let n = dy.len() / (channels * 64);
+                let count = (n * 64) as f32;
+
+                let mut dgamma = vec![0.0; channels];
+                let mut dbeta = vec![0.0; channels];
+                let mut dx = vec![0.0; dy.len()];
+
+                for c in 0..channels {
+                    let inv = 1.0 / (var[c] + BN_EPSILON).sqrt();
+                    let g = self.value(gamma)[c];
+                    let (mut sum_dy, mut sum_dy_xhat) = (0.0, 0.0);
+
+                    for i in 0..n {
+                        let at = (i * channels + c) * 64;
+
+                        for (d, xv) in dy[at..at + 64].iter().zip(&self.value(x)[at..at + 64]) {
+                            let xhat = (xv - mean[c]) * inv;
+
+                            sum_dy += d;
+                            sum_dy_xhat += d * xhat;
+                        }
+                    }
+
+                    dgamma[c] = sum_dy_xhat;
+                    dbeta[c] = sum_dy;
+
+                    for i in 0..n {
+                        let at = (i * channels + c) * 64;
+
+                        for s in at..at + 64 {
+                            dx[s] = if train {
+                                let xhat = (self.value(x)[s] - mean[c]) * inv;
+                                g * inv * (dy[s] - sum_dy / count - xhat * sum_dy_xhat / count)
+                            } else {
+                                g * inv * dy[s]
+                            };
+                        }
+                    }
+                }
+
+                self.accumulate(gamma, &dgamma);
+                self.accumulate(beta, &dbeta);
+                self.accumulate(x, &dx);
+            }
+            Op::Dense { x, w, b, cin, cout } => {
+                let n = dy.len() / cout;
+
+                let mut dw = vec![0.0; cout * cin];
+                let mut db = vec![0.0; cout];
+                let mut dx = vec![0.0; n * cin];
+
+                matmul_at(dy, self.value(x), n, cout, cin, &mut dw);
+                matmul(dy, self.value(w), n, cout, cin, &mut dx);
+
+                for i in 0..n {
+                    for (d, g) in db.iter_mut().zip(&dy[i * cout..(i + 1) * cout]) {
+                        *d += g;
+                    }
+                }
+
+                self.accumulate(w, &dw);
+                self.accumulate(b, &db);
+                self.accumulate(x, &dx);
+            }
+            Op::Relu(x) => {
+                let dx: Vec<f32> = dy
+                    .iter()
+                    .zip(self.value(y))
+                    .map(|(g, v)| if *v > 0.0 { *g } else { 0.0 })
+                    .collect();
+
+                self.accumulate(x, &dx);
+            }
+            Op::Tanh(x) => {
+                let dx: Vec<f32> = dy
+                    .iter()
+                    .zip(self.value(y))
+                    .map(|(g, v)| g * (1.0 - v * v))
+                    .collect();
+
+                self.accumulate(x, &dx);
+            }
+            Op::Add(a, b) => {
+                self.accumulate(a, dy);
+                self.accumulate(b, dy);
+            }
+            Op::SoftmaxXent {
+                logits,
+                ref target,
+                classes,
+            } => {
+                let l = self.value(logits);
+                let n = l.len() / classes;
+                let mut dx = vec![0.0; l.len()];
+
+                for i in 0..n {
+                    let row = &l[i * classes..(i + 1) * classes];
+                    let t = &target[i * classes..(i + 1) * classes];
+                    let max = row.iter().cloned().fold(f32::NEG_INFINITY, f32::max);
+                    let total: f32 = row.iter().map(|v| (v - max).exp()).sum();
+                    let mass: f32 = t.iter().sum();
+
+                    for j in 0..classes {
+                        let p = (row[j] - max).exp() / total;
+                        dx[i * classes + j] = dy[0] * (p * mass - t[j]) / n as f32;
+                    }
+                }
+
+                self.accumulate(logits, &dx);
+            }
+            Op::Mse { x, ref target } => {
+                let xs = self.value(x);
+                let scale = 2.0 * dy[0] / xs.len() as f32;
+                let dx: Vec<f32> = xs
+                    .iter()
+                    .zip(target.iter())
+                    .map(|(a, b)| scale * (a - b))
+                    .collect();
+
+                self.accumulate(x, &dx);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    fn random(rng: &mut StdRng, n: usize) -> Vec<f32> {
+        (0..n).map(|_| rng.gen_range(-1.0, 1.0)).collect()
+    }
+
+    /*
+     * Compares analytic gradients of `f` with central differences for every
+     * element of every input. `f` builds a scalar loss from the given leaves.
+     */
+    fn check<F>(inputs: Vec<Vec<f32>>, f: F)
+    where
+        F: Fn(&mut Graph, &[Var]) -> Var,
+    {
+        let eval = |inputs: &[Vec<f32>]| {
+            let mut g = Graph::new();
+            let vars: Vec<Var> = inputs.iter().map(|v| g.leaf(v.clone())).collect();
+            let loss = f(&mut g, &vars);
+
+            (g, vars, loss)
+        };
+
+        let (mut g, vars, loss) = eval(&inputs);
+        g.backward(loss);
+
+        let eps = 1e-2;
+
+        for (i, input) in inputs.iter().enumerate() {
+            for j in 0..input.len() {
+                let mut plus = inputs.clone();
+                let mut minus = inputs.clone();
+
+                plus[i][j] += eps;
+                minus[i][j] -= eps;
+
+                let (gp, _, lp) = eval(&plus);
+                let (gm, _, lm) = eval(&minus);
+
+                let numeric = (gp.value(lp)[0] - gm.value(lm)[0]) / (2.0 * eps);
+                let analytic = g.grad(vars[i]).get(j).cloned().unwrap_or(0.0);
+
+                assert!(
+                    (numeric - analytic).abs() < 2e-2 * (1.0 + numeric.abs()),
+                    "input {} element {}: numeric {} analytic {}",
+                    i,
+                    j,
+                    numeric,
+                    analytic
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn autograd_conv_gradients_match() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let target = random(&mut rng, 2 * 3 * 64);
+
+        for k in [1, 3].iter() {
+            let k = *k;
+
+            check(
+                vec![
+                    random(&mut rng, 2 * 2 * 64),
+                    random(&mut rng, 3 * 2 * k * k),
+                    random(&mut rng, 3),
+                ],
+                |g, v| {
+                    let y = g.conv(v[0], v[1], v[2], 2, 3, k);
+                    g.mse(y, target.clone())
+                },
+            );
+        }
+    }
+
+    #[test]
+    fn autograd_batch_norm_gradients_match() {
+        let mut rng = StdRng::seed_from_u64(2);
+        let target = random(&mut rng, 2 * 2 * 64);
+
+        for train in [true, false].iter() {
+            let stats = (vec![0.1, -0.2], vec![0.5, 1.5]);
+
+            check(
+                vec![
+                    random(&mut rng, 2 * 2 * 64),
+                    random(&mut rng, 2),
+                    random(&mut rng, 2),
+                ],
+                |g, v| {
+                    let s = if *train {
+                        None
+                    } else {
+                        Some((&stats.0[..], &stats.1[..]))
+                    };
+
+                    let y = g.batch_norm(v[0], v[1], v[2], 2, s);
+                    let y = g.tanh(y);
+                    g.mse(y, target.clone())
+                },
+            );
+        }
+    }
+
+    #[test]
+    fn autograd_dense_and_activations_gradients_match() {
+        let mut rng = StdRng::seed_from_u64(3);
+        let target = random(&mut rng, 3 * 4);
+
+        check(
+            vec![
+                random(&mut rng, 3 * 5),
+                random(&mut rng, 4 * 5),
+                random(&mut rng, 4),
+            ],
+            |g, v| {
+                let y = g.dense(v[0], v[1], v[2], 5, 4);
+                let r = g.relu(y);
+                let t = g.tanh(y);
+                let s = g.add(r, t);
+                g.mse(s, target.clone())
+            },
+        );
+    }
+
+    #[test]
+    fn autograd_softmax_xent_gradients_match() {
+        let mut rng = StdRng::seed_from_u64(4);
+        let mut target = vec![0.0; 2 * 6];
+
+        target[1] = 0.25;
+        target[4] = 0.75;
+        target[6] = 1.0;
+
+        check(vec![random(&mut rng, 2 * 6)], |g, v| {
+            let xent = g.softmax_xent(v[0], target.clone(), 6);
+            let mse = g.mse(v[0], vec![0.0; 12]);
+            g.add(xent, mse)
+        });
+    }
+
+    #[test]
+    fn autograd_batch_stats_work() {
+        let mut g = Graph::new();
+        let x = g.leaf((0..128).map(|i| (i / 64) as f32).collect());
+        let gamma = g.leaf(vec![1.0]);
+        let beta = g.leaf(vec![0.0]);
+        let y = g.batch_norm(x, gamma, beta, 1, None);
+
+        let (mean, var) = g.batch_stats(y);
+
+        assert_eq!(mean, &[0.5]);
+        assert_eq!(var, &[0.25]);
+    }
+}
@@ -0,0 +1,114 @@
+use crate::position::*;
+use crate::search::*;
+use crate::tt::*;
+
+use std::sync::atomic::{AtomicBool, AtomicU64};
+use std::sync::Arc;
+use std::time::Instant;
+
+/* Default search depth for each bench position */
+pub const DEPTH: usize = 6;
+
+/* Fixed positions searched by the bench, covering openings, middlegames and endgames */
+pub const POSITIONS: [&str; 8] = [
+    STARTPOS,
+    "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+    "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+    "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+    "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+    "r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10",
+    "r1bqkb1r/pppp1ppp/2n2n2/4p2Q/2B1P3/8/PPPP1PPP/RNB1K1NR w KQkq - 4 4",
+    "8/8/1p1k4/p1p5/P1P2K2/1P6/8/8 w - - 0 1",
+];
+
+/* Nodes searched per position */
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Report {
+    pub nodes: Vec<u64>,
+    pub time: u64, /* Elapsed milliseconds */
+}
+
+impl Report {
+    pub fn total(&self) -> u64 {
+        self.nodes.iter().sum()
+    }
+
+    pub fn nps(&self) -> u64 {
+        self.total() * 1000 / self.time.max(1)
+    }
+}
+
+/* Searches every bench position to `depth` on one thread, each with a fresh table */
+pub fn run(depth: usize) -> Report {
+    let start = Instant::now();
+    let nodes = POSITIONS
+        .iter()
+        .map(|fen| {
+            let mut p = Position::new(fen.to_string()).unwrap();
+            let mut s = Search::new(
+                0,
+                Arc::new(AtomicBool::new(false)),
+                Arc::new(TranspositionTable::new(DEFAULT_MB)),
+                Arc::new(AtomicU64::new(0)),
+            );
+
+            s.go(
+                &mut p,
+                Limits {
+                    depth: Some(depth),
+                    ..Limits::default()
+                },
+                |_| {},
+            );
+
+            s.nodes()
+        })
+        .collect();
+
+    Report {
+        nodes,
+        time: start.elapsed().as_millis() as u64,
+    }
+}
+
+pub fn cli(args: &[String]) {
+    let depth = match args.first().map(|a| a.parse::<usize>()) {
+        None => DEPTH,
+        Some(Ok(d)) if (1..MAX_PLY).contains(&d) => d,
+        _ => {
+            error!("usage: nc bench [depth]");
+            return;
+        }
+    };
+
+    let report = run(depth);
+
+    for (fen, n) in POSITIONS.iter().zip(report.nodes.iter()) {
+        println!("{:>10} {}", n, fen);
+    }
+
+    println!(
+        "depth {} nodes {} time {} nps {}",
+        depth,
+        report.total(),
+        report.time,
+        report.nps()
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bench_is_deterministic() {
+        let a = run(3);
+
+        assert_eq!(a.nodes.len(), POSITIONS.len());
+        assert!(a.nodes.iter().all(|n| *n > 0));
+
+        /* The bench signature: changes whenever the search tree does */
+        assert_eq!(a.total(), 25479);
+        assert_eq!(a.nodes, run(3).nodes);
+    }
+}
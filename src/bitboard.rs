@@ -1,3 +1,4 @@
+use crate::piece::*;
 use crate::square::*;
 
 pub const RANKS: [u64; 8] = [
@@ -23,15 +24,22 @@ pub const FILES: [u64; 8] = [
 ];
 
 pub fn to_pretty(b: u64) -> String {
+    to_grid(|s| {
+        if b & s.mask() != 0u64 {
+            "#".to_string()
+        } else {
+            ".".to_string()
+        }
+    })
+}
+
+/* Renders a cell per square, rank 8 first, in the same layout as `to_pretty` */
+pub fn to_grid<F: Fn(Square) -> String>(cell: F) -> String {
     let mut output: String = String::new();
 
     for r in (0..8).rev() {
         for f in 0..8 {
-            if b & (1u64 << (r * 8 + f)) != 0u64 {
-                output.push('#');
-            } else {
-                output.push('.');
-            }
+            output.push_str(&cell(Square::at(r, f)));
         }
 
         if r > 0 {
@@ -61,6 +69,44 @@ pub fn shift(b: u64, d: i32) -> u64 {
     }
 }
 
+/* Files on either side of file `f` */
+pub fn adjacent_files(f: usize) -> u64 {
+    let mut out = 0u64;
+
+    if f > 0 {
+        out |= FILES[f - 1];
+    }
+
+    if f < 7 {
+        out |= FILES[f + 1];
+    }
+
+    out
+}
+
+/* Ranks strictly in front of `rank` from `c`'s point of view */
+pub fn forward_ranks(c: Color, rank: usize) -> u64 {
+    match c {
+        Color::WHITE => (!0u64).checked_shl(8 * (rank as u32 + 1)).unwrap_or(0),
+        Color::BLACK => (!0u64).checked_shr(8 * (8 - rank as u32)).unwrap_or(0),
+    }
+}
+
+/* Squares in front of `s` on its own and adjacent files, where enemy pawns stop a passer */
+pub fn passed_span(c: Color, s: Square) -> u64 {
+    forward_ranks(c, s.rank()) & (FILES[s.file()] | adjacent_files(s.file()))
+}
+
+/* Squares attacked by `c`'s pawns on `b`, without wrapping around the edges */
+pub fn pawn_attacks(c: Color, b: u64) -> u64 {
+    let (west, east) = match c {
+        Color::WHITE => (7, 9),
+        Color::BLACK => (-9, -7),
+    };
+
+    shift(b & !FILES[0], west) | shift(b & !FILES[7], east)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -72,4 +118,34 @@ mod tests {
             ".#######\n#..####.\n...#.###\n#.#..#.#\n#..#..##\n...###.#\n#..#....\n..###.#."
         );
     }
+
+    #[test]
+    fn bitboard_pawn_masks_work() {
+        assert_eq!(adjacent_files(0), FILES[1]);
+        assert_eq!(adjacent_files(4), FILES[3] | FILES[5]);
+
+        assert_eq!(forward_ranks(Color::WHITE, 5), RANKS[6] | RANKS[7]);
+        assert_eq!(forward_ranks(Color::BLACK, 2), RANKS[0] | RANKS[1]);
+        assert_eq!(forward_ranks(Color::WHITE, 7), 0);
+        assert_eq!(forward_ranks(Color::BLACK, 0), 0);
+
+        let e4 = Square::at(3, 4);
+        assert_eq!(
+            passed_span(Color::WHITE, e4),
+            (RANKS[4] | RANKS[5] | RANKS[6] | RANKS[7]) & (FILES[3] | FILES[4] | FILES[5])
+        );
+        assert_eq!(
+            passed_span(Color::BLACK, e4),
+            (RANKS[0] | RANKS[1] | RANKS[2]) & (FILES[3] | FILES[4] | FILES[5])
+        );
+
+        let a2 = Square::at(1, 0).mask();
+        let h7 = Square::at(6, 7).mask();
+        assert_eq!(pawn_attacks(Color::WHITE, a2), Square::at(2, 1).mask());
+        assert_eq!(pawn_attacks(Color::BLACK, h7), Square::at(5, 6).mask());
+        assert_eq!(
+            pawn_attacks(Color::WHITE, e4.mask()),
+            Square::at(4, 3).mask() | Square::at(4, 5).mask()
+        );
+    }
 }
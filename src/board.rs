@@ -1,17 +1,28 @@
 use crate::attacks;
 use crate::bitboard;
+use crate::eval::{self, Score};
+use crate::nnue::{self, Network};
+use crate::params::{self, Params};
 use crate::piece::*;
 use crate::square::*;
 use crate::zobrist;
 
+use std::sync::Arc;
+
 #[derive(Clone, PartialEq, Eq, Debug)]
 pub struct Board {
     state: [Option<Piece>; 64],
     zkey: u64,
+    pawn_key: u64, /* Zobrist key of the pawns alone */
     ad: [[u64; 64]; 2],
     occ: u64,
     color: [u64; 2],
     piece: [u64; 6],
+    material: [Score; 2], /* Tapered material per colour */
+    psqt: [Score; 2],     /* Tapered material and piece-square sums per colour */
+    generation: usize,    /* Parameters the material and piece-square sums were built with */
+    phase: i32,           /* Game phase from non-pawn material */
+    nnue: Option<nnue::Accumulator>,
 }
 
 impl Board {
@@ -19,14 +30,25 @@ impl Board {
         Board {
             state: [None; 64],
             zkey: 0u64,
+            pawn_key: 0u64,
             ad: [[0; 64]; 2],
             occ: 0u64,
             color: [0u64; 2],
             piece: [0u64; 6],
+            material: [Score::default(); 2],
+            psqt: [Score::default(); 2],
+            generation: params::with(|params| params.generation),
+            phase: 0,
+            nnue: None,
         }
     }
 
     pub fn place(&mut self, s: Square, p: Piece) {
+        params::with(|params| self.place_with(s, p, params))
+    }
+
+    /* Places a piece, updating the evaluation sums with parameters the caller already holds */
+    pub fn place_with(&mut self, s: Square, p: Piece, params: &Params) {
         assert!(!self.state[s.index()].is_some());
 
         /* Find pieces that require an A/D recalculation */
@@ -46,11 +68,23 @@ impl Board {
         self.state[s.index()].replace(p);
         self.zkey ^= zobrist::PIECE[s.index()][p.index()];
 
+        if p.get_type() == Type::PAWN {
+            self.pawn_key ^= zobrist::PIECE[s.index()][p.index()];
+        }
+
         let mask: u64 = s.mask();
         self.occ ^= mask;
         self.color[p.get_color() as usize] ^= mask;
         self.piece[p.get_type() as usize] ^= mask;
 
+        /* Update evaluation accumulators */
+        self.update_scores(params, |material, psqt| {
+            material[p.get_color() as usize] += params.material(p.get_type());
+            psqt[p.get_color() as usize] += params.piece_square(p, s.index());
+        });
+        self.phase += eval::PHASE[p.get_type() as usize];
+        self.update_nnue(|nnue, b| nnue.place(b, s.index(), p));
+
         /* Add attacks from piece being placed */
         self.add_attacks(s);
 
@@ -58,9 +92,16 @@ impl Board {
         bitboard::for_each(umask, |ts| {
             self.add_attacks(ts);
         });
+
+        debug_assert!(self.accumulators_valid(params));
     }
 
     pub fn remove(&mut self, s: Square) -> Piece {
+        params::with(|params| self.remove_with(s, params))
+    }
+
+    /* Removes a piece, updating the evaluation sums with parameters the caller already holds */
+    pub fn remove_with(&mut self, s: Square, params: &Params) -> Piece {
         assert!(self.state[s.index()].is_some());
 
         /* Find pieces that require an A/D recalculation */
@@ -83,17 +124,31 @@ impl Board {
         let p: Piece = self.state[s.index()].take().unwrap();
         self.zkey ^= zobrist::PIECE[s.index()][p.index()];
 
+        if p.get_type() == Type::PAWN {
+            self.pawn_key ^= zobrist::PIECE[s.index()][p.index()];
+        }
+
         /* Drop occupancies */
         let mask: u64 = s.mask();
         self.occ ^= mask;
         self.color[p.get_color() as usize] ^= mask;
         self.piece[p.get_type() as usize] ^= mask;
 
+        /* Update evaluation accumulators */
+        self.update_scores(params, |material, psqt| {
+            material[p.get_color() as usize] -= params.material(p.get_type());
+            psqt[p.get_color() as usize] -= params.piece_square(p, s.index());
+        });
+        self.phase -= eval::PHASE[p.get_type() as usize];
+        self.update_nnue(|nnue, _| nnue.remove(s.index(), p));
+
         /* Re-add attacks from pieces that needed update */
         bitboard::for_each(umask, |ts| {
             self.add_attacks(ts);
         });
 
+        debug_assert!(self.accumulators_valid(params));
+
         p
     }
 
@@ -101,6 +156,106 @@ impl Board {
         self.zkey
     }
 
+    pub fn pawn_key(&self) -> u64 {
+        self.pawn_key
+    }
+
+    /* Material of a colour's pieces under `params` */
+    pub fn material(&self, c: Color, params: &Params) -> Score {
+        if params.generation == self.generation {
+            self.material[c as usize]
+        } else {
+            self.sum_scores(params).0[c as usize]
+        }
+    }
+
+    /* Material and piece-square score of a colour's pieces under `params`, from its own perspective */
+    pub fn psqt(&self, c: Color, params: &Params) -> Score {
+        if params.generation == self.generation {
+            self.psqt[c as usize]
+        } else {
+            self.sum_scores(params).1[c as usize]
+        }
+    }
+
+    fn sum_scores(&self, params: &Params) -> ([Score; 2], [Score; 2]) {
+        let mut material = [Score::default(); 2];
+        let mut psqt = [Score::default(); 2];
+
+        for (sq, p) in self.state.iter().enumerate() {
+            if let Some(p) = p {
+                material[p.get_color() as usize] += params.material(p.get_type());
+                psqt[p.get_color() as usize] += params.piece_square(*p, sq);
+            }
+        }
+
+        (material, psqt)
+    }
+
+    /* Applies an incremental update, or rebuilds the sums if the parameters changed since */
+    fn update_scores<F: FnOnce(&mut [Score; 2], &mut [Score; 2])>(
+        &mut self,
+        params: &Params,
+        f: F,
+    ) {
+        if params.generation == self.generation {
+            f(&mut self.material, &mut self.psqt);
+        } else {
+            let (material, psqt) = self.sum_scores(params);
+
+            self.material = material;
+            self.psqt = psqt;
+            self.generation = params.generation;
+        }
+    }
+
+    /* Applies an update to the network accumulators, if a network is attached */
+    fn update_nnue<F: FnOnce(&mut nnue::Accumulator, &Board)>(&mut self, f: F) {
+        if let Some(mut nnue) = self.nnue.take() {
+            f(&mut nnue, self);
+            self.nnue = Some(nnue);
+        }
+    }
+
+    /* Attaches a network to evaluate this board with, or detaches it */
+    pub fn set_network(&mut self, network: Option<Arc<Network>>) {
+        self.nnue = network.map(|n| nnue::Accumulator::new(n, self));
+    }
+
+    /* Network score in centipawns for `ctm`, if a network is attached */
+    pub fn nnue(&self, ctm: Color) -> Option<i32> {
+        self.nnue.as_ref()?.evaluate(ctm)
+    }
+
+    pub fn phase(&self) -> i32 {
+        self.phase
+    }
+
+    /* Checks the incremental accumulators and pawn key against a full recompute */
+    fn accumulators_valid(&self, params: &Params) -> bool {
+        let mut phase = 0;
+        let mut pawn_key = 0u64;
+
+        for (sq, p) in self.state.iter().enumerate() {
+            if let Some(p) = p {
+                if p.get_type() == Type::PAWN {
+                    pawn_key ^= zobrist::PIECE[sq][p.index()];
+                }
+
+                phase += eval::PHASE[p.get_type() as usize];
+            }
+        }
+
+        (params.generation != self.generation
+            || self.sum_scores(params) == (self.material, self.psqt))
+            && phase == self.phase
+            && pawn_key == self.pawn_key
+            && self
+                .nnue
+                .as_ref()
+                .is_none_or(|a| *a == nnue::Accumulator::new(a.network().clone(), self))
+    }
+
     pub fn add_attacks(&mut self, s: Square) {
         let p = self.state[s.index()].unwrap();
         let c = p.get_color();
@@ -151,8 +306,12 @@ impl Board {
         self.occ
     }
 
-    pub fn get_ad(&self) -> [[u64; 64]; 2] {
-        self.ad
+    pub fn attack_count(&self, c: Color, s: Square) -> u64 {
+        self.ad[c as usize][s.index()]
+    }
+
+    pub fn piece_at(&self, s: Square) -> Option<Piece> {
+        self.state[s.index()]
     }
 
     pub fn to_fen_string(&self) -> String {
@@ -244,6 +403,39 @@ mod tests {
         b.remove(Square::at(0, 0));
     }
 
+    #[test]
+    fn board_accumulators_work() {
+        let mut b: Board = Board::new();
+        let queen = Piece::from_fen('Q').unwrap();
+        let knight = Piece::from_fen('n').unwrap();
+
+        let params = params::current();
+
+        b.place(Square::at(0, 3), queen);
+        b.place(Square::at(7, 1), knight);
+
+        assert_eq!(
+            b.material(Color::WHITE, &params),
+            params.material(Type::QUEEN)
+        );
+        assert_eq!(
+            b.material(Color::BLACK, &params),
+            params.material(Type::KNIGHT)
+        );
+        assert_eq!(b.psqt(Color::WHITE, &params), params.piece_square(queen, 3));
+        assert_eq!(
+            b.psqt(Color::BLACK, &params),
+            params.piece_square(knight, 57)
+        );
+        assert_eq!(b.phase(), 5);
+        assert!(b.accumulators_valid(&params));
+
+        b.remove(Square::at(0, 3));
+        b.remove(Square::at(7, 1));
+
+        assert_eq!(b, Board::new());
+    }
+
     #[test]
     fn board_to_fen_string_works() {
         let b: Board = Board::new();
@@ -0,0 +1,187 @@
+use crate::bitboard;
+use crate::piece::*;
+use crate::position::*;
+
+/* Number of positions (current + previous) encoded */
+pub const HISTORY: usize = 8;
+
+/* 6 piece planes per side, plus two repetition planes */
+pub const PLANES_PER_STEP: usize = 14;
+
+/* Side to move, 4 castling rights, halfmove clock, constant ones */
+pub const AUX_PLANES: usize = 7;
+
+pub const PLANES: usize = HISTORY * PLANES_PER_STEP + AUX_PLANES;
+pub const INPUT_SIZE: usize = PLANES * 64;
+
+/* The only plane which is not all zeros or ones */
+pub const HALFMOVE_PLANE: usize = HISTORY * PLANES_PER_STEP + 5;
+
+/* Orients a square index from the perspective of `c` */
+pub fn orient(c: Color, sq: usize) -> usize {
+    match c {
+        Color::WHITE => sq,
+        Color::BLACK => sq ^ 56,
+    }
+}
+
+fn fill(out: &mut [f32], plane: usize, value: f32) {
+    for v in out[plane * 64..(plane + 1) * 64].iter_mut() {
+        *v = value;
+    }
+}
+
+/*
+ * Encodes a position and its recent history into planes of 64 floats each,
+ * laid out [plane][square]. Everything is seen from the side to move: their
+ * pieces come first and the board is flipped vertically when black is to move.
+ */
+pub fn encode(p: &Position) -> Vec<f32> {
+    let mut out = vec![0.0f32; INPUT_SIZE];
+    let us = p.ctm();
+    let mut hist = p.clone();
+
+    for step in 0..HISTORY {
+        let base = step * PLANES_PER_STEP;
+        let b = hist.board();
+
+        for (rel, c) in [us, us.flip()].iter().enumerate() {
+            for t in 0..6 {
+                let occ = b.piece_occ(Type::from_index(t).unwrap()) & b.color_occ(*c);
+
+                bitboard::for_each(occ, |s| {
+                    out[(base + rel * 6 + t) * 64 + orient(us, s.index())] = 1.0;
+                });
+            }
+        }
+
+        let reps = hist.repetitions();
+
+        if reps >= 1 {
+            fill(&mut out, base + 12, 1.0);
+        }
+
+        if reps >= 2 {
+            fill(&mut out, base + 13, 1.0);
+        }
+
+        match hist.last_move() {
+            Some(m) => hist.unmake_move(m),
+            None => break,
+        }
+    }
+
+    let aux = HISTORY * PLANES_PER_STEP;
+
+    if us == Color::BLACK {
+        fill(&mut out, aux, 1.0);
+    }
+
+    for (i, (c, side)) in [
+        (us, Castling::KINGSIDE),
+        (us, Castling::QUEENSIDE),
+        (us.flip(), Castling::KINGSIDE),
+        (us.flip(), Castling::QUEENSIDE),
+    ]
+    .iter()
+    .enumerate()
+    {
+        if p.castling(*c, *side) {
+            fill(&mut out, aux + 1 + i, 1.0);
+        }
+    }
+
+    fill(&mut out, HALFMOVE_PLANE, p.halfmove_clock() as f32 / 100.0);
+    fill(&mut out, aux + 6, 1.0);
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gmove::*;
+    use crate::position::tests::mirror_fen;
+
+    fn pos(fen: &str) -> Position {
+        Position::new(fen.to_string()).unwrap()
+    }
+
+    fn plane(planes: &[f32], i: usize) -> &[f32] {
+        &planes[i * 64..(i + 1) * 64]
+    }
+
+    #[test]
+    fn encode_is_deterministic() {
+        let p = pos(STARTPOS);
+
+        assert_eq!(encode(&p), encode(&p));
+        assert_eq!(encode(&p).len(), INPUT_SIZE);
+    }
+
+    #[test]
+    fn encode_orients_to_side_to_move() {
+        let w = encode(&pos("4k3/4p3/8/8/8/8/8/4K3 w - - 0 1"));
+        let b = encode(&pos("4k3/4p3/8/8/8/8/8/4K3 b - - 0 1"));
+
+        /* White to move: the black pawn on e7 is theirs */
+        assert_eq!(plane(&w, 6)[52], 1.0);
+
+        /* Black to move: the same pawn is ours and appears on e2, their king on e8 */
+        assert_eq!(plane(&b, 0)[12], 1.0);
+        assert_eq!(plane(&b, 6 + 5)[60], 1.0);
+    }
+
+    #[test]
+    fn encode_color_flip_mirrors_planes() {
+        for fen in [
+            STARTPOS,
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w Kq - 3 1",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 b - - 0 1",
+        ]
+        .iter()
+        {
+            let a = encode(&pos(fen));
+            let b = encode(&pos(&mirror_fen(fen)));
+            let color = HISTORY * PLANES_PER_STEP;
+
+            for i in 0..PLANES {
+                if i == color {
+                    assert_ne!(plane(&a, i), plane(&b, i));
+                } else {
+                    assert_eq!(plane(&a, i), plane(&b, i), "plane {} differs", i);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn encode_history_and_repetitions_work() {
+        let mut p = pos(STARTPOS);
+        let before = encode(&p);
+
+        for m in ["g1f3", "g8f6", "f3g1", "f6g8"].iter() {
+            p.make_move(Move::from_uci(&m.to_string()).unwrap());
+        }
+
+        let after = encode(&p);
+
+        /* Current pieces match the start, and the position is a repetition */
+        assert_eq!(&after[..12 * 64], &before[..12 * 64]);
+        assert!(plane(&after, 12).iter().all(|v| *v == 1.0));
+        assert!(plane(&after, 13).iter().all(|v| *v == 0.0));
+
+        /* Step 4 back is the start position, oriented the same way */
+        let s4 = 4 * PLANES_PER_STEP;
+        assert_eq!(&after[s4 * 64..(s4 + 12) * 64], &before[..12 * 64]);
+
+        /* Steps beyond the available history are empty */
+        let s5 = 5 * PLANES_PER_STEP;
+        assert!(after[s5 * 64..(HISTORY * PLANES_PER_STEP) * 64]
+            .iter()
+            .all(|v| *v == 0.0));
+
+        /* Halfmove clock */
+        assert_eq!(plane(&after, HALFMOVE_PLANE)[0], 0.04);
+    }
+}
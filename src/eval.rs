@@ -0,0 +1,422 @@
+use crate::board::*;
+use crate::gmove::*;
+use crate::mcts::{self, Evaluator};
+use crate::mobility;
+use crate::movepick::SEE_VALUE;
+use crate::params::{self, Accumulator, Params, TermGroup};
+use crate::pawns::{self, PawnTable};
+use crate::piece::*;
+use crate::position::*;
+use crate::safety;
+use crate::square::*;
+
+use std::ops::{Add, AddAssign, Mul, Neg, Sub, SubAssign};
+use std::sync::Mutex;
+
+/* Default tapered material values, indexed by piece type */
+pub const MG_MATERIAL: [i32; 6] = [82, 365, 337, 477, 1025, 0];
+pub const EG_MATERIAL: [i32; 6] = [94, 297, 281, 512, 936, 0];
+
+/* Game phase contributed by each piece type; the starting position has MAX_PHASE */
+pub const PHASE: [i32; 6] = [0, 1, 1, 2, 4, 0];
+pub const MAX_PHASE: i32 = 24;
+
+/* Bonus for the side to move */
+pub const TEMPO: i32 = 15;
+
+/*
+ * Piece-square tables are written from white's point of view with a8 first,
+ * so a white piece on `sq` reads entry `sq ^ 56` and a black piece entry `sq`.
+ */
+#[rustfmt::skip]
+const PAWN_MG: [i32; 64] = [
+      0,   0,   0,   0,   0,   0,   0,   0,
+     50,  50,  50,  50,  50,  50,  50,  50,
+     10,  10,  20,  30,  30,  20,  10,  10,
+      5,   5,  10,  25,  25,  10,   5,   5,
+      0,   0,   0,  20,  20,   0,   0,   0,
+      5,  -5, -10,   0,   0, -10,  -5,   5,
+      5,  10,  10, -20, -20,  10,  10,   5,
+      0,   0,   0,   0,   0,   0,   0,   0,
+];
+
+#[rustfmt::skip]
+const PAWN_EG: [i32; 64] = [
+      0,   0,   0,   0,   0,   0,   0,   0,
+     80,  80,  80,  80,  80,  80,  80,  80,
+     50,  50,  50,  50,  50,  50,  50,  50,
+     30,  30,  30,  30,  30,  30,  30,  30,
+     20,  20,  20,  20,  20,  20,  20,  20,
+     10,  10,  10,  10,  10,  10,  10,  10,
+     10,  10,  10,  10,  10,  10,  10,  10,
+      0,   0,   0,   0,   0,   0,   0,   0,
+];
+
+#[rustfmt::skip]
+const BISHOP: [i32; 64] = [
+    -20, -10, -10, -10, -10, -10, -10, -20,
+    -10,   0,   0,   0,   0,   0,   0, -10,
+    -10,   0,   5,  10,  10,   5,   0, -10,
+    -10,   5,   5,  10,  10,   5,   5, -10,
+    -10,   0,  10,  10,  10,  10,   0, -10,
+    -10,  10,  10,  10,  10,  10,  10, -10,
+    -10,   5,   0,   0,   0,   0,   5, -10,
+    -20, -10, -10, -10, -10, -10, -10, -20,
+];
+
+#[rustfmt::skip]
+const KNIGHT: [i32; 64] = [
+    -50, -40, -30, -30, -30, -30, -40, -50,
+    -40, -20,   0,   0,   0,   0, -20, -40,
+    -30,   0,  10,  15,  15,  10,   0, -30,
+    -30,   5,  15,  20,  20,  15,   5, -30,
+    -30,   0,  15,  20,  20,  15,   0, -30,
+    -30,   5,  10,  15,  15,  10,   5, -30,
+    -40, -20,   0,   5,   5,   0, -20, -40,
+    -50, -40, -30, -30, -30, -30, -40, -50,
+];
+
+#[rustfmt::skip]
+const ROOK: [i32; 64] = [
+      0,   0,   0,   0,   0,   0,   0,   0,
+      5,  10,  10,  10,  10,  10,  10,   5,
+     -5,   0,   0,   0,   0,   0,   0,  -5,
+     -5,   0,   0,   0,   0,   0,   0,  -5,
+     -5,   0,   0,   0,   0,   0,   0,  -5,
+     -5,   0,   0,   0,   0,   0,   0,  -5,
+     -5,   0,   0,   0,   0,   0,   0,  -5,
+      0,   0,   0,   5,   5,   0,   0,   0,
+];
+
+#[rustfmt::skip]
+const QUEEN: [i32; 64] = [
+    -20, -10, -10,  -5,  -5, -10, -10, -20,
+    -10,   0,   0,   0,   0,   0,   0, -10,
+    -10,   0,   5,   5,   5,   5,   0, -10,
+     -5,   0,   5,   5,   5,   5,   0,  -5,
+      0,   0,   5,   5,   5,   5,   0,  -5,
+    -10,   5,   5,   5,   5,   5,   0, -10,
+    -10,   0,   5,   0,   0,   0,   0, -10,
+    -20, -10, -10,  -5,  -5, -10, -10, -20,
+];
+
+#[rustfmt::skip]
+const KING_MG: [i32; 64] = [
+    -30, -40, -40, -50, -50, -40, -40, -30,
+    -30, -40, -40, -50, -50, -40, -40, -30,
+    -30, -40, -40, -50, -50, -40, -40, -30,
+    -30, -40, -40, -50, -50, -40, -40, -30,
+    -20, -30, -30, -40, -40, -30, -30, -20,
+    -10, -20, -20, -20, -20, -20, -20, -10,
+     20,  20,   0,   0,   0,   0,  20,  20,
+     20,  30,  10,   0,   0,  10,  30,  20,
+];
+
+#[rustfmt::skip]
+const KING_EG: [i32; 64] = [
+    -50, -40, -30, -20, -20, -30, -40, -50,
+    -30, -20, -10,   0,   0, -10, -20, -30,
+    -30, -10,  20,  30,  30,  20, -10, -30,
+    -30, -10,  30,  40,  40,  30, -10, -30,
+    -30, -10,  30,  40,  40,  30, -10, -30,
+    -30, -10,  20,  30,  30,  20, -10, -30,
+    -30, -30,   0,   0,   0,   0, -30, -30,
+    -50, -30, -30, -30, -30, -30, -30, -50,
+];
+
+/* Piece-square tables indexed by piece type */
+pub const MG_PST: [[i32; 64]; 6] = [PAWN_MG, BISHOP, KNIGHT, ROOK, QUEEN, KING_MG];
+pub const EG_PST: [[i32; 64]; 6] = [PAWN_EG, BISHOP, KNIGHT, ROOK, QUEEN, KING_EG];
+
+/* A midgame and endgame score pair */
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Score {
+    pub mg: i32,
+    pub eg: i32,
+}
+
+impl Score {
+    pub fn new(mg: i32, eg: i32) -> Score {
+        Score { mg, eg }
+    }
+
+    /* Interpolates between the endgame (phase 0) and midgame (MAX_PHASE) scores */
+    pub fn taper(&self, phase: i32) -> i32 {
+        let phase = phase.clamp(0, MAX_PHASE);
+
+        (self.mg * phase + self.eg * (MAX_PHASE - phase)) / MAX_PHASE
+    }
+}
+
+impl Add for Score {
+    type Output = Score;
+
+    fn add(self, o: Score) -> Score {
+        Score::new(self.mg + o.mg, self.eg + o.eg)
+    }
+}
+
+impl Sub for Score {
+    type Output = Score;
+
+    fn sub(self, o: Score) -> Score {
+        Score::new(self.mg - o.mg, self.eg - o.eg)
+    }
+}
+
+impl Neg for Score {
+    type Output = Score;
+
+    fn neg(self) -> Score {
+        Score::new(-self.mg, -self.eg)
+    }
+}
+
+impl Mul<i32> for Score {
+    type Output = Score;
+
+    fn mul(self, n: i32) -> Score {
+        Score::new(self.mg * n, self.eg * n)
+    }
+}
+
+impl AddAssign for Score {
+    fn add_assign(&mut self, o: Score) {
+        *self = *self + o;
+    }
+}
+
+impl SubAssign for Score {
+    fn sub_assign(&mut self, o: Score) {
+        *self = *self - o;
+    }
+}
+
+/* Game phase from the remaining non-pawn material */
+pub fn phase(b: &Board) -> i32 {
+    b.phase().min(MAX_PHASE)
+}
+
+/* Material and piece-square score from white's perspective */
+pub fn psqt(b: &Board, params: &Params) -> Score {
+    b.psqt(Color::WHITE, params) - b.psqt(Color::BLACK, params)
+}
+
+/* Static evaluation from the perspective of the side to move, from the network if one is attached */
+pub fn evaluate(p: &Position, pawns: &mut PawnTable) -> i32 {
+    let b = p.board();
+
+    if let Some(score) = b.nnue(p.ctm()) {
+        return score;
+    }
+
+    let score = params::with(|params| {
+        psqt(b, params)
+            + pawns.probe(b, params)
+            + safety::Terms::new(b).score(params)
+            + mobility::Terms::new(b).score(params)
+    })
+    .taper(phase(b));
+
+    TEMPO
+        + match p.ctm() {
+            Color::WHITE => score,
+            Color::BLACK => -score,
+        }
+}
+
+/*
+ * Feeds every term of the evaluation to `acc` from white's perspective, before
+ * tapering and without the tempo bonus. Summed with the parameters in use this
+ * gives the same score `evaluate` reaches through the board's accumulators and
+ * the pawn hash.
+ */
+pub fn accumulate<A: Accumulator>(b: &Board, acc: &mut A) {
+    for (sq, p) in (0..64).filter_map(|sq| Some((sq, b.piece_at(Square::from_index(sq)?)?))) {
+        let sign = match p.get_color() {
+            Color::WHITE => 1,
+            Color::BLACK => -1,
+        };
+
+        acc.add(params::MATERIAL + p.get_type() as usize, sign);
+        acc.add(params::pst_index(p, sq), sign);
+    }
+
+    pawns::Terms::new(b).accumulate(acc);
+    safety::Terms::new(b).accumulate(acc);
+    mobility::Terms::new(b).accumulate(acc);
+}
+
+/*
+ * The static evaluation as an MCTS evaluator. The value is the evaluation
+ * mapped into [-1, 1]; the policy prefers captures and promotions by the
+ * material they win.
+ */
+#[derive(Default)]
+pub struct Classical {
+    pawns: Mutex<PawnTable>,
+}
+
+impl Classical {
+    pub fn new() -> Classical {
+        Classical::default()
+    }
+}
+
+impl Evaluator for Classical {
+    fn evaluate(&self, p: &Position, moves: &[Move]) -> (Vec<f32>, f32) {
+        let policy = moves
+            .iter()
+            .map(|m| {
+                let victim = p.captured_type(*m).map_or(0, |t| SEE_VALUE[t as usize]);
+                let promotion = m.ptype().map_or(0, |t| SEE_VALUE[t as usize]);
+
+                1.0 + (victim + promotion) as f32 / 100.0
+            })
+            .collect();
+
+        (
+            policy,
+            mcts::cp_to_value(evaluate(p, &mut self.pawns.lock().unwrap())),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::params::Sum;
+    use crate::position::tests::mirror_fen;
+
+    #[test]
+    fn eval_startpos_is_balanced() {
+        let p = Position::new(STARTPOS.to_string()).unwrap();
+
+        assert_eq!(phase(p.board()), MAX_PHASE);
+        assert_eq!(psqt(p.board(), &params::current()), Score::default());
+        assert_eq!(evaluate(&p, &mut PawnTable::default()), TEMPO);
+    }
+
+    #[test]
+    fn eval_is_side_relative() {
+        let w = Position::new("4k3/8/8/8/8/8/8/3QK3 w - - 0 1".to_string()).unwrap();
+        let b = Position::new("4k3/8/8/8/8/8/8/3QK3 b - - 0 1".to_string()).unwrap();
+
+        assert!(evaluate(&w, &mut PawnTable::default()) > 800);
+        assert_eq!(
+            evaluate(&w, &mut PawnTable::default()) + evaluate(&b, &mut PawnTable::default()),
+            2 * TEMPO
+        );
+    }
+
+    #[test]
+    fn eval_is_symmetric() {
+        let params = params::current();
+
+        for fen in [
+            STARTPOS,
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+            "r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10",
+            "4k3/8/8/3q4/8/8/8/3RK3 b - - 0 1",
+            "r1bq1rk1/ppp2ppp/2n5/3np1N1/2B5/8/PPPP1PPP/RNBQK2R w KQ - 0 7",
+            "6k1/5ppp/8/6N1/8/3Q4/8/4K3 w - - 0 1",
+            "4k3/pp3p1p/2p3p1/4p3/2P1P3/3P4/PP3PPP/4K3 w - - 0 1",
+            "4k3/p6p/8/4N3/3P4/8/8/4K3 w - - 0 1",
+            "4k3/B7/1p6/8/8/8/6PP/6KR w - - 0 1",
+        ]
+        .iter()
+        {
+            let p = Position::new(fen.to_string()).unwrap();
+            let m = Position::new(mirror_fen(fen)).unwrap();
+            let (b, mb) = (p.board(), m.board());
+
+            /* Every term group on its own, then the whole evaluation */
+            assert_eq!(psqt(b, &params), -psqt(mb, &params), "{}", fen);
+            assert_eq!(
+                pawns::Terms::new(b).score(&params),
+                -pawns::Terms::new(mb).score(&params),
+                "{}",
+                fen
+            );
+            assert_eq!(
+                safety::Terms::new(b).score(&params),
+                -safety::Terms::new(mb).score(&params),
+                "{}",
+                fen
+            );
+            assert_eq!(
+                mobility::Terms::new(b).score(&params),
+                -mobility::Terms::new(mb).score(&params),
+                "{}",
+                fen
+            );
+            assert_eq!(
+                evaluate(&p, &mut PawnTable::default()),
+                evaluate(&m, &mut PawnTable::default()),
+                "{}",
+                fen
+            );
+        }
+    }
+
+    #[test]
+    fn eval_accumulate_matches_evaluate() {
+        for fen in [
+            STARTPOS,
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "r1bq1rk1/ppp2ppp/2n5/3np1N1/2B5/8/PPPP1PPP/RNBQK2R b KQ - 0 7",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+        ]
+        .iter()
+        {
+            let p = Position::new(fen.to_string()).unwrap();
+            let params = params::current();
+            let mut sum = Sum::new(&params);
+
+            accumulate(p.board(), &mut sum);
+
+            let white = sum.score.taper(phase(p.board()));
+            let expected = TEMPO
+                + match p.ctm() {
+                    Color::WHITE => white,
+                    Color::BLACK => -white,
+                };
+
+            assert_eq!(evaluate(&p, &mut PawnTable::default()), expected, "{}", fen);
+        }
+    }
+
+    #[test]
+    fn eval_tapers_by_phase() {
+        let s = Score::new(100, -50);
+
+        assert_eq!(s.taper(MAX_PHASE), 100);
+        assert_eq!(s.taper(0), -50);
+        assert_eq!(s.taper(MAX_PHASE / 2), 25);
+        assert_eq!((-s).taper(7), -s.taper(7));
+
+        /* A centralised king is worth more once the queens are gone */
+        let mg = Position::new("r1bqkb1r/8/8/8/4K3/8/8/R1BQ1B1R w - - 0 1".to_string()).unwrap();
+        let eg = Position::new("4k3/8/8/8/4K3/8/8/8 w - - 0 1".to_string()).unwrap();
+
+        assert!(phase(mg.board()) > phase(eg.board()));
+        assert!(evaluate(&eg, &mut PawnTable::default()) > TEMPO);
+    }
+
+    #[test]
+    fn eval_classical_evaluator_works() {
+        let mut p = Position::new("4k3/8/8/3q4/8/8/8/3RK3 w - - 0 1".to_string()).unwrap();
+        let moves = p.gen_legal_moves();
+        let (policy, value) = Classical::new().evaluate(&p, &moves);
+        let rxq = Move::from_uci(&"d1d5".to_string()).unwrap();
+        let best = moves
+            .iter()
+            .zip(policy.iter())
+            .max_by(|a, b| a.1.partial_cmp(b.1).unwrap())
+            .unwrap();
+
+        assert_eq!(*best.0, rxq);
+        assert!(value < 0.0 && value > -1.0);
+    }
+}
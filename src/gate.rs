@@ -0,0 +1,423 @@
+use crate::gmove::*;
+use crate::mcts::Evaluator;
+use crate::pgn;
+use crate::piece::*;
+use crate::position::*;
+use crate::selfplay::{self, play};
+
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+
+/* Short, balanced openings in UCI notation; each is played with both colours */
+pub const OPENINGS: [&str; 12] = [
+    "",
+    "e2e4 e7e5 g1f3 b8c6",
+    "e2e4 c7c5 g1f3 d7d6",
+    "e2e4 e7e6 d2d4 d7d5",
+    "e2e4 c7c6 d2d4 d7d5",
+    "d2d4 d7d5 c2c4 e7e6",
+    "d2d4 g8f6 c2c4 g7g6",
+    "d2d4 g8f6 c2c4 e7e6",
+    "c2c4 e7e5 b1c3 g8f6",
+    "g1f3 d7d5 g2g3 g8f6",
+    "e2e4 d7d5 e4d5 d8d5",
+    "d2d4 d7d5 c1f4 g8f6",
+];
+
+#[derive(Copy, Clone, Debug)]
+pub struct Config {
+    pub games: usize,
+    pub threshold: f32, /* Score the candidate needs to be promoted */
+    pub play: selfplay::Config,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            games: 100,
+            threshold: 0.55,
+            play: selfplay::Config {
+                playouts: 400,
+                temperature_plies: 4, /* Seeded per game, so repeated openings diverge */
+                noise_epsilon: 0.0,
+                resign_playthrough: 0.0,
+                ..selfplay::Config::default()
+            },
+        }
+    }
+}
+
+/* Match result from the candidate's point of view */
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Score {
+    pub wins: usize,
+    pub draws: usize,
+    pub losses: usize,
+}
+
+impl Score {
+    pub fn games(&self) -> usize {
+        self.wins + self.draws + self.losses
+    }
+
+    pub fn score(&self) -> f32 {
+        (self.wins as f32 + 0.5 * self.draws as f32) / self.games().max(1) as f32
+    }
+
+    pub fn passes(&self, threshold: f32) -> bool {
+        self.games() > 0 && self.score() >= threshold
+    }
+}
+
+/* Starting position of an opening given as UCI moves */
+pub fn opening(moves: &str) -> Option<Position> {
+    let mut p = Position::new(STARTPOS.to_string()).unwrap();
+
+    for m in moves.split_whitespace() {
+        let m = Move::from_uci(&m.to_string())?;
+
+        if !p.gen_legal_moves().contains(&m) {
+            return None;
+        }
+
+        p.make_move(m);
+    }
+
+    Some(p)
+}
+
+/* Converts a finished game to PGN, naming the players by colour */
+fn to_pgn(g: &selfplay::Game, round: usize, names: [&str; 2]) -> pgn::Game {
+    let mut p = g.start.clone();
+    let result = match g.winner {
+        Some(Color::WHITE) => "1-0",
+        Some(Color::BLACK) => "0-1",
+        None => "1/2-1/2",
+    };
+
+    let mut tags = vec![
+        ("Event".to_string(), "Gating".to_string()),
+        ("Round".to_string(), round.to_string()),
+        ("White".to_string(), names[0].to_string()),
+        ("Black".to_string(), names[1].to_string()),
+        ("Result".to_string(), result.to_string()),
+    ];
+
+    let fen = p.to_fen();
+
+    if fen != STARTPOS {
+        tags.push(("SetUp".to_string(), "1".to_string()));
+        tags.push(("FEN".to_string(), fen));
+    }
+
+    if g.resigned {
+        tags.push(("Termination".to_string(), "resignation".to_string()));
+    }
+
+    let moves = g
+        .moves
+        .iter()
+        .map(|m| {
+            let san = pgn::to_san(&mut p, *m);
+            p.make_move(*m);
+            san
+        })
+        .collect();
+
+    pgn::Game {
+        tags,
+        moves,
+        result: result.to_string(),
+    }
+}
+
+/*
+ * Plays `config.games` games between `candidate` and `best` on `threads`
+ * threads. Game 2i and 2i+1 share an opening with colours swapped, the
+ * candidate taking white in even games. Every game is written to `output`
+ * as PGN if given.
+ */
+pub fn run(
+    candidate: Arc<dyn Evaluator>,
+    best: Arc<dyn Evaluator>,
+    config: &Config,
+    openings: &[Position],
+    threads: usize,
+    output: Option<&str>,
+) -> io::Result<Score> {
+    if openings.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "No openings to play",
+        ));
+    }
+
+    let mut score = Score::default();
+    let mut games: Vec<(usize, pgn::Game)> = Vec::new();
+    let next = AtomicUsize::new(0);
+    let (tx, rx) = mpsc::channel::<(usize, selfplay::Game)>();
+
+    thread::scope(|sc| {
+        for _ in 0..threads.max(1) {
+            let tx = tx.clone();
+            let (candidate, best) = (candidate.clone(), best.clone());
+            let next = &next;
+
+            sc.spawn(move || loop {
+                let i = next.fetch_add(1, Ordering::Relaxed);
+
+                if i >= config.games {
+                    break;
+                }
+
+                let players = if i.is_multiple_of(2) {
+                    [candidate.clone(), best.clone()]
+                } else {
+                    [best.clone(), candidate.clone()]
+                };
+
+                let start = &openings[(i / 2) % openings.len()];
+                let mut rng = StdRng::seed_from_u64(config.play.seed.wrapping_add(i as u64));
+
+                if tx
+                    .send((i, play(players, start, &config.play, &mut rng)))
+                    .is_err()
+                {
+                    break;
+                }
+            });
+        }
+
+        drop(tx);
+
+        for (i, g) in rx.iter() {
+            let candidate_color = if i % 2 == 0 {
+                Color::WHITE
+            } else {
+                Color::BLACK
+            };
+
+            match g.winner {
+                Some(c) if c == candidate_color => score.wins += 1,
+                Some(_) => score.losses += 1,
+                None => score.draws += 1,
+            }
+
+            let names = match candidate_color {
+                Color::WHITE => ["candidate", "best"],
+                Color::BLACK => ["best", "candidate"],
+            };
+
+            games.push((i, to_pgn(&g, i + 1, names)));
+
+            info!(
+                "Gating game {}/{}: +{} ={} -{} ({:.1}%)",
+                score.games(),
+                config.games,
+                score.wins,
+                score.draws,
+                score.losses,
+                100.0 * score.score()
+            );
+        }
+    });
+
+    if let Some(path) = output {
+        let mut out = BufWriter::new(File::create(path)?);
+
+        games.sort_by_key(|g| g.0);
+
+        for (_, g) in games.iter() {
+            writeln!(out, "{}", g)?;
+        }
+
+        out.flush()?;
+    }
+
+    Ok(score)
+}
+
+/*
+ * Entry point for `nc gate <candidate> <best> <games> [output.pgn]`, where
+ * each player is "classical", "uniform" or a weights file.
+ */
+pub fn cli(args: &[String]) {
+    if args.len() < 3 || args.len() > 4 {
+        error!("usage: nc gate <candidate> <best> <games> [output.pgn]");
+        return;
+    }
+
+    let games = match args[2].parse::<usize>() {
+        Ok(g) => g,
+        Err(_) => {
+            error!("Invalid game count '{}'", args[2]);
+            return;
+        }
+    };
+
+    let mut nets: Vec<Arc<dyn Evaluator>> = Vec::new();
+
+    for spec in args[..2].iter() {
+        match selfplay::evaluator(spec) {
+            Ok(e) => nets.push(e),
+            Err(e) => {
+                error!("Failed to load evaluator '{}': {}", spec, e);
+                return;
+            }
+        }
+    }
+
+    let config = Config {
+        games,
+        ..Config::default()
+    };
+
+    let openings: Vec<Position> = OPENINGS.iter().map(|o| opening(o).unwrap()).collect();
+    let threads = thread::available_parallelism().map_or(1, |n| n.get());
+    let output = args.get(3).map(|s| s.as_str());
+
+    match run(
+        nets[0].clone(),
+        nets[1].clone(),
+        &config,
+        &openings,
+        threads,
+        output,
+    ) {
+        Ok(s) => info!(
+            "Gating finished: +{} ={} -{}, score {:.1}%, {}",
+            s.wins,
+            s.draws,
+            s.losses,
+            100.0 * s.score(),
+            if s.passes(config.threshold) {
+                "promote"
+            } else {
+                "reject"
+            }
+        ),
+        Err(e) => error!("Gating failed: {}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::BufReader;
+
+    #[test]
+    fn gate_openings_are_legal() {
+        for o in OPENINGS.iter() {
+            assert!(opening(o).is_some(), "{}", o);
+        }
+
+        assert!(opening("e2e5").is_none());
+    }
+
+    #[test]
+    fn gate_score_works() {
+        let s = Score {
+            wins: 5,
+            draws: 2,
+            losses: 3,
+        };
+
+        assert_eq!(s.games(), 10);
+        assert!((s.score() - 0.6).abs() < 1e-6);
+        assert!(s.passes(0.55));
+        assert!(!s.passes(0.65));
+        assert!(!Score::default().passes(0.0));
+    }
+
+    /* White is always lost, so it resigns at once whenever it plays white */
+    struct WhiteLoses;
+
+    impl Evaluator for WhiteLoses {
+        fn evaluate(&self, p: &Position, moves: &[Move]) -> (Vec<f32>, f32) {
+            let v = match p.ctm() {
+                Color::WHITE => -1.0,
+                Color::BLACK => 1.0,
+            };
+
+            (vec![1.0; moves.len()], v)
+        }
+    }
+
+    #[test]
+    fn gate_without_openings_fails() {
+        let config = Config {
+            games: 2,
+            ..Config::default()
+        };
+
+        let uniform = Arc::new(crate::mcts::Uniform);
+        let err = run(uniform.clone(), uniform, &config, &[], 1, None).unwrap_err();
+
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn gate_match_works() {
+        let path = std::env::temp_dir().join("nc_gate_test.pgn");
+        let path = path.to_str().unwrap();
+
+        let config = Config {
+            games: 6,
+            play: selfplay::Config {
+                playouts: 16,
+                max_plies: 16,
+                ..Config::default().play
+            },
+            ..Config::default()
+        };
+
+        let openings = [opening("").unwrap(), opening(OPENINGS[1]).unwrap()];
+        let score = run(
+            Arc::new(crate::mcts::Uniform),
+            Arc::new(WhiteLoses),
+            &config,
+            &openings,
+            2,
+            Some(path),
+        )
+        .unwrap();
+
+        assert_eq!(score.games(), 6);
+        assert!(score.wins >= 3);
+        assert!(score.passes(config.threshold));
+
+        let games: Vec<pgn::Game> =
+            pgn::Games::new(BufReader::new(File::open(path).unwrap())).collect();
+
+        assert_eq!(games.len(), 6);
+
+        for (i, g) in games.iter().enumerate() {
+            let candidate = if i % 2 == 0 { "White" } else { "Black" };
+
+            assert_eq!(g.tag("Round"), Some((i + 1).to_string().as_str()));
+            assert_eq!(g.tag(candidate), Some("candidate"));
+            assert_eq!(g.tag("FEN").is_some(), i / 2 % 2 == 1);
+
+            /* Odd games have the resigning evaluator as white */
+            if i % 2 == 1 {
+                assert_eq!(g.result, "0-1");
+                assert!(g.moves.is_empty());
+            }
+
+            let mut p = Position::new(g.tag("FEN").unwrap_or(STARTPOS).to_string()).unwrap();
+
+            for san in g.moves.iter() {
+                let m = pgn::parse_san(&mut p, san).unwrap();
+                p.make_move(m);
+            }
+        }
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
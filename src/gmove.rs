@@ -68,6 +68,30 @@ impl Move {
         })
     }
 
+    /* Packs the move into 16 bits. Zero is reserved for the null move. */
+    pub fn to_u16(&self) -> u16 {
+        let promo = self.m_ptype.map_or(0, |t| t as u16 + 1);
+
+        self.m_src.index() as u16 | (self.m_dst.index() as u16) << 6 | promo << 12
+    }
+
+    pub fn from_u16(v: u16) -> Option<Move> {
+        if v == 0 {
+            return None;
+        }
+
+        let ptype = match v >> 12 {
+            0 => None,
+            t => Some(Type::from_index(t as usize - 1)?),
+        };
+
+        Some(Move {
+            m_src: Square::from_index((v & 63) as usize)?,
+            m_dst: Square::from_index((v >> 6 & 63) as usize)?,
+            m_ptype: ptype,
+        })
+    }
+
     pub fn src(&self) -> Square {
         self.m_src
     }
@@ -136,6 +160,17 @@ mod tests {
         );
     }
 
+    #[test]
+    fn move_u16_roundtrip_works() {
+        for uci in ["a1f5", "b7b8q", "b7b8n", "h2h1r", "e1g1"].iter() {
+            let m = Move::from_uci(&uci.to_string()).unwrap();
+
+            assert_eq!(Move::from_u16(m.to_u16()), Some(m));
+        }
+
+        assert_eq!(Move::from_u16(0), None);
+    }
+
     #[test]
     fn move_from_uci_works() {
         assert_eq!(Move::from_uci(&"0000".to_string()), None);
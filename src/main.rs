@@ -1,14 +1,43 @@
 mod attacks;
+mod autograd;
+mod bench;
 mod bitboard;
 mod board;
+mod encode;
+mod eval;
+mod gate;
 mod gmove;
+mod mcts;
+mod mobility;
+mod movepick;
+mod nn;
+mod nnue;
+mod params;
+mod pawns;
+mod pgn;
 mod piece;
+mod pipeline;
+mod policy;
 mod position;
+mod record;
+mod safety;
+mod search;
+mod selfplay;
+mod smp;
 mod square;
+mod timeman;
+mod trace;
+mod train;
+mod tt;
+mod tuner;
+mod uci;
 mod zobrist;
 
+extern crate flate2;
+extern crate memmap2;
 extern crate pretty_env_logger;
 extern crate rand;
+extern crate rand_distr;
 
 #[macro_use]
 extern crate arr_macro;
@@ -17,26 +46,22 @@ extern crate lazy_static;
 #[macro_use]
 extern crate log;
 
-use square::*;
-
 fn main() {
     pretty_env_logger::init();
 
     info!("Starting neocortex.");
 
-    println!(
-        "{}",
-        bitboard::to_pretty(attacks::rook(Square::at(0, 0), 0u64))
-    );
-    println!(
-        "{}",
-        bitboard::to_pretty(attacks::queen(Square::at(0, 0), 128237u64))
-    );
-
-    let p = position::Position::new("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1".to_string()).unwrap();
-    let m = p.gen_pseudolegal_moves();
+    let args: Vec<String> = std::env::args().collect();
 
-    for _m in m {
-        println!("Move: {}", _m.to_uci());
+    match args.get(1).map(|a| a.as_str()) {
+        Some("bench") => bench::cli(&args[2..]),
+        Some("eval") => trace::cli(&args[2..]),
+        Some("gate") => gate::cli(&args[2..]),
+        Some("pgn") => pgn::cli(&args[2..]),
+        Some("pipeline") => pipeline::cli(&args[2..]),
+        Some("selfplay") => selfplay::cli(&args[2..]),
+        Some("train") => train::cli(&args[2..]),
+        Some("tune") => tuner::cli(&args[2..]),
+        _ => uci::Uci::new().run(),
     }
 }
@@ -0,0 +1,785 @@
+use crate::gmove::*;
+use crate::position::*;
+use crate::search::*;
+use crate::timeman::*;
+
+use rand::Rng;
+use rand_distr::{Distribution, Gamma};
+
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/* Playouts between info reports */
+const REPORT_INTERVAL: usize = 1000;
+
+/* Playouts per unit of `go depth`, doubled for every ply */
+const DEPTH_PLAYOUTS: u64 = 64;
+
+/* Playout budget standing in for an alpha-beta depth limit */
+pub fn depth_playouts(depth: usize) -> u64 {
+    DEPTH_PLAYOUTS << depth.min(20)
+}
+
+/*
+ * Evaluates a non-terminal position. Returns a prior for each of `moves` (in
+ * the same order) and a value in [-1, 1] from the side to move's perspective.
+ */
+pub trait Evaluator: Send + Sync {
+    fn evaluate(&self, p: &Position, moves: &[Move]) -> (Vec<f32>, f32);
+
+    /* Evaluates several positions at once, for evaluators which benefit from batching */
+    fn evaluate_batch(&self, items: &[(&Position, &[Move])]) -> Vec<(Vec<f32>, f32)> {
+        items.iter().map(|(p, m)| self.evaluate(p, m)).collect()
+    }
+}
+
+/* Uniform policy and a neutral value. Useful as a baseline and in tests. */
+pub struct Uniform;
+
+impl Evaluator for Uniform {
+    fn evaluate(&self, _: &Position, moves: &[Move]) -> (Vec<f32>, f32) {
+        (vec![1.0 / moves.len() as f32; moves.len()], 0.0)
+    }
+}
+
+/* Converts an expected value in [-1, 1] to an approximate centipawn score */
+pub fn value_to_cp(v: f32) -> i32 {
+    (800.0 * v.clamp(-0.999, 0.999).atanh()) as i32
+}
+
+/* Converts a centipawn score to an expected value in [-1, 1]; the inverse of `value_to_cp` */
+pub fn cp_to_value(cp: i32) -> f32 {
+    (cp as f32 / 800.0).tanh()
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Config {
+    pub cpuct: f32,
+    pub fpu: f32,     /* Value assumed for unvisited children */
+    pub batch: usize, /* Leaves gathered per evaluator call */
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            cpuct: 1.5,
+            fpu: 0.0,
+            batch: 8,
+        }
+    }
+}
+
+struct Node {
+    mv: Option<Move>,
+    parent: Option<usize>,
+    children: Vec<usize>,
+    prior: f32,
+    visits: u32,
+    in_flight: u32,        /* Playouts through this node awaiting evaluation */
+    value: f32,            /* Sum of values for the side which played `mv` */
+    terminal: Option<f32>, /* Exact value for the side to move, if the game is over */
+    expanded: bool,
+}
+
+impl Node {
+    fn new(mv: Option<Move>, parent: Option<usize>, prior: f32) -> Node {
+        Node {
+            mv,
+            parent,
+            children: Vec::new(),
+            prior,
+            visits: 0,
+            in_flight: 0,
+            value: 0.0,
+            terminal: None,
+            expanded: false,
+        }
+    }
+}
+
+/* A leaf selected for evaluation */
+struct Leaf {
+    idx: usize,
+    pos: Position,
+    moves: Vec<Move>,
+}
+
+fn evaluate(eval: &dyn Evaluator, leaves: &[Leaf]) -> Vec<(Vec<f32>, f32)> {
+    if leaves.is_empty() {
+        return Vec::new();
+    }
+
+    let items: Vec<(&Position, &[Move])> = leaves.iter().map(|l| (&l.pos, &l.moves[..])).collect();
+
+    eval.evaluate_batch(&items)
+}
+
+pub struct Mcts {
+    eval: Arc<dyn Evaluator>,
+    config: Config,
+    nodes: Vec<Node>,
+    root: Position,
+    pending: usize, /* Leaves awaiting evaluation */
+}
+
+impl Mcts {
+    pub fn new(eval: Arc<dyn Evaluator>, config: Config) -> Mcts {
+        Mcts {
+            eval,
+            config,
+            nodes: vec![Node::new(None, None, 1.0)],
+            root: Position::new(STARTPOS.to_string()).unwrap(),
+            pending: 0,
+        }
+    }
+
+    /* Discards the tree and starts over from `p` */
+    pub fn set_root(&mut self, p: &Position) {
+        self.root = p.clone();
+        self.nodes = vec![Node::new(None, None, 1.0)];
+    }
+
+    /*
+     * Moves the root to `p`, keeping the subtree under the moves played since
+     * the current root if `p` continues its game. Returns true if the tree was
+     * kept, otherwise starts over as `set_root` does.
+     */
+    pub fn advance(&mut self, p: &Position) -> bool {
+        let moves = match p.moves_since(&self.root) {
+            Some(m) => m,
+            None => {
+                self.set_root(p);
+                return false;
+            }
+        };
+
+        let mut idx = 0;
+
+        for m in moves {
+            match self.nodes[idx]
+                .children
+                .iter()
+                .find(|&&c| self.nodes[c].mv == Some(m))
+            {
+                Some(&c) => idx = c,
+                None => {
+                    self.set_root(p);
+                    return false;
+                }
+            }
+        }
+
+        /* A repetition may have marked the new root as a drawn leaf */
+        if idx != 0 && self.nodes[idx].terminal.is_some() {
+            self.set_root(p);
+            return false;
+        }
+
+        if idx != 0 {
+            self.reroot(idx);
+        }
+
+        self.root = p.clone();
+        true
+    }
+
+    /* Rebuilds the node arena with `idx` as the root, preserving child order */
+    fn reroot(&mut self, idx: usize) {
+        let mut old = std::mem::take(&mut self.nodes);
+        let mut queue = VecDeque::new();
+
+        queue.push_back((idx, None));
+
+        while let Some((i, parent)) = queue.pop_front() {
+            let mut n = std::mem::replace(&mut old[i], Node::new(None, None, 0.0));
+            let at = self.nodes.len();
+
+            for c in n.children.drain(..) {
+                queue.push_back((c, Some(at)));
+            }
+
+            n.parent = parent;
+
+            if let Some(p) = parent {
+                self.nodes[p].children.push(at);
+            }
+
+            self.nodes.push(n);
+        }
+
+        self.nodes[0].mv = None;
+    }
+
+    pub fn playouts(&self) -> u32 {
+        self.nodes[0].visits
+    }
+
+    /* Runs a single playout from the root */
+    pub fn playout(&mut self) {
+        let leaves = self.gather(1);
+        let results = evaluate(&*self.eval, &leaves);
+
+        self.finish(leaves, results);
+    }
+
+    /* Runs `playouts` more playouts, evaluating leaves in batches */
+    pub fn search(&mut self, playouts: usize) {
+        let target = self.playouts() as usize + playouts;
+
+        while (self.playouts() as usize) < target && self.nodes[0].terminal.is_none() {
+            let n = (target - self.playouts() as usize).min(self.config.batch.max(1));
+            let leaves = self.gather(n);
+            let results = evaluate(&*self.eval, &leaves);
+
+            self.finish(leaves, results);
+        }
+    }
+
+    /*
+     * Descends from the root up to `n` times, applying a virtual loss along
+     * each path so later descents spread out. Terminal leaves are backed up
+     * at once; the others are returned for evaluation. Stops early when a
+     * descent reaches a leaf which is already awaiting evaluation.
+     */
+    fn gather(&mut self, n: usize) -> Vec<Leaf> {
+        let mut out = Vec::new();
+
+        for _ in 0..n {
+            let mut pos = self.root.clone();
+            let mut idx = 0;
+
+            while self.nodes[idx].expanded && self.nodes[idx].terminal.is_none() {
+                idx = self.select(idx);
+                pos.make_move(self.nodes[idx].mv.unwrap());
+            }
+
+            self.walk(idx, |n| n.in_flight += 1);
+
+            if let Some(t) = self.nodes[idx].terminal {
+                self.backup(idx, t);
+                continue;
+            }
+
+            if self.nodes[idx].in_flight > 1 {
+                self.walk(idx, |n| n.in_flight -= 1);
+                break;
+            }
+
+            let moves = pos.gen_legal_moves();
+
+            let terminal = if moves.is_empty() {
+                Some(if pos.in_check() { -1.0 } else { 0.0 })
+            } else if idx != 0 && pos.is_draw() {
+                Some(0.0)
+            } else {
+                None
+            };
+
+            if let Some(t) = terminal {
+                self.nodes[idx].terminal = Some(t);
+                self.backup(idx, t);
+                continue;
+            }
+
+            self.pending += 1;
+            out.push(Leaf { idx, pos, moves });
+        }
+
+        out
+    }
+
+    /* Expands gathered leaves with their evaluations and backs them up */
+    fn finish(&mut self, leaves: Vec<Leaf>, results: Vec<(Vec<f32>, f32)>) {
+        for (leaf, (policy, value)) in leaves.into_iter().zip(results) {
+            self.expand(leaf.idx, &leaf.moves, &policy);
+            self.backup(leaf.idx, value);
+            self.pending -= 1;
+        }
+    }
+
+    /* Applies `f` to `idx` and each of its ancestors */
+    fn walk<F: FnMut(&mut Node)>(&mut self, mut idx: usize, mut f: F) {
+        loop {
+            f(&mut self.nodes[idx]);
+
+            match self.nodes[idx].parent {
+                Some(p) => idx = p,
+                None => break,
+            }
+        }
+    }
+
+    fn select(&self, idx: usize) -> usize {
+        let parent = &self.nodes[idx];
+        let sqrt_n = ((parent.visits + parent.in_flight).max(1) as f32).sqrt();
+        let mut best = parent.children[0];
+        let mut best_score = f32::NEG_INFINITY;
+
+        for &c in parent.children.iter() {
+            let child = &self.nodes[c];
+            let n = child.visits + child.in_flight;
+
+            /* Each pending playout counts as a loss for the side which moved */
+            let q = if n > 0 {
+                (child.value - child.in_flight as f32) / n as f32
+            } else {
+                self.config.fpu
+            };
+
+            let u = self.config.cpuct * child.prior * sqrt_n / (1.0 + n as f32);
+
+            if q + u > best_score {
+                best_score = q + u;
+                best = c;
+            }
+        }
+
+        best
+    }
+
+    /* Adds a child for each move with its normalised prior */
+    fn expand(&mut self, idx: usize, moves: &[Move], policy: &[f32]) {
+        let total: f32 = policy.iter().sum();
+
+        for (m, prior) in moves.iter().zip(policy.iter()) {
+            let prior = if total > 0.0 {
+                prior / total
+            } else {
+                1.0 / moves.len() as f32
+            };
+
+            self.nodes.push(Node::new(Some(*m), Some(idx), prior));
+
+            let child = self.nodes.len() - 1;
+            self.nodes[idx].children.push(child);
+        }
+
+        self.nodes[idx].expanded = true;
+    }
+
+    /*
+     * Propagates a value (for the side to move at `idx`) back to the root,
+     * removing the virtual loss applied when the leaf was gathered.
+     */
+    fn backup(&mut self, idx: usize, mut v: f32) {
+        self.walk(idx, |n| {
+            n.in_flight -= 1;
+            n.visits += 1;
+            n.value -= v;
+            v = -v;
+        });
+    }
+
+    /*
+     * Mixes Dirichlet(alpha) noise into the root priors with weight `epsilon`,
+     * expanding the root first if needed.
+     */
+    pub fn add_noise<R: Rng>(&mut self, rng: &mut R, alpha: f32, epsilon: f32) {
+        if !self.nodes[0].expanded {
+            self.playout();
+        }
+
+        let children = self.nodes[0].children.clone();
+
+        if children.is_empty() {
+            return;
+        }
+
+        let gamma = Gamma::new(alpha, 1.0).unwrap();
+        let noise: Vec<f32> = children.iter().map(|_| gamma.sample(rng)).collect();
+        let total: f32 = noise.iter().sum();
+
+        if total <= 0.0 {
+            return;
+        }
+
+        for (c, n) in children.iter().zip(noise.iter()) {
+            let prior = &mut self.nodes[*c].prior;
+            *prior = (1.0 - epsilon) * *prior + epsilon * n / total;
+        }
+    }
+
+    /* Visit counts of the root moves, in generation order */
+    pub fn root_visits(&self) -> Vec<(Move, u32)> {
+        self.nodes[0]
+            .children
+            .iter()
+            .map(|&c| (self.nodes[c].mv.unwrap(), self.nodes[c].visits))
+            .collect()
+    }
+
+    /* Expected value at the root for the side to move */
+    pub fn root_value(&self) -> f32 {
+        let root = &self.nodes[0];
+
+        -root.value / root.visits.max(1) as f32
+    }
+
+    pub fn best_move(&self) -> Option<Move> {
+        self.best_child(0).map(|c| self.nodes[c].mv.unwrap())
+    }
+
+    fn best_child(&self, idx: usize) -> Option<usize> {
+        let mut best: Option<usize> = None;
+
+        for &c in self.nodes[idx].children.iter() {
+            if best.is_none_or(|b| self.nodes[c].visits > self.nodes[b].visits) {
+                best = Some(c);
+            }
+        }
+
+        best
+    }
+
+    /* Most visited line from the root */
+    pub fn pv(&self) -> Vec<Move> {
+        let mut out = Vec::new();
+        let mut idx = 0;
+
+        while let Some(c) = self.best_child(idx) {
+            if self.nodes[c].visits == 0 {
+                break;
+            }
+
+            out.push(self.nodes[c].mv.unwrap());
+            idx = c;
+        }
+
+        out
+    }
+
+    fn info(&self, tm: &TimeManager) -> Info {
+        Info {
+            depth: self.pv().len(),
+            score: value_to_cp(self.root_value()),
+            nodes: self.playouts() as u64,
+            time: tm.elapsed(),
+            hashfull: 0,
+            pv: self.pv(),
+        }
+    }
+
+    /*
+     * Searches `p` on `threads` workers until the playout (nodes) or time
+     * limit, or until stopped. A depth limit without a node limit becomes
+     * a playout budget. The tree from the previous search is reused if `p`
+     * continues its game; the node limit counts new playouts only.
+     */
+    pub fn go<F>(
+        &mut self,
+        p: &Position,
+        limits: Limits,
+        threads: usize,
+        stop: Arc<AtomicBool>,
+        mut report: F,
+    ) -> Option<Move>
+    where
+        F: FnMut(&Info),
+    {
+        let tm = TimeManager::new(&limits, p.ctm());
+        let budget = limits.nodes.or_else(|| limits.depth.map(depth_playouts));
+
+        self.advance(p);
+
+        let start = self.playouts() as u64;
+        let eval = self.eval.clone();
+        let batch = self.config.batch.max(1) as u64;
+        let done = AtomicBool::new(false);
+        let tree = Mutex::new(&mut *self);
+
+        thread::scope(|sc| {
+            for _ in 0..threads.max(1) {
+                sc.spawn(|| {
+                    while !done.load(Ordering::Relaxed) {
+                        let leaves = {
+                            let mut t = tree.lock().unwrap();
+                            let started = t.playouts() as u64 - start + t.pending as u64;
+                            let n = budget.map_or(batch, |l| l.saturating_sub(started));
+
+                            t.gather(n.min(batch) as usize)
+                        };
+
+                        if leaves.is_empty() {
+                            thread::sleep(Duration::from_micros(100));
+                            continue;
+                        }
+
+                        let results = evaluate(&*eval, &leaves);
+                        tree.lock().unwrap().finish(leaves, results);
+                    }
+                });
+            }
+
+            let mut reported = 0;
+
+            loop {
+                thread::sleep(Duration::from_millis(1));
+
+                let t = tree.lock().unwrap();
+                let n = t.playouts() as u64 - start;
+
+                if budget.is_some_and(|l| n >= l)
+                    || t.nodes[0].terminal.is_some()
+                    || stop.load(Ordering::Relaxed)
+                    || tm.hard_exceeded()
+                    || tm.soft_exceeded()
+                {
+                    done.store(true, Ordering::Relaxed);
+                    break;
+                }
+
+                if n / REPORT_INTERVAL as u64 > reported {
+                    reported = n / REPORT_INTERVAL as u64;
+                    report(&t.info(&tm));
+                }
+            }
+        });
+
+        report(&self.info(&tm));
+
+        self.best_move()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rand::SeedableRng;
+
+    fn mcts(fen: &str, playouts: usize) -> Mcts {
+        let mut m = Mcts::new(Arc::new(Uniform), Config::default());
+
+        m.set_root(&Position::new(fen.to_string()).unwrap());
+
+        for _ in 0..playouts {
+            m.playout();
+        }
+
+        m
+    }
+
+    #[test]
+    fn mcts_visits_sum_to_playouts() {
+        let m = mcts(STARTPOS, 200);
+        let children = &m.nodes[0].children;
+        let total: u32 = children.iter().map(|&c| m.nodes[c].visits).sum();
+
+        assert_eq!(m.playouts(), 200);
+        assert_eq!(children.len(), 20);
+
+        /* The first playout only expands the root */
+        assert_eq!(total, 199);
+    }
+
+    #[test]
+    fn mcts_finds_mate_in_one() {
+        let m = mcts("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1", 400);
+
+        assert_eq!(m.best_move().unwrap().to_uci(), "a1a8");
+        assert!(m.root_value() > 0.5);
+    }
+
+    #[test]
+    fn mcts_detects_terminal_root() {
+        let m = mcts("7k/5Q2/6K1/8/8/8/8/8 b - - 0 1", 10);
+
+        assert_eq!(m.best_move(), None);
+        assert_eq!(m.root_value(), 0.0);
+
+        let m = mcts("R5k1/5ppp/8/8/8/8/8/6K1 b - - 0 1", 10);
+
+        assert_eq!(m.best_move(), None);
+        assert_eq!(m.root_value(), -1.0);
+    }
+
+    struct Prefer(Move);
+
+    impl Evaluator for Prefer {
+        fn evaluate(&self, _: &Position, moves: &[Move]) -> (Vec<f32>, f32) {
+            let policy = moves
+                .iter()
+                .map(|m| if *m == self.0 { 10.0 } else { 0.1 })
+                .collect();
+
+            (policy, 0.0)
+        }
+    }
+
+    #[test]
+    fn mcts_follows_evaluator_policy() {
+        let e4 = Move::from_uci(&"e2e4".to_string()).unwrap();
+        let mut m = Mcts::new(Arc::new(Prefer(e4)), Config::default());
+
+        m.set_root(&Position::new(STARTPOS.to_string()).unwrap());
+
+        for _ in 0..100 {
+            m.playout();
+        }
+
+        assert_eq!(m.best_move(), Some(e4));
+    }
+
+    #[test]
+    fn mcts_go_respects_playout_limit() {
+        let mut m = Mcts::new(Arc::new(Uniform), Config::default());
+        let p = Position::new(STARTPOS.to_string()).unwrap();
+
+        let best = m.go(
+            &p,
+            Limits {
+                nodes: Some(300),
+                ..Limits::default()
+            },
+            1,
+            Arc::new(AtomicBool::new(false)),
+            |_| {},
+        );
+
+        assert!(best.is_some());
+        assert_eq!(m.playouts(), 300);
+    }
+
+    #[test]
+    fn mcts_tree_reuse_works() {
+        let mut m = mcts(STARTPOS, 300);
+        let mut p = Position::new(STARTPOS.to_string()).unwrap();
+        let e4 = Move::from_uci(&"e2e4".to_string()).unwrap();
+
+        let visits = m.root_visits().iter().find(|v| v.0 == e4).unwrap().1;
+        assert!(visits > 1);
+
+        p.make_move(e4);
+        assert!(m.advance(&p));
+
+        assert_eq!(m.playouts(), visits);
+        assert_eq!(m.root_visits().len(), 20);
+        assert_eq!(m.nodes[0].parent, None);
+        assert_eq!(m.nodes[0].mv, None);
+
+        for (i, n) in m.nodes.iter().enumerate() {
+            for &c in n.children.iter() {
+                assert_eq!(m.nodes[c].parent, Some(i));
+            }
+        }
+
+        /* The same position keeps everything; unrelated ones start over */
+        m.search(50);
+        assert!(m.advance(&p));
+        assert_eq!(m.playouts(), visits + 50);
+
+        assert!(
+            !m.advance(&Position::new("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1".to_string()).unwrap())
+        );
+        assert_eq!(m.playouts(), 0);
+    }
+
+    /* Records the largest batch it was asked to evaluate */
+    struct Batches(std::sync::atomic::AtomicUsize);
+
+    impl Evaluator for Batches {
+        fn evaluate(&self, p: &Position, moves: &[Move]) -> (Vec<f32>, f32) {
+            Uniform.evaluate(p, moves)
+        }
+
+        fn evaluate_batch(&self, items: &[(&Position, &[Move])]) -> Vec<(Vec<f32>, f32)> {
+            self.0.fetch_max(items.len(), Ordering::Relaxed);
+            items.iter().map(|(p, m)| self.evaluate(p, m)).collect()
+        }
+    }
+
+    #[test]
+    fn mcts_batches_leaves_with_virtual_loss() {
+        let eval = Arc::new(Batches(Default::default()));
+        let mut m = Mcts::new(eval.clone(), Config::default());
+
+        m.set_root(&Position::new(STARTPOS.to_string()).unwrap());
+        m.playout();
+
+        /* Virtual loss sends each descent to a different leaf */
+        let leaves = m.gather(8);
+        let mut idx: Vec<usize> = leaves.iter().map(|l| l.idx).collect();
+
+        idx.sort_unstable();
+        idx.dedup();
+        assert_eq!(idx.len(), 8);
+        assert_eq!(m.nodes[0].in_flight, 8);
+
+        let results = evaluate(&*m.eval, &leaves);
+        m.finish(leaves, results);
+
+        assert_eq!(m.playouts(), 9);
+        assert_eq!(m.pending, 0);
+        assert!(m.nodes.iter().all(|n| n.in_flight == 0));
+
+        m.search(100);
+
+        assert_eq!(m.playouts(), 109);
+        assert_eq!(eval.0.load(Ordering::Relaxed), 8);
+    }
+
+    #[test]
+    fn mcts_parallel_go_works() {
+        let mut m = Mcts::new(Arc::new(Uniform), Config::default());
+        let p = Position::new("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1".to_string()).unwrap();
+        let limits = Limits {
+            nodes: Some(800),
+            ..Limits::default()
+        };
+
+        let best = m.go(
+            &p,
+            limits.clone(),
+            4,
+            Arc::new(AtomicBool::new(false)),
+            |_| {},
+        );
+
+        assert_eq!(best.unwrap().to_uci(), "a1a8");
+        assert_eq!(m.playouts(), 800);
+        assert_eq!(m.pending, 0);
+        assert!(m.nodes.iter().all(|n| n.in_flight == 0));
+
+        /* The second search continues the same tree */
+        m.go(&p, limits, 4, Arc::new(AtomicBool::new(false)), |_| {});
+        assert_eq!(m.playouts(), 1600);
+    }
+
+    #[test]
+    fn mcts_root_noise_works() {
+        let mut m = Mcts::new(Arc::new(Uniform), Config::default());
+        let mut rng = rand::rngs::StdRng::seed_from_u64(1);
+
+        m.set_root(&Position::new(STARTPOS.to_string()).unwrap());
+        m.add_noise(&mut rng, 0.3, 0.25);
+
+        let priors: Vec<f32> = m.nodes[0]
+            .children
+            .iter()
+            .map(|&c| m.nodes[c].prior)
+            .collect();
+
+        assert!((priors.iter().sum::<f32>() - 1.0).abs() < 1e-4);
+        assert!(priors.iter().any(|p| (p - 0.05).abs() > 1e-3));
+
+        for _ in 0..99 {
+            m.playout();
+        }
+
+        let visits = m.root_visits();
+
+        assert_eq!(visits.len(), 20);
+        assert_eq!(visits.iter().map(|v| v.1).sum::<u32>(), 99);
+    }
+
+    #[test]
+    fn mcts_value_to_cp_works() {
+        assert_eq!(value_to_cp(0.0), 0);
+        assert!(value_to_cp(0.5) > 0);
+        assert_eq!(value_to_cp(-0.5), -value_to_cp(0.5));
+        assert!((value_to_cp(cp_to_value(300)) - 300).abs() <= 1);
+    }
+}
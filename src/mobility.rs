@@ -0,0 +1,279 @@
+use crate::attacks;
+use crate::bitboard;
+use crate::board::*;
+use crate::eval::Score;
+use crate::params::{self, Accumulator, TermGroup};
+use crate::piece::*;
+use crate::square::*;
+
+/* Default bonus per safe square a piece attacks, indexed by piece type */
+pub const MOBILITY: [Score; 6] = [
+    Score { mg: 0, eg: 0 },
+    Score { mg: 5, eg: 5 },
+    Score { mg: 4, eg: 4 },
+    Score { mg: 2, eg: 4 },
+    Score { mg: 1, eg: 2 },
+    Score { mg: 0, eg: 0 },
+];
+
+/* Bonuses for a minor piece on a pawn-defended square enemy pawns can never attack */
+pub const KNIGHT_OUTPOST: Score = Score { mg: 20, eg: 10 };
+pub const BISHOP_OUTPOST: Score = Score { mg: 10, eg: 5 };
+
+pub const ROOK_OPEN: Score = Score { mg: 20, eg: 10 };
+pub const ROOK_SEMI_OPEN: Score = Score { mg: 10, eg: 5 };
+pub const ROOK_SEVENTH: Score = Score { mg: 10, eg: 20 };
+pub const BISHOP_PAIR: Score = Score { mg: 30, eg: 50 };
+pub const TRAPPED_BISHOP: Score = Score { mg: -100, eg: -100 };
+pub const TRAPPED_ROOK: Score = Score { mg: -40, eg: -10 };
+
+/* A rook with at most this many safe squares can be trapped by its own king */
+pub const TRAPPED_ROOK_MOBILITY: u32 = 3;
+
+/* Pieces matching each term, per colour */
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Terms {
+    pub mobility: [[u32; 6]; 2], /* Safe squares attacked, summed per piece type */
+    pub knight_outposts: [u64; 2],
+    pub bishop_outposts: [u64; 2],
+    pub rook_open: [u64; 2],
+    pub rook_semi_open: [u64; 2],
+    pub rook_seventh: [u64; 2],
+    pub bishop_pair: [bool; 2],
+    pub trapped_bishops: [u64; 2],
+    pub trapped_rooks: [u64; 2],
+}
+
+/* Flips a mask given from white's point of view to `c`'s */
+fn relative(c: Color, b: u64) -> u64 {
+    match c {
+        Color::WHITE => b,
+        Color::BLACK => b.swap_bytes(),
+    }
+}
+
+/* Squares of the trap patterns from white's point of view */
+const A7: u64 = 1 << 48;
+const H7: u64 = 1 << 55;
+const B6: u64 = 1 << 41;
+const G6: u64 = 1 << 46;
+const KINGSIDE_ROOK: u64 = 1 << 6 | 1 << 7 | 1 << 15; /* g1, h1, h2 */
+const KINGSIDE_KING: u64 = 1 << 5 | 1 << 6; /* f1, g1 */
+const QUEENSIDE_ROOK: u64 = 1 | 1 << 1 | 1 << 8; /* a1, b1, a2 */
+const QUEENSIDE_KING: u64 = 1 << 1 | 1 << 2 | 1 << 3; /* b1, c1, d1 */
+
+/*
+ * Whether the piece on `s` is shut in by a well-known pattern, from white's
+ * point of view: a bishop on a7/h7 cut off by a pawn on b6/g6, or a rook in
+ * the corner behind an uncastled king.
+ */
+fn trapped(b: &Board, c: Color, t: Type, s: Square, mobility: u32) -> bool {
+    let theirs = b.piece_occ(Type::PAWN) & b.color_occ(c.flip());
+    let king = b.piece_occ(Type::KING) & b.color_occ(c);
+    let on = |mask: u64| relative(c, mask) & s.mask() != 0;
+    let any = |mask: u64, occ: u64| relative(c, mask) & occ != 0;
+
+    match t {
+        Type::BISHOP => (on(A7) && any(B6, theirs)) || (on(H7) && any(G6, theirs)),
+        Type::ROOK if mobility <= TRAPPED_ROOK_MOBILITY => {
+            (on(KINGSIDE_ROOK) && any(KINGSIDE_KING, king))
+                || (on(QUEENSIDE_ROOK) && any(QUEENSIDE_KING, king))
+        }
+        _ => false,
+    }
+}
+
+impl Terms {
+    pub fn new(b: &Board) -> Terms {
+        let mut t = Terms::default();
+        let occ = b.global_occ();
+        let pawns = b.piece_occ(Type::PAWN);
+
+        for c in [Color::WHITE, Color::BLACK].iter() {
+            let c = *c;
+            let i = c as usize;
+            let ours = pawns & b.color_occ(c);
+            let theirs = pawns & b.color_occ(c.flip());
+            let defended = bitboard::pawn_attacks(c, ours);
+            let area = !b.color_occ(c) & !bitboard::pawn_attacks(c.flip(), theirs);
+            let seventh = relative(c, bitboard::RANKS[6]);
+            let eighth = relative(c, bitboard::RANKS[7]);
+            let outpost_ranks = relative(
+                c,
+                bitboard::RANKS[3] | bitboard::RANKS[4] | bitboard::RANKS[5],
+            );
+            let their_king = b.piece_occ(Type::KING) & b.color_occ(c.flip());
+
+            bitboard::for_each(b.color_occ(c) & !pawns, |s| {
+                let ty = b.piece_at(s).unwrap().get_type();
+                let att = match ty {
+                    Type::BISHOP => attacks::bishop(s, occ),
+                    Type::KNIGHT => attacks::knight(s),
+                    Type::ROOK => attacks::rook(s, occ),
+                    Type::QUEEN => attacks::queen(s, occ),
+                    _ => 0,
+                };
+                let mobility = (att & area).count_ones();
+                let mask = s.mask();
+
+                t.mobility[i][ty as usize] += mobility;
+
+                let outpost = mask & outpost_ranks & defended != 0
+                    && bitboard::passed_span(c, s) & !bitboard::FILES[s.file()] & theirs == 0;
+
+                match ty {
+                    Type::BISHOP => {
+                        if outpost {
+                            t.bishop_outposts[i] |= mask;
+                        }
+
+                        if trapped(b, c, ty, s, mobility) {
+                            t.trapped_bishops[i] |= mask;
+                        }
+                    }
+                    Type::KNIGHT if outpost => t.knight_outposts[i] |= mask,
+                    Type::ROOK => {
+                        if trapped(b, c, ty, s, mobility) {
+                            t.trapped_rooks[i] |= mask;
+                        }
+
+                        let file = bitboard::FILES[s.file()];
+
+                        if file & pawns == 0 {
+                            t.rook_open[i] |= mask;
+                        } else if file & ours == 0 {
+                            t.rook_semi_open[i] |= mask;
+                        }
+
+                        if mask & seventh != 0
+                            && (theirs & seventh != 0 || their_king & eighth != 0)
+                        {
+                            t.rook_seventh[i] |= mask;
+                        }
+                    }
+                    _ => {}
+                }
+            });
+
+            t.bishop_pair[i] = (b.piece_occ(Type::BISHOP) & b.color_occ(c)).count_ones() >= 2;
+        }
+
+        t
+    }
+}
+
+/* Mobility, outposts, rook files, the bishop pair and trapped pieces */
+impl TermGroup for Terms {
+    fn accumulate<A: Accumulator>(&self, acc: &mut A) {
+        for (c, sign) in [(Color::WHITE, 1), (Color::BLACK, -1)].iter() {
+            let i = *c as usize;
+            let mut count = |index: usize, n: u32| acc.add(index, sign * n as i32);
+
+            for (t, m) in self.mobility[i].iter().enumerate() {
+                count(params::MOBILITY + t, *m);
+            }
+
+            count(params::KNIGHT_OUTPOST, self.knight_outposts[i].count_ones());
+            count(params::BISHOP_OUTPOST, self.bishop_outposts[i].count_ones());
+            count(params::ROOK_OPEN, self.rook_open[i].count_ones());
+            count(params::ROOK_SEMI_OPEN, self.rook_semi_open[i].count_ones());
+            count(params::ROOK_SEVENTH, self.rook_seventh[i].count_ones());
+            count(params::BISHOP_PAIR, self.bishop_pair[i] as u32);
+            count(params::TRAPPED_BISHOP, self.trapped_bishops[i].count_ones());
+            count(params::TRAPPED_ROOK, self.trapped_rooks[i].count_ones());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::position::*;
+
+    fn terms(fen: &str) -> Terms {
+        Terms::new(Position::new(fen.to_string()).unwrap().board())
+    }
+
+    fn square(name: &str) -> u64 {
+        Square::from_uci(&name.to_string()).unwrap().mask()
+    }
+
+    #[test]
+    fn mobility_trap_masks_work() {
+        let squares = |names: &[&str]| names.iter().fold(0, |m, n| m | square(n));
+
+        assert_eq!(
+            [A7, H7, B6, G6],
+            [square("a7"), square("h7"), square("b6"), square("g6")]
+        );
+        assert_eq!(KINGSIDE_ROOK, squares(&["g1", "h1", "h2"]));
+        assert_eq!(KINGSIDE_KING, squares(&["f1", "g1"]));
+        assert_eq!(QUEENSIDE_ROOK, squares(&["a1", "b1", "a2"]));
+        assert_eq!(QUEENSIDE_KING, squares(&["b1", "c1", "d1"]));
+
+        /* Black's patterns are the mirror images */
+        let t = terms("r2k4/pp6/8/8/8/8/8/4K3 b - - 0 1");
+        assert_eq!(t.trapped_rooks[1], square("a8"));
+
+        let t = terms("4k3/8/8/8/8/1P6/b7/4K3 b - - 0 1");
+        assert_eq!(t.trapped_bishops[1], square("a2"));
+    }
+
+    #[test]
+    fn mobility_counts_safe_squares() {
+        let t = terms("4k3/8/8/8/3N4/8/8/4K3 w - - 0 1");
+        assert_eq!(t.mobility[0][Type::KNIGHT as usize], 8);
+
+        /* f5 is covered by the e6 pawn, which can still be captured */
+        let t = terms("4k3/8/4p3/8/3N4/8/8/4K3 w - - 0 1");
+        assert_eq!(t.mobility[0][Type::KNIGHT as usize], 7);
+
+        let t = terms(STARTPOS);
+        assert_eq!(t.mobility[0], t.mobility[1]);
+        assert_eq!(t.mobility[0][Type::KNIGHT as usize], 4);
+        assert_eq!(t.mobility[0][Type::ROOK as usize], 0);
+    }
+
+    #[test]
+    fn mobility_outposts_work() {
+        let t = terms("4k3/p6p/8/4N3/3P4/8/8/4K3 w - - 0 1");
+        assert_eq!(t.knight_outposts[0], square("e5"));
+
+        /* The f-pawn could still drive the knight away */
+        let t = terms("4k3/p4p1p/8/4N3/3P4/8/8/4K3 w - - 0 1");
+        assert_eq!(t.knight_outposts[0], 0);
+
+        let t = terms("4k3/p6p/8/5p2/4b3/8/8/4K3 b - - 0 1");
+        assert_eq!(t.bishop_outposts[1], square("e4"));
+    }
+
+    #[test]
+    fn mobility_rook_files_work() {
+        let t = terms("4k3/pp4pp/8/8/8/8/P5PP/RR1R1K2 w - - 0 1");
+
+        assert_eq!(t.rook_open[0], square("d1"));
+        assert_eq!(t.rook_semi_open[0], square("b1"));
+
+        let t = terms("6k1/R4ppp/8/8/8/8/8/6K1 w - - 0 1");
+        assert_eq!(t.rook_seventh[0], square("a7"));
+
+        let t = terms("8/R7/6k1/8/8/8/8/6K1 w - - 0 1");
+        assert_eq!(t.rook_seventh[0], 0);
+    }
+
+    #[test]
+    fn mobility_bishop_pair_and_trapped_work() {
+        let t = terms(STARTPOS);
+        assert_eq!(t.bishop_pair, [true, true]);
+
+        let t = terms("4k3/B7/1p6/8/8/8/8/4K3 w - - 0 1");
+        assert_eq!(t.trapped_bishops[0], square("a7"));
+        assert!(!t.bishop_pair[0]);
+
+        let t = terms("4k3/8/8/8/8/8/6PP/6KR w - - 0 1");
+        assert_eq!(t.trapped_rooks[0], square("h1"));
+
+        let t = terms("4k3/8/8/8/8/8/6PP/R5K1 w - - 0 1");
+        assert_eq!(t.trapped_rooks[0], 0);
+    }
+}
@@ -0,0 +1,432 @@
+use crate::attacks;
+use crate::gmove::*;
+use crate::piece::*;
+use crate::position::*;
+use crate::search::{self, MAX_PLY};
+use crate::square::*;
+
+/* Piece values for static exchange evaluation; the king is never given up */
+pub const SEE_VALUE: [i32; 6] = [100, 300, 300, 500, 900, 20000];
+
+/* Attackers are tried from least to most valuable */
+const SEE_ORDER: [Type; 6] = [
+    Type::PAWN,
+    Type::KNIGHT,
+    Type::BISHOP,
+    Type::ROOK,
+    Type::QUEEN,
+    Type::KING,
+];
+
+/* History scores are kept within [-MAX_HISTORY, MAX_HISTORY] */
+pub const MAX_HISTORY: i32 = 16384;
+
+/* Largest history adjustment from a single cutoff */
+pub const MAX_HISTORY_BONUS: i32 = 1200;
+
+/* Pieces of either colour attacking `s` through the occupancy `occ` */
+fn attackers(p: &Position, s: Square, occ: u64) -> u64 {
+    let b = p.board();
+    let diagonal = b.piece_occ(Type::BISHOP) | b.piece_occ(Type::QUEEN);
+    let straight = b.piece_occ(Type::ROOK) | b.piece_occ(Type::QUEEN);
+    let pawns = (attacks::pawn(Color::BLACK, s) & b.color_occ(Color::WHITE))
+        | (attacks::pawn(Color::WHITE, s) & b.color_occ(Color::BLACK));
+
+    ((pawns & b.piece_occ(Type::PAWN))
+        | (attacks::knight(s) & b.piece_occ(Type::KNIGHT))
+        | (attacks::king(s) & b.piece_occ(Type::KING))
+        | (attacks::bishop(s, occ) & diagonal)
+        | (attacks::rook(s, occ) & straight))
+        & occ
+}
+
+/*
+ * Static exchange evaluation: the material `m` wins once every capture on its
+ * destination has been played out, least valuable attacker first, with either
+ * side free to stop capturing.
+ */
+pub fn see(p: &Position, m: Move) -> i32 {
+    let b = p.board();
+    let s = m.dst();
+    let mut occ = b.global_occ() ^ m.src().mask();
+    let mut gain = [0; 32];
+    let mut piece = b.piece_at(m.src()).unwrap().get_type();
+
+    gain[0] = p.captured_type(m).map_or(0, |t| SEE_VALUE[t as usize]);
+
+    /* En passant removes a pawn from beside the destination */
+    if p.is_capture(m) && b.piece_at(s).is_none() {
+        occ ^= Square::at(m.src().rank(), s.file()).mask();
+    }
+
+    if let Some(t) = m.ptype() {
+        gain[0] += SEE_VALUE[t as usize] - SEE_VALUE[Type::PAWN as usize];
+        piece = t;
+    }
+
+    let mut side = p.ctm().flip();
+    let mut d = 0;
+
+    while d + 1 < gain.len() {
+        let ours = attackers(p, s, occ) & b.color_occ(side);
+        let next = SEE_ORDER
+            .iter()
+            .map(|t| (*t, ours & b.piece_occ(*t)))
+            .find(|(_, bb)| *bb != 0);
+
+        let (t, bb) = match next {
+            Some(n) => n,
+            None => break,
+        };
+
+        d += 1;
+        gain[d] = SEE_VALUE[piece as usize] - gain[d - 1];
+
+        /* Neither side can do better by continuing */
+        if (-gain[d - 1]).max(gain[d]) < 0 {
+            break;
+        }
+
+        occ ^= bb & bb.wrapping_neg();
+        piece = t;
+        side = side.flip();
+    }
+
+    while d > 0 {
+        gain[d - 1] = -(-gain[d - 1]).max(gain[d]);
+        d -= 1;
+    }
+
+    gain[0]
+}
+
+/* Whether `m` is ordered with the captures rather than the quiet moves */
+pub fn is_noisy(p: &Position, m: Move) -> bool {
+    p.is_capture(m) || m.ptype().is_some()
+}
+
+/* Quiet move ordering state learned during a search */
+pub struct Heuristics {
+    killers: Vec<[Option<Move>; 2]>, /* Quiet moves that caused cutoffs, per ply */
+    counters: [[Option<Move>; 64]; 12], /* Replies indexed by the previous move's piece and destination */
+    history: Box<[[[i32; 64]; 64]; 2]>, /* Butterfly table indexed by colour, source, destination */
+}
+
+impl Default for Heuristics {
+    fn default() -> Heuristics {
+        Heuristics {
+            killers: vec![[None; 2]; MAX_PLY + 1],
+            counters: [[None; 64]; 12],
+            history: Box::new([[[0; 64]; 64]; 2]),
+        }
+    }
+}
+
+impl Heuristics {
+    pub fn new() -> Heuristics {
+        Heuristics::default()
+    }
+
+    pub fn killers(&self, ply: usize) -> [Option<Move>; 2] {
+        self.killers[ply]
+    }
+
+    /* The recorded reply to the move that led to `p` */
+    pub fn counter(&self, p: &Position) -> Option<Move> {
+        let last = p.last_move()?;
+        let piece = p.board().piece_at(last.dst())?;
+
+        self.counters[piece.index()][last.dst().index()]
+    }
+
+    pub fn history(&self, c: Color, m: Move) -> i32 {
+        self.history[c as usize][m.src().index()][m.dst().index()]
+    }
+
+    /* Moves a history score towards the bound, more slowly the closer it is */
+    fn gravity(&mut self, c: Color, m: Move, bonus: i32) {
+        let h = &mut self.history[c as usize][m.src().index()][m.dst().index()];
+
+        *h += bonus - *h * bonus.abs() / MAX_HISTORY;
+    }
+
+    /*
+     * Records a quiet beta cutoff by `best` at a node of `depth` remaining
+     * plies. Quiet moves searched before it are penalised by the same amount.
+     */
+    pub fn update(&mut self, p: &Position, ply: usize, depth: usize, best: Move, tried: &[Move]) {
+        let c = p.ctm();
+        let bonus = (depth * depth * 16).min(MAX_HISTORY_BONUS as usize) as i32;
+
+        if self.killers[ply][0] != Some(best) {
+            self.killers[ply][1] = self.killers[ply][0];
+            self.killers[ply][0] = Some(best);
+        }
+
+        if let Some(last) = p.last_move() {
+            if let Some(piece) = p.board().piece_at(last.dst()) {
+                self.counters[piece.index()][last.dst().index()] = Some(best);
+            }
+        }
+
+        self.gravity(c, best, bonus);
+
+        for m in tried.iter().filter(|m| **m != best) {
+            self.gravity(c, *m, -bonus);
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Stage {
+    HASH,
+    CAPTURES,
+    KILLERS,
+    COUNTER,
+    QUIETS,
+    LOSING,
+    DONE,
+}
+
+/*
+ * Hands out the pseudolegal moves of a node one at a time: the hash move,
+ * captures that do not lose material (by MVV-LVA), killers, the countermove,
+ * quiet moves by history and finally the losing captures. Each stage picks
+ * its best remaining move on demand, so moves after a cutoff are never
+ * scored or sorted.
+ */
+pub struct MovePicker {
+    stage: Stage,
+    hash: Option<Move>,
+    killers: [Option<Move>; 2],
+    counter: Option<Move>,
+    noisy: Vec<(i32, Move)>,
+    quiets: Vec<(i32, Move)>,
+    bad: Vec<Move>,
+    next_killer: usize,
+    next_bad: usize,
+}
+
+impl MovePicker {
+    pub fn new(p: &Position, hash: Option<Move>, h: &Heuristics, ply: usize) -> MovePicker {
+        let (noisy, quiets): (Vec<Move>, Vec<Move>) = p
+            .gen_pseudolegal_moves()
+            .into_iter()
+            .partition(|m| is_noisy(p, *m));
+
+        MovePicker {
+            stage: Stage::HASH,
+            hash,
+            killers: h.killers(ply),
+            counter: h.counter(p),
+            noisy: noisy
+                .into_iter()
+                .map(|m| (search::mvv_lva(p, m), m))
+                .collect(),
+            quiets: quiets.into_iter().map(|m| (0, m)).collect(),
+            bad: Vec::new(),
+            next_killer: 0,
+            next_bad: 0,
+        }
+    }
+
+    /* Removes `m` from a list, if it is there */
+    fn take(list: &mut Vec<(i32, Move)>, m: Option<Move>) -> Option<Move> {
+        let i = list.iter().position(|(_, x)| Some(*x) == m)?;
+
+        Some(list.swap_remove(i).1)
+    }
+
+    /* Removes and returns the highest scored move of a list */
+    fn best(list: &mut Vec<(i32, Move)>) -> Option<Move> {
+        let i = (0..list.len()).max_by_key(|i| (list[*i].0, std::cmp::Reverse(*i)))?;
+
+        Some(list.swap_remove(i).1)
+    }
+
+    pub fn next(&mut self, p: &Position, h: &Heuristics) -> Option<Move> {
+        loop {
+            match self.stage {
+                Stage::HASH => {
+                    self.stage = Stage::CAPTURES;
+
+                    let hash = Self::take(&mut self.noisy, self.hash)
+                        .or_else(|| Self::take(&mut self.quiets, self.hash));
+
+                    if hash.is_some() {
+                        return hash;
+                    }
+                }
+                Stage::CAPTURES => match Self::best(&mut self.noisy) {
+                    Some(m) if see(p, m) < 0 => self.bad.push(m),
+                    Some(m) => return Some(m),
+                    None => self.stage = Stage::KILLERS,
+                },
+                Stage::KILLERS => {
+                    if self.next_killer == self.killers.len() {
+                        self.stage = Stage::COUNTER;
+                        continue;
+                    }
+
+                    let killer = self.killers[self.next_killer];
+                    self.next_killer += 1;
+
+                    if let Some(m) = Self::take(&mut self.quiets, killer) {
+                        return Some(m);
+                    }
+                }
+                Stage::COUNTER => {
+                    self.stage = Stage::QUIETS;
+
+                    for (s, m) in self.quiets.iter_mut() {
+                        *s = h.history(p.ctm(), *m);
+                    }
+
+                    if let Some(m) = Self::take(&mut self.quiets, self.counter) {
+                        return Some(m);
+                    }
+                }
+                Stage::QUIETS => match Self::best(&mut self.quiets) {
+                    Some(m) => return Some(m),
+                    None => self.stage = Stage::LOSING,
+                },
+                Stage::LOSING => match self.bad.get(self.next_bad) {
+                    Some(m) => {
+                        self.next_bad += 1;
+                        return Some(*m);
+                    }
+                    None => self.stage = Stage::DONE,
+                },
+                Stage::DONE => return None,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mv(s: &str) -> Move {
+        Move::from_uci(&s.to_string()).unwrap()
+    }
+
+    fn see_of(fen: &str, m: &str) -> i32 {
+        see(&Position::new(fen.to_string()).unwrap(), mv(m))
+    }
+
+    #[test]
+    fn movepick_see_works() {
+        /* Undefended rook */
+        assert_eq!(see_of("4k3/8/8/3r4/8/8/8/3RK3 w - - 0 1", "d1d5"), 500);
+
+        /* Queen takes a pawn defended by a pawn */
+        assert_eq!(see_of("4k3/8/2p5/3p4/8/8/8/3QK3 w - - 0 1", "d1d5"), -800);
+
+        /* Pawn takes a defended knight */
+        assert_eq!(see_of("4k3/4p3/3n4/2P5/8/8/8/4K3 w - - 0 1", "c5d6"), 200);
+
+        /* Doubled rooks win the exchange through the x-ray */
+        assert_eq!(see_of("3rk3/8/8/3n4/8/8/3R4/3RK3 w - - 0 1", "d2d5"), 300);
+        assert_eq!(
+            see_of("3rk3/3r4/8/3n4/8/8/3R4/3RK3 w - - 0 1", "d2d5"),
+            -200
+        );
+
+        /* The king cannot take a defended piece */
+        assert!(see_of("4k3/8/8/8/8/2b5/3p4/4K3 w - - 0 1", "e1d2") < -SEE_VALUE[4]);
+
+        /* Quiet moves only lose what can be taken on the destination */
+        assert_eq!(see_of("4k3/8/8/8/4p3/8/3Q4/4K3 w - - 0 1", "d2d3"), -900);
+        assert_eq!(see_of(STARTPOS, "e2e4"), 0);
+
+        /* En passant */
+        assert_eq!(see_of("4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 2", "e5d6"), 100);
+    }
+
+    #[test]
+    fn movepick_returns_every_move_once() {
+        let h = Heuristics::new();
+
+        for fen in [
+            STARTPOS,
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+        ]
+        .iter()
+        {
+            let p = Position::new(fen.to_string()).unwrap();
+            let mut expected = p.gen_pseudolegal_moves();
+            let hash = expected[expected.len() / 2];
+            let mut picker = MovePicker::new(&p, Some(hash), &h, 0);
+            let mut picked = Vec::new();
+
+            while let Some(m) = picker.next(&p, &h) {
+                picked.push(m);
+            }
+
+            assert_eq!(picked[0], hash);
+
+            let key = |m: &Move| m.to_uci();
+            expected.sort_by_key(key);
+            picked.sort_by_key(key);
+            assert_eq!(picked, expected, "{}", fen);
+        }
+    }
+
+    #[test]
+    fn movepick_orders_stages() {
+        let fen = "4k3/8/2p5/3p4/8/r7/1P6/3QK2R b K - 0 1";
+        let mut p = Position::new(fen.to_string()).unwrap();
+        let mut h = Heuristics::new();
+
+        assert!(p.make_move(mv("e8f8")));
+
+        h.update(&p, 1, 4, mv("h1h7"), &[mv("h1h6"), mv("h1h5")]);
+        h.update(&p, 1, 2, mv("e1f2"), &[]);
+        h.update(&p, 5, 1, mv("h1h3"), &[]);
+
+        assert_eq!(h.killers(1), [Some(mv("e1f2")), Some(mv("h1h7"))]);
+        assert_eq!(h.counter(&p), Some(mv("h1h3")));
+        assert!(h.history(Color::WHITE, mv("h1h7")) > 0);
+        assert!(h.history(Color::WHITE, mv("h1h6")) < 0);
+
+        let mut picker = MovePicker::new(&p, Some(mv("d1d2")), &h, 1);
+        let mut order = Vec::new();
+
+        while let Some(m) = picker.next(&p, &h) {
+            order.push(m);
+        }
+
+        /* Hash move, winning capture, killers, countermove */
+        assert_eq!(
+            order[..5],
+            [mv("d1d2"), mv("b2a3"), mv("e1f2"), mv("h1h7"), mv("h1h3")]
+        );
+
+        /* Then quiets by history, with the losing capture last */
+        let n = order.len();
+
+        assert_eq!(order[n - 1], mv("d1d5"));
+        assert!(order[5..n - 1].iter().all(|m| !is_noisy(&p, *m)));
+
+        let mut worst = order[n - 3..n - 1].to_vec();
+        worst.sort_by_key(|m| m.to_uci());
+        assert_eq!(worst, [mv("h1h5"), mv("h1h6")]);
+    }
+
+    #[test]
+    fn movepick_history_is_bounded() {
+        let p = Position::new(STARTPOS.to_string()).unwrap();
+        let mut h = Heuristics::new();
+
+        for _ in 0..1000 {
+            h.update(&p, 0, 20, mv("g1f3"), &[mv("b1c3")]);
+        }
+
+        assert!(h.history(Color::WHITE, mv("g1f3")) <= MAX_HISTORY);
+        assert!(h.history(Color::WHITE, mv("g1f3")) > MAX_HISTORY - MAX_HISTORY_BONUS);
+        assert!(h.history(Color::WHITE, mv("b1c3")) >= -MAX_HISTORY);
+        assert_eq!(h.history(Color::BLACK, mv("g1f3")), 0);
+    }
+}
@@ -0,0 +1,751 @@
+use crate::encode;
+use crate::gmove::*;
+use crate::mcts::*;
+use crate::policy;
+use crate::position::*;
+
+use std::fs;
+use std::io::{self, Error, ErrorKind};
+
+/*
+ * Weights file layout (little endian):
+ *
+ *   magic "NCNN", version (u32)
+ *   planes, filters, blocks, policy channels, value channels, value hidden (u32)
+ *   tensors (f32), in the order given by `Network::tensors`
+ *
+ * Convolution kernels are indexed [out][in][rank][file] and dense weights
+ * [out][in]. Batch norms are stored as gamma, beta, mean and variance and are
+ * folded into the preceding convolution on load.
+ */
+const MAGIC: &[u8; 4] = b"NCNN";
+pub const VERSION: u32 = 1;
+
+const BN_EPSILON: f32 = 1e-5;
+
+const HEADER: usize = 32;
+
+/* Widest layer and deepest residual tower accepted from a weights file */
+pub const MAX_WIDTH: usize = 1024;
+pub const MAX_BLOCKS: usize = 64;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Shape {
+    pub filters: usize,
+    pub blocks: usize,
+    pub policy_channels: usize,
+    pub value_channels: usize,
+    pub value_hidden: usize,
+}
+
+impl Default for Shape {
+    fn default() -> Shape {
+        Shape {
+            filters: 64,
+            blocks: 6,
+            policy_channels: 32,
+            value_channels: 32,
+            value_hidden: 128,
+        }
+    }
+}
+
+impl Shape {
+    /* Raw parameter count of a network of this shape, or None if it overflows */
+    pub fn parameters(&self) -> Option<usize> {
+        let f = self.filters;
+
+        /* Kernel, bias and the four batch norm vectors */
+        let conv_bn = |cin: usize, cout: usize, k: usize| {
+            cout.checked_mul(cin)?
+                .checked_mul(k * k)?
+                .checked_add(cout.checked_mul(5)?)
+        };
+        let dense = |cin: usize, cout: usize| cout.checked_mul(cin)?.checked_add(cout);
+
+        [
+            conv_bn(encode::PLANES, f, 3)?,
+            conv_bn(f, f, 3)?.checked_mul(2)?.checked_mul(self.blocks)?,
+            conv_bn(f, self.policy_channels, 1)?,
+            dense(self.policy_channels.checked_mul(64)?, policy::POLICY_SIZE)?,
+            conv_bn(f, self.value_channels, 1)?,
+            dense(self.value_channels.checked_mul(64)?, self.value_hidden)?,
+            dense(self.value_hidden, 1)?,
+        ]
+        .iter()
+        .try_fold(0usize, |t, n| t.checked_add(*n))
+    }
+}
+
+pub struct Conv {
+    pub cin: usize,
+    pub cout: usize,
+    pub k: usize,
+    pub weight: Vec<f32>,
+    pub bias: Vec<f32>,
+}
+
+impl Conv {
+    fn new(cin: usize, cout: usize, k: usize) -> Conv {
+        Conv {
+            cin,
+            cout,
+            k,
+            weight: vec![0.0; cout * cin * k * k],
+            bias: vec![0.0; cout],
+        }
+    }
+}
+
+pub struct BatchNorm {
+    pub gamma: Vec<f32>,
+    pub beta: Vec<f32>,
+    pub mean: Vec<f32>,
+    pub var: Vec<f32>,
+}
+
+impl BatchNorm {
+    fn new(c: usize) -> BatchNorm {
+        BatchNorm {
+            gamma: vec![1.0; c],
+            beta: vec![0.0; c],
+            mean: vec![0.0; c],
+            var: vec![1.0; c],
+        }
+    }
+}
+
+pub struct Dense {
+    pub cin: usize,
+    pub cout: usize,
+    pub weight: Vec<f32>,
+    pub bias: Vec<f32>,
+}
+
+impl Dense {
+    fn new(cin: usize, cout: usize) -> Dense {
+        Dense {
+            cin,
+            cout,
+            weight: vec![0.0; cout * cin],
+            bias: vec![0.0; cout],
+        }
+    }
+
+    /* Applies the layer to `n` inputs stored back to back */
+    fn forward(&self, x: &[f32], n: usize) -> Vec<f32> {
+        let mut out = Vec::with_capacity(n * self.cout);
+
+        for s in 0..n {
+            let xs = &x[s * self.cin..(s + 1) * self.cin];
+
+            for o in 0..self.cout {
+                let w = &self.weight[o * self.cin..(o + 1) * self.cin];
+                out.push(self.bias[o] + w.iter().zip(xs).map(|(a, b)| a * b).sum::<f32>());
+            }
+        }
+
+        out
+    }
+}
+
+/* A convolution followed by a batch norm, with the norm folded in for inference */
+pub struct ConvBn {
+    pub conv: Conv,
+    pub bn: BatchNorm,
+    weight: Vec<f32>,
+    bias: Vec<f32>,
+}
+
+impl ConvBn {
+    fn new(cin: usize, cout: usize, k: usize) -> ConvBn {
+        ConvBn {
+            conv: Conv::new(cin, cout, k),
+            bn: BatchNorm::new(cout),
+            weight: Vec::new(),
+            bias: Vec::new(),
+        }
+    }
+
+    fn fold(&mut self) {
+        let per_out = self.conv.cin * self.conv.k * self.conv.k;
+
+        self.weight = self.conv.weight.clone();
+        self.bias = vec![0.0; self.conv.cout];
+
+        for o in 0..self.conv.cout {
+            let scale = self.bn.gamma[o] / (self.bn.var[o] + BN_EPSILON).sqrt();
+
+            for w in self.weight[o * per_out..(o + 1) * per_out].iter_mut() {
+                *w *= scale;
+            }
+
+            self.bias[o] = (self.conv.bias[o] - self.bn.mean[o]) * scale + self.bn.beta[o];
+        }
+    }
+
+    /*
+     * Convolves `n` boards laid out [channel][board][square] and returns the
+     * output in the same layout. Done as a single matrix product over the
+     * whole batch, with zero padding at the board edges.
+     */
+    fn forward(&self, x: &[f32], n: usize) -> Vec<f32> {
+        let (cin, cout, k) = (self.conv.cin, self.conv.cout, self.conv.k);
+        let cols = n * 64;
+        let rows = cin * k * k;
+        let half = (k / 2) as i32;
+
+        let mut out = vec![0.0; cout * cols];
+
+        for o in 0..cout {
+            for v in out[o * cols..(o + 1) * cols].iter_mut() {
+                *v = self.bias[o];
+            }
+        }
+
+        if k == 1 {
+            matmul(&self.weight, x, cout, rows, cols, &mut out);
+            return out;
+        }
+
+        let mut col = vec![0.0; rows * cols];
+
+        for c in 0..cin {
+            for ky in 0..k {
+                for kx in 0..k {
+                    let row = (c * k + ky) * k + kx;
+                    let dr = ky as i32 - half;
+                    let df = kx as i32 - half;
+
+                    for b in 0..n {
+                        for s in 0..64 {
+                            let r = (s / 8) as i32 + dr;
+                            let f = (s % 8) as i32 + df;
+
+                            if (0..8).contains(&r) && (0..8).contains(&f) {
+                                col[row * cols + b * 64 + s] =
+                                    x[c * cols + b * 64 + (r * 8 + f) as usize];
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        matmul(&self.weight, &col, cout, rows, cols, &mut out);
+        out
+    }
+}
+
+/* out[m][n] += a[m][k] * b[k][n] */
+fn matmul(a: &[f32], b: &[f32], m: usize, k: usize, n: usize, out: &mut [f32]) {
+    for i in 0..m {
+        let row = &mut out[i * n..(i + 1) * n];
+
+        for j in 0..k {
+            let w = a[i * k + j];
+
+            if w == 0.0 {
+                continue;
+            }
+
+            for (o, v) in row.iter_mut().zip(&b[j * n..(j + 1) * n]) {
+                *o += w * v;
+            }
+        }
+    }
+}
+
+fn relu(x: &mut [f32]) {
+    for v in x.iter_mut() {
+        *v = v.max(0.0);
+    }
+}
+
+/* Reorders [channel][board][square] into [board][channel][square] */
+fn by_board(x: &[f32], channels: usize, n: usize) -> Vec<f32> {
+    let mut out = vec![0.0; x.len()];
+
+    for c in 0..channels {
+        for b in 0..n {
+            let src = (c * n + b) * 64;
+            let dst = (b * channels + c) * 64;
+
+            out[dst..dst + 64].copy_from_slice(&x[src..src + 64]);
+        }
+    }
+
+    out
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Output {
+    pub policy: Vec<f32>, /* Raw logits, indexed by `policy::encode` */
+    pub value: f32,       /* In [-1, 1], for the side to move */
+}
+
+/* Residual policy/value network evaluated on the CPU */
+pub struct Network {
+    pub shape: Shape,
+    pub input: ConvBn,
+    pub blocks: Vec<(ConvBn, ConvBn)>,
+    pub policy_conv: ConvBn,
+    pub policy_fc: Dense,
+    pub value_conv: ConvBn,
+    pub value_fc1: Dense,
+    pub value_fc2: Dense,
+}
+
+impl Network {
+    /* A network with zero weights and identity batch norms */
+    pub fn new(shape: Shape) -> Network {
+        let f = shape.filters;
+
+        let mut net = Network {
+            shape,
+            input: ConvBn::new(encode::PLANES, f, 3),
+            blocks: (0..shape.blocks)
+                .map(|_| (ConvBn::new(f, f, 3), ConvBn::new(f, f, 3)))
+                .collect(),
+            policy_conv: ConvBn::new(f, shape.policy_channels, 1),
+            policy_fc: Dense::new(shape.policy_channels * 64, policy::POLICY_SIZE),
+            value_conv: ConvBn::new(f, shape.value_channels, 1),
+            value_fc1: Dense::new(shape.value_channels * 64, shape.value_hidden),
+            value_fc2: Dense::new(shape.value_hidden, 1),
+        };
+
+        net.fold();
+        net
+    }
+
+    fn conv_bns_mut(&mut self) -> Vec<&mut ConvBn> {
+        let mut out = vec![&mut self.input];
+
+        for (a, b) in self.blocks.iter_mut() {
+            out.push(a);
+            out.push(b);
+        }
+
+        out.push(&mut self.policy_conv);
+        out.push(&mut self.value_conv);
+        out
+    }
+
+    /* Recomputes the folded inference weights after the raw ones change */
+    pub fn fold(&mut self) {
+        for cb in self.conv_bns_mut() {
+            cb.fold();
+        }
+    }
+
+    /* Every raw parameter tensor, in file order */
+    pub fn tensors(&self) -> Vec<&Vec<f32>> {
+        fn conv_bn(cb: &ConvBn) -> Vec<&Vec<f32>> {
+            vec![
+                &cb.conv.weight,
+                &cb.conv.bias,
+                &cb.bn.gamma,
+                &cb.bn.beta,
+                &cb.bn.mean,
+                &cb.bn.var,
+            ]
+        }
+
+        let mut out = conv_bn(&self.input);
+
+        for (a, b) in self.blocks.iter() {
+            out.extend(conv_bn(a));
+            out.extend(conv_bn(b));
+        }
+
+        out.extend(conv_bn(&self.policy_conv));
+        out.push(&self.policy_fc.weight);
+        out.push(&self.policy_fc.bias);
+
+        out.extend(conv_bn(&self.value_conv));
+        out.push(&self.value_fc1.weight);
+        out.push(&self.value_fc1.bias);
+        out.push(&self.value_fc2.weight);
+        out.push(&self.value_fc2.bias);
+
+        out
+    }
+
+    /* Same as `tensors`, for loading and training */
+    pub fn tensors_mut(&mut self) -> Vec<&mut Vec<f32>> {
+        fn conv_bn(cb: &mut ConvBn) -> Vec<&mut Vec<f32>> {
+            vec![
+                &mut cb.conv.weight,
+                &mut cb.conv.bias,
+                &mut cb.bn.gamma,
+                &mut cb.bn.beta,
+                &mut cb.bn.mean,
+                &mut cb.bn.var,
+            ]
+        }
+
+        let mut out = conv_bn(&mut self.input);
+
+        for (a, b) in self.blocks.iter_mut() {
+            out.extend(conv_bn(a));
+            out.extend(conv_bn(b));
+        }
+
+        out.extend(conv_bn(&mut self.policy_conv));
+        out.push(&mut self.policy_fc.weight);
+        out.push(&mut self.policy_fc.bias);
+
+        out.extend(conv_bn(&mut self.value_conv));
+        out.push(&mut self.value_fc1.weight);
+        out.push(&mut self.value_fc1.bias);
+        out.push(&mut self.value_fc2.weight);
+        out.push(&mut self.value_fc2.bias);
+
+        out
+    }
+
+    pub fn load(path: &str) -> io::Result<Network> {
+        Network::from_bytes(&fs::read(path)?)
+    }
+
+    pub fn from_bytes(data: &[u8]) -> io::Result<Network> {
+        let invalid = |msg: &str| Error::new(ErrorKind::InvalidData, msg.to_string());
+
+        if data.len() < HEADER || &data[0..4] != MAGIC {
+            return Err(invalid("not a network weights file"));
+        }
+
+        let word = |i: usize| u32::from_le_bytes([data[i], data[i + 1], data[i + 2], data[i + 3]]);
+        let header: Vec<usize> = (0..7).map(|i| word(4 + i * 4) as usize).collect();
+
+        if header[0] != VERSION as usize {
+            return Err(invalid(&format!("unsupported version {}", header[0])));
+        }
+
+        if header[1] != encode::PLANES {
+            return Err(invalid(&format!(
+                "expected {} input planes, read {}",
+                encode::PLANES,
+                header[1]
+            )));
+        }
+
+        let widths = [header[2], header[4], header[5], header[6]];
+
+        if widths.iter().any(|w| !(1..=MAX_WIDTH).contains(w)) || header[3] > MAX_BLOCKS {
+            return Err(invalid(&format!("bad network shape {:?}", &header[2..])));
+        }
+
+        let shape = Shape {
+            filters: header[2],
+            blocks: header[3],
+            policy_channels: header[4],
+            value_channels: header[5],
+            value_hidden: header[6],
+        };
+
+        /* Check the payload before allocating anything for it */
+        let expected = shape.parameters();
+        let size = expected
+            .and_then(|n| n.checked_mul(4))
+            .and_then(|n| n.checked_add(HEADER));
+
+        if size != Some(data.len()) {
+            return Err(invalid(&format!(
+                "expected {} parameters, file holds {}",
+                expected.map_or("too many".to_string(), |n| n.to_string()),
+                (data.len() - HEADER) / 4
+            )));
+        }
+
+        let mut net = Network::new(shape);
+        let mut pos = HEADER;
+
+        for t in net.tensors_mut() {
+            for v in t.iter_mut() {
+                *v = f32::from_le_bytes([data[pos], data[pos + 1], data[pos + 2], data[pos + 3]]);
+                pos += 4;
+            }
+        }
+
+        net.fold();
+
+        Ok(net)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let s = self.shape;
+        let mut out = MAGIC.to_vec();
+
+        for w in [
+            VERSION as usize,
+            encode::PLANES,
+            s.filters,
+            s.blocks,
+            s.policy_channels,
+            s.value_channels,
+            s.value_hidden,
+        ]
+        .iter()
+        {
+            out.extend_from_slice(&(*w as u32).to_le_bytes());
+        }
+
+        for t in self.tensors() {
+            for v in t.iter() {
+                out.extend_from_slice(&v.to_le_bytes());
+            }
+        }
+
+        out
+    }
+
+    pub fn save(&self, path: &str) -> io::Result<()> {
+        fs::write(path, self.to_bytes())
+    }
+
+    /* Evaluates a batch of encoded positions (see `encode::encode`) */
+    pub fn forward(&self, inputs: &[Vec<f32>]) -> Vec<Output> {
+        let n = inputs.len();
+
+        if n == 0 {
+            return Vec::new();
+        }
+
+        /* Convolutions work on [channel][board][square] */
+        let mut x = vec![0.0; encode::INPUT_SIZE * n];
+
+        for (b, input) in inputs.iter().enumerate() {
+            for c in 0..encode::PLANES {
+                let dst = (c * n + b) * 64;
+                x[dst..dst + 64].copy_from_slice(&input[c * 64..(c + 1) * 64]);
+            }
+        }
+
+        let mut x = self.input.forward(&x, n);
+        relu(&mut x);
+
+        for (a, b) in self.blocks.iter() {
+            let mut y = a.forward(&x, n);
+            relu(&mut y);
+
+            let mut y = b.forward(&y, n);
+
+            for (v, skip) in y.iter_mut().zip(x.iter()) {
+                *v += skip;
+            }
+
+            relu(&mut y);
+            x = y;
+        }
+
+        let mut p = self.policy_conv.forward(&x, n);
+        relu(&mut p);
+        let p = self
+            .policy_fc
+            .forward(&by_board(&p, self.shape.policy_channels, n), n);
+
+        let mut v = self.value_conv.forward(&x, n);
+        relu(&mut v);
+        let mut v = self
+            .value_fc1
+            .forward(&by_board(&v, self.shape.value_channels, n), n);
+        relu(&mut v);
+        let v = self.value_fc2.forward(&v, n);
+
+        (0..n)
+            .map(|b| Output {
+                policy: p[b * policy::POLICY_SIZE..(b + 1) * policy::POLICY_SIZE].to_vec(),
+                value: v[b].tanh(),
+            })
+            .collect()
+    }
+}
+
+/* Softmax of the network logits over the legal moves only */
+fn move_priors(p: &Position, moves: &[Move], logits: &[f32]) -> Vec<f32> {
+    let picked: Vec<f32> = moves
+        .iter()
+        .map(|m| policy::encode(p.ctm(), *m).map_or(f32::NEG_INFINITY, |i| logits[i]))
+        .collect();
+
+    let max = picked.iter().cloned().fold(f32::NEG_INFINITY, f32::max);
+    let exp: Vec<f32> = picked.iter().map(|l| (l - max).exp()).collect();
+    let total: f32 = exp.iter().sum();
+
+    exp.iter().map(|e| e / total).collect()
+}
+
+impl Evaluator for Network {
+    fn evaluate(&self, p: &Position, moves: &[Move]) -> (Vec<f32>, f32) {
+        self.evaluate_batch(&[(p, moves)]).pop().unwrap()
+    }
+
+    fn evaluate_batch(&self, items: &[(&Position, &[Move])]) -> Vec<(Vec<f32>, f32)> {
+        let inputs: Vec<Vec<f32>> = items.iter().map(|(p, _)| encode::encode(p)).collect();
+
+        self.forward(&inputs)
+            .into_iter()
+            .zip(items.iter())
+            .map(|(out, (p, moves))| (move_priors(p, moves, &out.policy), out.value))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /*
+     * Deterministic weights shared with fixtures/nn_reference.py. Values are
+     * multiples of 2^-24 so they are exact in both f32 and f64.
+     */
+    struct Lcg(u64);
+
+    impl Lcg {
+        fn next(&mut self) -> f32 {
+            self.0 = self
+                .0
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+
+            ((self.0 >> 40) as i32 - (1 << 23)) as f32 / (1 << 24) as f32
+        }
+    }
+
+    fn reference_shape() -> Shape {
+        Shape {
+            filters: 8,
+            blocks: 2,
+            policy_channels: 2,
+            value_channels: 1,
+            value_hidden: 16,
+        }
+    }
+
+    fn reference_network() -> Network {
+        let mut net = Network::new(reference_shape());
+        let mut rng = Lcg(0x6e63);
+
+        for t in net.tensors_mut() {
+            for v in t.iter_mut() {
+                *v = rng.next();
+            }
+        }
+
+        /* Variances must be positive */
+        for cb in net.conv_bns_mut() {
+            for v in cb.bn.var.iter_mut() {
+                *v = 1.0 + 2.0 * v.abs();
+            }
+        }
+
+        net.fold();
+        net
+    }
+
+    fn reference_input(b: usize) -> Vec<f32> {
+        (0..encode::INPUT_SIZE)
+            .map(|i| {
+                if (i * 31 + b * 17).is_multiple_of(11) {
+                    1.0
+                } else {
+                    0.0
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn nn_matches_reference_forward_pass() {
+        let fixture: Vec<f32> = include_str!("../fixtures/nn_reference.txt")
+            .lines()
+            .map(|l| l.trim().parse::<f32>().unwrap())
+            .collect();
+
+        let net = reference_network();
+        let out = net.forward(&[reference_input(0), reference_input(1)]);
+
+        assert_eq!(fixture.len(), 2 * (1 + policy::POLICY_SIZE));
+
+        for (b, o) in out.iter().enumerate() {
+            let expected = &fixture[b * (1 + policy::POLICY_SIZE)..];
+
+            assert!((o.value - expected[0]).abs() < 1e-4, "value {}", b);
+
+            for (i, l) in o.policy.iter().enumerate() {
+                assert!(
+                    (l - expected[1 + i]).abs() < 1e-4 * (1.0 + l.abs()),
+                    "logit {} of {}: {} != {}",
+                    i,
+                    b,
+                    l,
+                    expected[1 + i]
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn nn_batch_matches_single() {
+        let net = reference_network();
+        let inputs = [reference_input(0), reference_input(1), reference_input(2)];
+        let batch = net.forward(&inputs);
+
+        for (i, input) in inputs.iter().enumerate() {
+            let single = net.forward(std::slice::from_ref(input)).pop().unwrap();
+
+            assert!((single.value - batch[i].value).abs() < 1e-6);
+
+            for (a, b) in single.policy.iter().zip(batch[i].policy.iter()) {
+                assert!((a - b).abs() < 1e-5);
+            }
+        }
+    }
+
+    #[test]
+    fn nn_weights_roundtrip() {
+        let net = reference_network();
+        let bytes = net.to_bytes();
+        let loaded = Network::from_bytes(&bytes).unwrap();
+
+        assert_eq!(loaded.shape, reference_shape());
+        assert_eq!(loaded.to_bytes(), bytes);
+
+        let input = [reference_input(0)];
+        assert_eq!(loaded.forward(&input), net.forward(&input));
+
+        /* Truncated, bad magic and bad version */
+        assert!(Network::from_bytes(&bytes[..bytes.len() - 4]).is_err());
+        assert!(Network::from_bytes(&bytes[4..]).is_err());
+
+        let mut bad = bytes.clone();
+        bad[4] = 99;
+        assert!(Network::from_bytes(&bad).is_err());
+
+        /* Oversized shapes are rejected before anything is allocated */
+        for field in 2..7 {
+            let mut huge = bytes.clone();
+            huge[4 + field * 4..8 + field * 4].copy_from_slice(&u32::MAX.to_le_bytes());
+            assert!(Network::from_bytes(&huge).is_err());
+        }
+
+        assert_eq!(
+            reference_shape().parameters(),
+            Some(net.tensors().iter().map(|t| t.len()).sum())
+        );
+    }
+
+    #[test]
+    fn nn_evaluator_priors_cover_legal_moves() {
+        let net = reference_network();
+        let mut p = Position::new(STARTPOS.to_string()).unwrap();
+        let moves = p.gen_legal_moves();
+        let (priors, value) = net.evaluate(&p, &moves);
+
+        assert_eq!(priors.len(), moves.len());
+        assert!((priors.iter().sum::<f32>() - 1.0).abs() < 1e-4);
+        assert!(priors.iter().all(|x| *x > 0.0));
+        assert!((-1.0..=1.0).contains(&value));
+    }
+}
@@ -0,0 +1,738 @@
+use crate::bitboard;
+use crate::board::*;
+use crate::piece::*;
+
+use std::fmt;
+use std::fs;
+use std::io::{self, Error, ErrorKind};
+use std::sync::Arc;
+
+/*
+ * Network file layout (little endian):
+ *
+ *   magic "NCUE", version (u32)
+ *   features, l1, l2, l3 (u32)
+ *   feature transformer biases (i16 x l1), weights (i16 x features x l1)
+ *   hidden 1 biases (i32 x l2), weights (i8 x l2 x 2 * l1)
+ *   hidden 2 biases (i32 x l3), weights (i8 x l3 x l2)
+ *   output bias (i32), weights (i8 x l3)
+ *
+ * Feature transformer weights are stored one column of `l1` values per
+ * feature, dense weights [out][in].
+ */
+const MAGIC: &[u8; 4] = b"NCUE";
+pub const VERSION: u32 = 1;
+
+const HEADER: usize = 24;
+
+/* HalfKP inputs: own king square x non-king piece (type, ours/theirs) x square */
+pub const FEATURES: usize = 64 * 10 * 64;
+
+/* Widest layer accepted from a network file */
+pub const MAX_WIDTH: usize = 4096;
+
+/* Activations are clipped to [0, CLIP] between layers */
+pub const CLIP: i32 = 127;
+
+/* Hidden layer sums are scaled down by 2^WEIGHT_SHIFT before clipping */
+pub const WEIGHT_SHIFT: i32 = 6;
+
+/* Network output units per centipawn */
+pub const OUTPUT_SCALE: i32 = 16;
+
+/* Input index of the non-king piece `p` on `sq`, seen by `c` with its king on `king` */
+pub fn feature(c: Color, king: usize, p: Piece, sq: usize) -> usize {
+    let orient = |s: usize| match c {
+        Color::WHITE => s,
+        Color::BLACK => s ^ 56,
+    };
+    let theirs = (p.get_color() != c) as usize;
+
+    (orient(king) * 10 + p.get_type() as usize * 2 + theirs) * 64 + orient(sq)
+}
+
+/* Instruction set used for the accumulator updates and dot products */
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Simd {
+    SCALAR,
+    AVX2,
+}
+
+lazy_static! {
+    pub static ref SIMD: Simd = Simd::detect();
+}
+
+impl Simd {
+    /* The fastest instruction set supported by this CPU */
+    pub fn detect() -> Simd {
+        #[cfg(target_arch = "x86_64")]
+        {
+            if is_x86_feature_detected!("avx2") {
+                return Simd::AVX2;
+            }
+        }
+
+        Simd::SCALAR
+    }
+
+    /* Adds `w` to `acc` elementwise, wrapping on overflow */
+    pub fn add(self, acc: &mut [i16], w: &[i16]) {
+        assert_eq!(acc.len(), w.len());
+
+        match self {
+            #[cfg(target_arch = "x86_64")]
+            Simd::AVX2 => unsafe { avx2::add(acc, w) },
+            _ => scalar::add(acc, w),
+        }
+    }
+
+    /* Subtracts `w` from `acc` elementwise, wrapping on overflow */
+    pub fn sub(self, acc: &mut [i16], w: &[i16]) {
+        assert_eq!(acc.len(), w.len());
+
+        match self {
+            #[cfg(target_arch = "x86_64")]
+            Simd::AVX2 => unsafe { avx2::sub(acc, w) },
+            _ => scalar::sub(acc, w),
+        }
+    }
+
+    /*
+     * Dot product of clipped activations with a weight row. Inputs must be at
+     * most CLIP so the pairwise products summed by AVX2 cannot saturate.
+     */
+    pub fn dot(self, input: &[u8], w: &[i8]) -> i32 {
+        assert_eq!(input.len(), w.len());
+        debug_assert!(input.iter().all(|x| *x as i32 <= CLIP));
+
+        match self {
+            #[cfg(target_arch = "x86_64")]
+            Simd::AVX2 => unsafe { avx2::dot(input, w) },
+            _ => scalar::dot(input, w),
+        }
+    }
+}
+
+mod scalar {
+    pub fn add(acc: &mut [i16], w: &[i16]) {
+        for (a, w) in acc.iter_mut().zip(w.iter()) {
+            *a = a.wrapping_add(*w);
+        }
+    }
+
+    pub fn sub(acc: &mut [i16], w: &[i16]) {
+        for (a, w) in acc.iter_mut().zip(w.iter()) {
+            *a = a.wrapping_sub(*w);
+        }
+    }
+
+    pub fn dot(input: &[u8], w: &[i8]) -> i32 {
+        input
+            .iter()
+            .zip(w.iter())
+            .fold(0i32, |s, (x, w)| s.wrapping_add(*x as i32 * *w as i32))
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+mod avx2 {
+    use super::scalar;
+    use std::arch::x86_64::*;
+
+    #[target_feature(enable = "avx2")]
+    pub unsafe fn add(acc: &mut [i16], w: &[i16]) {
+        let n = acc.len() / 16 * 16;
+
+        for i in (0..n).step_by(16) {
+            let a = acc.as_mut_ptr().add(i) as *mut __m256i;
+            let b = _mm256_loadu_si256(w.as_ptr().add(i) as *const __m256i);
+
+            _mm256_storeu_si256(a, _mm256_add_epi16(_mm256_loadu_si256(a), b));
+        }
+
+        scalar::add(&mut acc[n..], &w[n..]);
+    }
+
+    #[target_feature(enable = "avx2")]
+    pub unsafe fn sub(acc: &mut [i16], w: &[i16]) {
+        let n = acc.len() / 16 * 16;
+
+        for i in (0..n).step_by(16) {
+            let a = acc.as_mut_ptr().add(i) as *mut __m256i;
+            let b = _mm256_loadu_si256(w.as_ptr().add(i) as *const __m256i);
+
+            _mm256_storeu_si256(a, _mm256_sub_epi16(_mm256_loadu_si256(a), b));
+        }
+
+        scalar::sub(&mut acc[n..], &w[n..]);
+    }
+
+    #[target_feature(enable = "avx2")]
+    pub unsafe fn dot(input: &[u8], w: &[i8]) -> i32 {
+        let n = input.len() / 32 * 32;
+        let ones = _mm256_set1_epi16(1);
+        let mut sum = _mm256_setzero_si256();
+
+        for i in (0..n).step_by(32) {
+            let x = _mm256_loadu_si256(input.as_ptr().add(i) as *const __m256i);
+            let y = _mm256_loadu_si256(w.as_ptr().add(i) as *const __m256i);
+
+            sum = _mm256_add_epi32(sum, _mm256_madd_epi16(_mm256_maddubs_epi16(x, y), ones));
+        }
+
+        let s = _mm_add_epi32(
+            _mm256_castsi256_si128(sum),
+            _mm256_extracti128_si256(sum, 1),
+        );
+        let s = _mm_add_epi32(s, _mm_shuffle_epi32(s, 0b01_00_11_10));
+        let s = _mm_add_epi32(s, _mm_shuffle_epi32(s, 0b10_11_00_01));
+
+        _mm_cvtsi128_si32(s).wrapping_add(scalar::dot(&input[n..], &w[n..]))
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Shape {
+    pub l1: usize, /* Feature transformer outputs per perspective */
+    pub l2: usize,
+    pub l3: usize,
+}
+
+impl Default for Shape {
+    fn default() -> Shape {
+        Shape {
+            l1: 256,
+            l2: 32,
+            l3: 32,
+        }
+    }
+}
+
+/* Quantised fully connected layer */
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Dense {
+    pub inputs: usize,
+    pub outputs: usize,
+    pub weights: Vec<i8>, /* [out][in] */
+    pub biases: Vec<i32>,
+}
+
+impl Dense {
+    pub fn new(inputs: usize, outputs: usize) -> Dense {
+        Dense {
+            inputs,
+            outputs,
+            weights: vec![0; inputs * outputs],
+            biases: vec![0; outputs],
+        }
+    }
+
+    /* Raw output sums */
+    pub fn forward(&self, input: &[u8], simd: Simd) -> Vec<i32> {
+        self.biases
+            .iter()
+            .zip(self.weights.chunks(self.inputs))
+            .map(|(b, w)| b.wrapping_add(simd.dot(input, w)))
+            .collect()
+    }
+
+    /* Output sums scaled and clipped to activations for the next layer */
+    pub fn activate(&self, input: &[u8], simd: Simd) -> Vec<u8> {
+        self.forward(input, simd)
+            .iter()
+            .map(|s| (s >> WEIGHT_SHIFT).clamp(0, CLIP) as u8)
+            .collect()
+    }
+}
+
+/* HalfKP evaluation network with int16 feature transformer and int8 dense layers */
+#[derive(Clone, PartialEq, Eq)]
+pub struct Network {
+    pub shape: Shape,
+    pub ft_weights: Vec<i16>, /* [feature][l1] */
+    pub ft_biases: Vec<i16>,
+    pub hidden1: Dense,
+    pub hidden2: Dense,
+    pub output: Dense,
+}
+
+impl fmt::Debug for Network {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Network")
+            .field("shape", &self.shape)
+            .finish()
+    }
+}
+
+impl Network {
+    /* A network with zero weights */
+    pub fn new(shape: Shape) -> Network {
+        Network {
+            shape,
+            ft_weights: vec![0; FEATURES * shape.l1],
+            ft_biases: vec![0; shape.l1],
+            hidden1: Dense::new(2 * shape.l1, shape.l2),
+            hidden2: Dense::new(shape.l2, shape.l3),
+            output: Dense::new(shape.l3, 1),
+        }
+    }
+
+    /* Feature transformer weights for input `f` */
+    pub fn column(&self, f: usize) -> &[i16] {
+        &self.ft_weights[f * self.shape.l1..(f + 1) * self.shape.l1]
+    }
+
+    /* Score in centipawns from the accumulators of the side to move and its opponent */
+    pub fn forward(&self, us: &[i16], them: &[i16], simd: Simd) -> i32 {
+        let input: Vec<u8> = us
+            .iter()
+            .chain(them.iter())
+            .map(|v| (*v as i32).clamp(0, CLIP) as u8)
+            .collect();
+
+        let h1 = self.hidden1.activate(&input, simd);
+        let h2 = self.hidden2.activate(&h1, simd);
+
+        self.output.forward(&h2, simd)[0] / OUTPUT_SCALE
+    }
+
+    pub fn load(path: &str) -> io::Result<Network> {
+        Network::from_bytes(&fs::read(path)?)
+    }
+
+    pub fn from_bytes(data: &[u8]) -> io::Result<Network> {
+        let invalid = |msg: &str| Error::new(ErrorKind::InvalidData, msg.to_string());
+
+        if data.len() < HEADER || &data[0..4] != MAGIC {
+            return Err(invalid("not an NNUE network file"));
+        }
+
+        let word = |i: usize| u32::from_le_bytes([data[i], data[i + 1], data[i + 2], data[i + 3]]);
+        let header: Vec<usize> = (0..5).map(|i| word(4 + i * 4) as usize).collect();
+
+        if header[0] != VERSION as usize {
+            return Err(invalid(&format!("unsupported version {}", header[0])));
+        }
+
+        if header[1] != FEATURES {
+            return Err(invalid(&format!(
+                "expected {} input features, read {}",
+                FEATURES, header[1]
+            )));
+        }
+
+        if header[2..].iter().any(|w| !(1..=MAX_WIDTH).contains(w)) {
+            return Err(invalid(&format!("bad layer widths {:?}", &header[2..])));
+        }
+
+        let mut net = Network::new(Shape {
+            l1: header[2],
+            l2: header[3],
+            l3: header[4],
+        });
+
+        if data.len() != HEADER + net.size() {
+            return Err(invalid(&format!(
+                "expected {} bytes of weights, file holds {}",
+                net.size(),
+                data.len() - HEADER
+            )));
+        }
+
+        let mut pos = HEADER;
+        let mut take = |n: usize| {
+            pos += n;
+            &data[pos - n..pos]
+        };
+
+        for v in net.ft_biases.iter_mut().chain(net.ft_weights.iter_mut()) {
+            let b = take(2);
+            *v = i16::from_le_bytes([b[0], b[1]]);
+        }
+
+        for d in [&mut net.hidden1, &mut net.hidden2, &mut net.output].iter_mut() {
+            for v in d.biases.iter_mut() {
+                let b = take(4);
+                *v = i32::from_le_bytes([b[0], b[1], b[2], b[3]]);
+            }
+
+            for v in d.weights.iter_mut() {
+                *v = take(1)[0] as i8;
+            }
+        }
+
+        Ok(net)
+    }
+
+    /* Bytes of weights following the header */
+    fn size(&self) -> usize {
+        let dense = |d: &Dense| d.biases.len() * 4 + d.weights.len();
+
+        (self.ft_biases.len() + self.ft_weights.len()) * 2
+            + dense(&self.hidden1)
+            + dense(&self.hidden2)
+            + dense(&self.output)
+    }
+}
+
+/*
+ * Feature transformer outputs for both perspectives, kept up to date by the
+ * board as pieces are placed and removed. A perspective is only valid while
+ * its king is on the board; placing the king rebuilds it from scratch.
+ */
+#[derive(Clone, Debug)]
+pub struct Accumulator {
+    network: Arc<Network>,
+    values: [Vec<i16>; 2],
+    kings: [Option<usize>; 2], /* King square each perspective was built for */
+}
+
+impl PartialEq for Accumulator {
+    fn eq(&self, o: &Accumulator) -> bool {
+        /* Values of a perspective without its king are stale and ignored */
+        Arc::ptr_eq(&self.network, &o.network)
+            && self.kings == o.kings
+            && (0..2).all(|i| self.kings[i].is_none() || self.values[i] == o.values[i])
+    }
+}
+
+impl Eq for Accumulator {}
+
+impl Accumulator {
+    pub fn new(network: Arc<Network>, b: &Board) -> Accumulator {
+        let values = [network.ft_biases.clone(), network.ft_biases.clone()];
+        let mut acc = Accumulator {
+            network,
+            values,
+            kings: [None; 2],
+        };
+
+        acc.refresh(b, Color::WHITE);
+        acc.refresh(b, Color::BLACK);
+        acc
+    }
+
+    pub fn network(&self) -> &Arc<Network> {
+        &self.network
+    }
+
+    /* Rebuilds `c`'s perspective from every piece on the board */
+    pub fn refresh(&mut self, b: &Board, c: Color) {
+        let i = c as usize;
+        let king = b.piece_occ(Type::KING) & b.color_occ(c);
+
+        if king == 0 {
+            self.kings[i] = None;
+            return;
+        }
+
+        let k = king.trailing_zeros() as usize;
+        let network = &self.network;
+        let values = &mut self.values[i];
+
+        values.copy_from_slice(&network.ft_biases);
+
+        bitboard::for_each(b.global_occ() & !b.piece_occ(Type::KING), |s| {
+            let p = b.piece_at(s).unwrap();
+            SIMD.add(values, network.column(feature(c, k, p, s.index())));
+        });
+
+        self.kings[i] = Some(k);
+    }
+
+    /* Updates for `p` placed on `sq`, after the board has been changed */
+    pub fn place(&mut self, b: &Board, sq: usize, p: Piece) {
+        for c in [Color::WHITE, Color::BLACK].iter() {
+            let i = *c as usize;
+
+            if p.get_type() == Type::KING {
+                if p.get_color() == *c {
+                    self.refresh(b, *c);
+                }
+            } else if let Some(k) = self.kings[i] {
+                SIMD.add(
+                    &mut self.values[i],
+                    self.network.column(feature(*c, k, p, sq)),
+                );
+            }
+        }
+    }
+
+    /* Updates for `p` removed from `sq` */
+    pub fn remove(&mut self, sq: usize, p: Piece) {
+        for c in [Color::WHITE, Color::BLACK].iter() {
+            let i = *c as usize;
+
+            if p.get_type() == Type::KING {
+                if p.get_color() == *c {
+                    self.kings[i] = None;
+                }
+            } else if let Some(k) = self.kings[i] {
+                SIMD.sub(
+                    &mut self.values[i],
+                    self.network.column(feature(*c, k, p, sq)),
+                );
+            }
+        }
+    }
+
+    /* Score in centipawns for the side to move, if both kings are on the board */
+    pub fn evaluate(&self, ctm: Color) -> Option<i32> {
+        self.kings[0]?;
+        self.kings[1]?;
+
+        Some(self.network.forward(
+            &self.values[ctm as usize],
+            &self.values[ctm.flip() as usize],
+            *SIMD,
+        ))
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::eval;
+    use crate::pawns::PawnTable;
+    use crate::position::tests::mirror_fen;
+    use crate::position::*;
+    use crate::square::*;
+
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    fn random_network(shape: Shape, seed: u64) -> Network {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut net = Network::new(shape);
+
+        for v in net.ft_biases.iter_mut() {
+            *v = rng.gen_range(0, 64);
+        }
+
+        for v in net.ft_weights.iter_mut() {
+            *v = rng.gen_range(-16, 17);
+        }
+
+        for d in [&mut net.hidden1, &mut net.hidden2, &mut net.output].iter_mut() {
+            for v in d.weights.iter_mut() {
+                *v = rng.gen_range(-128, 128) as i8;
+            }
+
+            for v in d.biases.iter_mut() {
+                *v = rng.gen_range(-2000, 2000);
+            }
+        }
+
+        net
+    }
+
+    /* Serialises a network in the file layout read by `Network::from_bytes` */
+    pub fn to_bytes(net: &Network) -> Vec<u8> {
+        let s = net.shape;
+        let mut out = MAGIC.to_vec();
+
+        out.reserve(net.size() + HEADER);
+
+        for w in [VERSION as usize, FEATURES, s.l1, s.l2, s.l3].iter() {
+            out.extend_from_slice(&(*w as u32).to_le_bytes());
+        }
+
+        for v in net.ft_biases.iter().chain(net.ft_weights.iter()) {
+            out.extend_from_slice(&v.to_le_bytes());
+        }
+
+        for d in [&net.hidden1, &net.hidden2, &net.output].iter() {
+            for v in d.biases.iter() {
+                out.extend_from_slice(&v.to_le_bytes());
+            }
+
+            out.extend(d.weights.iter().map(|v| *v as u8));
+        }
+
+        out
+    }
+
+    fn small() -> Shape {
+        Shape {
+            l1: 24,
+            l2: 40,
+            l3: 8,
+        }
+    }
+
+    fn position(fen: &str, net: &Arc<Network>) -> Position {
+        let mut p = Position::new(fen.to_string()).unwrap();
+
+        p.set_network(Some(net.clone()));
+        p
+    }
+
+    #[test]
+    fn nnue_features_are_distinct() {
+        let wk = Piece::from(Type::KNIGHT, Color::WHITE);
+        let bk = Piece::from(Type::KNIGHT, Color::BLACK);
+        let bq = Piece::from(Type::QUEEN, Color::BLACK);
+
+        assert_eq!(feature(Color::WHITE, 63, bq, 63), FEATURES - 1);
+        assert_ne!(
+            feature(Color::WHITE, 4, wk, 10),
+            feature(Color::WHITE, 4, bk, 10)
+        );
+
+        /* Black sees the board flipped with the colours swapped */
+        assert_eq!(
+            feature(Color::WHITE, 4, wk, 10),
+            feature(Color::BLACK, 60, bk, 50)
+        );
+    }
+
+    #[test]
+    fn nnue_network_roundtrips() {
+        let net = random_network(small(), 1);
+        let bytes = to_bytes(&net);
+
+        assert_eq!(Network::from_bytes(&bytes).unwrap(), net);
+
+        assert!(Network::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        assert!(Network::from_bytes(b"NCNN").is_err());
+
+        let mut wide = bytes.clone();
+        wide[12..16].copy_from_slice(&(MAX_WIDTH as u32 + 1).to_le_bytes());
+        assert!(Network::from_bytes(&wide).is_err());
+
+        let path = std::env::temp_dir().join("nc_nnue_roundtrip.nnue");
+        let path = path.to_str().unwrap();
+
+        fs::write(path, &bytes).unwrap();
+        assert_eq!(Network::load(path).unwrap(), net);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn nnue_simd_matches_scalar() {
+        let mut rng = StdRng::seed_from_u64(2);
+        let simd = Simd::detect();
+
+        for n in [1, 15, 16, 31, 32, 33, 100, 512].iter() {
+            let w: Vec<i16> = (0..*n).map(|_| rng.gen()).collect();
+            let mut a: Vec<i16> = (0..*n).map(|_| rng.gen()).collect();
+            let mut b = a.clone();
+
+            simd.add(&mut a, &w);
+            Simd::SCALAR.add(&mut b, &w);
+            assert_eq!(a, b);
+
+            simd.sub(&mut a, &w);
+            Simd::SCALAR.sub(&mut b, &w);
+            assert_eq!(a, b);
+
+            let x: Vec<u8> = (0..*n).map(|_| rng.gen_range(0, CLIP as u8 + 1)).collect();
+            let y: Vec<i8> = (0..*n).map(|_| rng.gen()).collect();
+            assert_eq!(simd.dot(&x, &y), Simd::SCALAR.dot(&x, &y));
+        }
+
+        let net = random_network(small(), 3);
+        let us: Vec<i16> = (0..small().l1).map(|_| rng.gen_range(-50, 200)).collect();
+        let them: Vec<i16> = (0..small().l1).map(|_| rng.gen_range(-50, 200)).collect();
+
+        assert_eq!(
+            net.forward(&us, &them, simd),
+            net.forward(&us, &them, Simd::SCALAR)
+        );
+    }
+
+    #[test]
+    fn nnue_incremental_matches_refresh() {
+        let net = Arc::new(random_network(small(), 4));
+        let mut rng = StdRng::seed_from_u64(5);
+
+        for fen in [
+            STARTPOS,
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+            "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+        ]
+        .iter()
+        {
+            let mut p = position(fen, &net);
+            let start = p.clone();
+            let mut played = Vec::new();
+
+            for _ in 0..40 {
+                let moves = p.gen_legal_moves();
+
+                if moves.is_empty() {
+                    break;
+                }
+
+                let m = moves[rng.gen_range(0, moves.len())];
+
+                assert!(p.make_move(m));
+                played.push(m);
+
+                let fresh = Accumulator::new(net.clone(), p.board());
+                assert_eq!(p.board().nnue(p.ctm()), fresh.evaluate(p.ctm()), "{}", fen);
+                assert_eq!(position(&p.to_fen(), &net).board(), p.board());
+            }
+
+            for m in played.iter().rev() {
+                p.unmake_move(*m);
+            }
+
+            assert_eq!(p, start);
+        }
+    }
+
+    #[test]
+    fn nnue_eval_is_symmetric() {
+        let net = Arc::new(random_network(small(), 6));
+
+        for fen in [
+            STARTPOS,
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "4k3/8/8/3q4/8/8/2PPN3/4K3 b - - 0 1",
+        ]
+        .iter()
+        {
+            let p = position(fen, &net);
+            let mirrored = position(&mirror_fen(fen), &net);
+
+            assert!(p.board().nnue(p.ctm()).is_some());
+            assert_eq!(
+                p.board().nnue(p.ctm()),
+                mirrored.board().nnue(mirrored.ctm()),
+                "{}",
+                fen
+            );
+        }
+    }
+
+    #[test]
+    fn nnue_replaces_classical_eval() {
+        let net = Arc::new(random_network(small(), 7));
+        let mut p = Position::new(STARTPOS.to_string()).unwrap();
+        let classical = eval::evaluate(&p, &mut PawnTable::default());
+
+        assert_eq!(p.board().nnue(p.ctm()), None);
+
+        p.set_network(Some(net));
+        assert_eq!(
+            Some(eval::evaluate(&p, &mut PawnTable::default())),
+            p.board().nnue(p.ctm())
+        );
+
+        p.set_network(None);
+        assert_eq!(eval::evaluate(&p, &mut PawnTable::default()), classical);
+
+        /* Removing a king leaves that perspective invalid until it returns */
+        let mut b = p.board().clone();
+        b.set_network(Some(Arc::new(random_network(small(), 8))));
+
+        let e1 = Square::from_uci(&"e1".to_string()).unwrap();
+        let king = b.remove(e1);
+
+        assert_eq!(b.nnue(Color::WHITE), None);
+        b.place(e1, king);
+        assert!(b.nnue(Color::WHITE).is_some());
+    }
+}
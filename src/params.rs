@@ -0,0 +1,369 @@
+use crate::eval::{self, Score};
+use crate::mobility;
+use crate::pawns;
+use crate::piece::*;
+use crate::safety;
+
+use std::cell::RefCell;
+use std::fs;
+use std::io;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::RwLock;
+
+/*
+ * Every linear evaluation weight lives in one flat vector so the tuner can
+ * adjust them together. Each term is a score per matching piece, with the
+ * offsets below naming where each group starts.
+ */
+pub const MATERIAL: usize = 0;
+pub const PST: usize = MATERIAL + 6;
+pub const PASSED: usize = PST + 6 * 64;
+pub const CONNECTED: usize = PASSED + 8;
+pub const ISOLATED: usize = CONNECTED + 8;
+pub const DOUBLED: usize = ISOLATED + 1;
+pub const BACKWARD: usize = DOUBLED + 1;
+pub const ISLAND: usize = BACKWARD + 1;
+pub const SHIELD: usize = ISLAND + 1;
+pub const SEMI_OPEN: usize = SHIELD + 2;
+pub const OPEN: usize = SEMI_OPEN + 1;
+pub const MOBILITY: usize = OPEN + 1;
+pub const KNIGHT_OUTPOST: usize = MOBILITY + 6;
+pub const BISHOP_OUTPOST: usize = KNIGHT_OUTPOST + 1;
+pub const ROOK_OPEN: usize = BISHOP_OUTPOST + 1;
+pub const ROOK_SEMI_OPEN: usize = ROOK_OPEN + 1;
+pub const ROOK_SEVENTH: usize = ROOK_SEMI_OPEN + 1;
+pub const BISHOP_PAIR: usize = ROOK_SEVENTH + 1;
+pub const TRAPPED_BISHOP: usize = BISHOP_PAIR + 1;
+pub const TRAPPED_ROOK: usize = TRAPPED_BISHOP + 1;
+pub const COUNT: usize = TRAPPED_ROOK + 1;
+
+/* Names used in parameter files, with each group's offset and length */
+pub const LAYOUT: [(&str, usize, usize); 25] = [
+    ("material", MATERIAL, 6),
+    ("pawn_pst", PST, 64),
+    ("bishop_pst", PST + 64, 64),
+    ("knight_pst", PST + 2 * 64, 64),
+    ("rook_pst", PST + 3 * 64, 64),
+    ("queen_pst", PST + 4 * 64, 64),
+    ("king_pst", PST + 5 * 64, 64),
+    ("passed", PASSED, 8),
+    ("connected", CONNECTED, 8),
+    ("isolated", ISOLATED, 1),
+    ("doubled", DOUBLED, 1),
+    ("backward", BACKWARD, 1),
+    ("island", ISLAND, 1),
+    ("shield", SHIELD, 2),
+    ("semi_open", SEMI_OPEN, 1),
+    ("open", OPEN, 1),
+    ("mobility", MOBILITY, 6),
+    ("knight_outpost", KNIGHT_OUTPOST, 1),
+    ("bishop_outpost", BISHOP_OUTPOST, 1),
+    ("rook_open", ROOK_OPEN, 1),
+    ("rook_semi_open", ROOK_SEMI_OPEN, 1),
+    ("rook_seventh", ROOK_SEVENTH, 1),
+    ("bishop_pair", BISHOP_PAIR, 1),
+    ("trapped_bishop", TRAPPED_BISHOP, 1),
+    ("trapped_rook", TRAPPED_ROOK, 1),
+];
+
+/* Index of the piece-square weight for a piece, read from white's point of view with a8 first */
+pub fn pst_index(p: Piece, sq: usize) -> usize {
+    let idx = match p.get_color() {
+        Color::WHITE => sq ^ 56,
+        Color::BLACK => sq,
+    };
+
+    PST + p.get_type() as usize * 64 + idx
+}
+
+/* Position in LAYOUT of the group holding the weight at `index` */
+pub fn group(index: usize) -> usize {
+    LAYOUT
+        .iter()
+        .position(|(_, o, l)| (*o..*o + *l).contains(&index))
+        .unwrap()
+}
+
+/* Name of the weight at `index`, as written in parameter files */
+pub fn name(index: usize) -> String {
+    let (group, offset, len) = LAYOUT[group(index)];
+
+    if len == 1 {
+        group.to_string()
+    } else {
+        format!("{}[{}]", group, index - offset)
+    }
+}
+
+/* Index of the weight called `name`, if there is one */
+pub fn index(name: &str) -> Option<usize> {
+    let (group, i) = match name.split_once('[') {
+        Some((g, rest)) => (g, rest.strip_suffix(']')?.parse::<usize>().ok()?),
+        None => (name, 0),
+    };
+
+    LAYOUT
+        .iter()
+        .find(|(g, _, _)| *g == group)
+        .filter(|(_, _, len)| i < *len)
+        .map(|(_, offset, _)| offset + i)
+}
+
+/* A set of evaluation weights */
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Params {
+    pub values: Vec<Score>,
+    pub generation: usize, /* Bumped by `set` so cached scores can be discarded */
+}
+
+impl Default for Params {
+    fn default() -> Params {
+        let mut values = vec![Score::default(); COUNT];
+        let mut copy = |offset: usize, scores: &[Score]| {
+            values[offset..offset + scores.len()].copy_from_slice(scores);
+        };
+
+        for t in 0..6 {
+            copy(
+                MATERIAL + t,
+                &[Score::new(eval::MG_MATERIAL[t], eval::EG_MATERIAL[t])],
+            );
+
+            let pst: Vec<Score> = (0..64)
+                .map(|i| Score::new(eval::MG_PST[t][i], eval::EG_PST[t][i]))
+                .collect();
+
+            copy(PST + t * 64, &pst);
+        }
+
+        copy(PASSED, &pawns::PASSED);
+        copy(CONNECTED, &pawns::CONNECTED);
+        copy(ISOLATED, &[pawns::ISOLATED]);
+        copy(DOUBLED, &[pawns::DOUBLED]);
+        copy(BACKWARD, &[pawns::BACKWARD]);
+        copy(ISLAND, &[pawns::ISLAND]);
+        copy(SHIELD, &safety::SHIELD);
+        copy(SEMI_OPEN, &[safety::SEMI_OPEN]);
+        copy(OPEN, &[safety::OPEN]);
+        copy(MOBILITY, &mobility::MOBILITY);
+        copy(KNIGHT_OUTPOST, &[mobility::KNIGHT_OUTPOST]);
+        copy(BISHOP_OUTPOST, &[mobility::BISHOP_OUTPOST]);
+        copy(ROOK_OPEN, &[mobility::ROOK_OPEN]);
+        copy(ROOK_SEMI_OPEN, &[mobility::ROOK_SEMI_OPEN]);
+        copy(ROOK_SEVENTH, &[mobility::ROOK_SEVENTH]);
+        copy(BISHOP_PAIR, &[mobility::BISHOP_PAIR]);
+        copy(TRAPPED_BISHOP, &[mobility::TRAPPED_BISHOP]);
+        copy(TRAPPED_ROOK, &[mobility::TRAPPED_ROOK]);
+
+        Params {
+            values,
+            generation: 0,
+        }
+    }
+}
+
+impl Params {
+    /* Tapered material value of a piece type */
+    pub fn material(&self, t: Type) -> Score {
+        self.values[MATERIAL + t as usize]
+    }
+
+    /* Material and piece-square score of a piece, from its owner's perspective */
+    pub fn piece_square(&self, p: Piece, sq: usize) -> Score {
+        self.material(p.get_type()) + self.values[pst_index(p, sq)]
+    }
+
+    /*
+     * Reads a parameter file of "name mg eg" lines. Weights missing from the
+     * file keep their defaults; blank lines and '#' comments are ignored.
+     */
+    pub fn load(path: &str) -> io::Result<Params> {
+        let mut params = Params::default();
+
+        for line in fs::read_to_string(path)?.lines() {
+            let line = line.split('#').next().unwrap().trim();
+
+            if line.is_empty() {
+                continue;
+            }
+
+            let parts: Vec<&str> = line.split_whitespace().collect();
+            let parsed = match parts[..] {
+                [name, mg, eg] => {
+                    index(name).zip(mg.parse::<i32>().ok().zip(eg.parse::<i32>().ok()))
+                }
+                _ => None,
+            };
+
+            match parsed {
+                Some((i, (mg, eg))) => params.values[i] = Score::new(mg, eg),
+                None => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("bad parameter line '{}'", line),
+                    ))
+                }
+            }
+        }
+
+        Ok(params)
+    }
+
+    pub fn save(&self, path: &str) -> io::Result<()> {
+        let text: String = self
+            .values
+            .iter()
+            .enumerate()
+            .map(|(i, s)| format!("{} {} {}\n", name(i), s.mg, s.eg))
+            .collect();
+
+        fs::write(path, text)
+    }
+}
+
+/*
+ * Receives evaluation terms as a weight index and a count from white's point
+ * of view. Terms that are not linear in any weight are passed as constants.
+ */
+pub trait Accumulator {
+    fn add(&mut self, index: usize, count: i32);
+    fn constant(&mut self, score: Score);
+}
+
+/* A group of evaluation terms extracted from a board */
+pub trait TermGroup {
+    /* Feeds each term to `acc`, counted from white's perspective */
+    fn accumulate<A: Accumulator>(&self, acc: &mut A);
+
+    /* The group's score under `params`, from white's perspective */
+    fn score(&self, params: &Params) -> Score {
+        let mut sum = Sum::new(params);
+
+        self.accumulate(&mut sum);
+        sum.score
+    }
+}
+
+/* Sums terms weighted by a set of parameters */
+pub struct Sum<'a> {
+    pub params: &'a Params,
+    pub score: Score,
+}
+
+impl<'a> Sum<'a> {
+    pub fn new(params: &'a Params) -> Sum<'a> {
+        Sum {
+            params,
+            score: Score::default(),
+        }
+    }
+}
+
+impl<'a> Accumulator for Sum<'a> {
+    fn add(&mut self, index: usize, count: i32) {
+        self.score += self.params.values[index] * count;
+    }
+
+    fn constant(&mut self, score: Score) {
+        self.score += score;
+    }
+}
+
+static GENERATION: AtomicUsize = AtomicUsize::new(0);
+
+lazy_static! {
+    static ref CURRENT: RwLock<Params> = RwLock::new(Params::default());
+}
+
+thread_local! {
+    static LOCAL: RefCell<Rc<Params>> = RefCell::new(Rc::new(current()));
+}
+
+/* A copy of the parameters in use */
+pub fn current() -> Params {
+    CURRENT.read().unwrap().clone()
+}
+
+/* Replaces the parameters used by every evaluation */
+pub fn set(mut params: Params) {
+    let mut current = CURRENT.write().unwrap();
+
+    params.generation = GENERATION.fetch_add(1, Ordering::Relaxed) + 1;
+    *current = params;
+}
+
+/* Runs `f` with the parameters in use, cached per thread until the next `set` */
+pub fn with<R, F: FnOnce(&Params) -> R>(f: F) -> R {
+    let params = LOCAL.with(|l| {
+        if l.borrow().generation != GENERATION.load(Ordering::Relaxed) {
+            *l.borrow_mut() = Rc::new(current());
+        }
+
+        l.borrow().clone()
+    });
+
+    f(&params)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn params_layout_is_contiguous() {
+        for w in LAYOUT.windows(2) {
+            assert_eq!(w[0].1 + w[0].2, w[1].1, "{}", w[0].0);
+        }
+
+        let last = LAYOUT[LAYOUT.len() - 1];
+        assert_eq!(last.1 + last.2, COUNT);
+
+        for i in 0..COUNT {
+            assert_eq!(index(&name(i)), Some(i), "{}", name(i));
+        }
+
+        assert_eq!(name(PASSED + 3), "passed[3]");
+        assert_eq!(name(BISHOP_PAIR), "bishop_pair");
+        assert_eq!(index("passed[8]"), None);
+        assert_eq!(index("nonsense"), None);
+    }
+
+    #[test]
+    fn params_defaults_match_constants() {
+        let p = Params::default();
+        let wq = Piece::from(Type::QUEEN, Color::WHITE);
+
+        assert_eq!(p.values[PASSED + 6], pawns::PASSED[6]);
+        assert_eq!(p.values[TRAPPED_ROOK], mobility::TRAPPED_ROOK);
+        assert_eq!(
+            p.piece_square(wq, 3).mg,
+            eval::MG_MATERIAL[Type::QUEEN as usize] + eval::MG_PST[Type::QUEEN as usize][3 ^ 56]
+        );
+    }
+
+    #[test]
+    fn params_file_roundtrips() {
+        let path = std::env::temp_dir().join("nc_params_roundtrip.txt");
+        let path = path.to_str().unwrap();
+        let mut p = Params::default();
+
+        p.values[ISOLATED] = Score::new(-3, -7);
+        p.save(path).unwrap();
+        assert_eq!(Params::load(path).unwrap(), p);
+
+        fs::write(path, "# partial\nbishop_pair 1 2\n\n").unwrap();
+        assert_eq!(
+            Params::load(path).unwrap().values[BISHOP_PAIR],
+            Score::new(1, 2)
+        );
+
+        fs::write(path, "bishop_pair 1\n").unwrap();
+        assert!(Params::load(path).is_err());
+
+        fs::write(path, "queen_pst[64] 1 2\n").unwrap();
+        assert!(Params::load(path).is_err());
+
+        fs::remove_file(path).unwrap();
+    }
+}
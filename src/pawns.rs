@@ -0,0 +1,313 @@
+use crate::bitboard;
+use crate::board::*;
+use crate::eval::Score;
+use crate::params::{self, Accumulator, Params, TermGroup};
+use crate::piece::*;
+use crate::square::*;
+
+pub const DEFAULT_ENTRIES: usize = 1 << 14;
+
+/* Default weights; bonuses are indexed by the pawn's rank from its owner's side */
+pub const PASSED: [Score; 8] = [
+    Score { mg: 0, eg: 0 },
+    Score { mg: 5, eg: 10 },
+    Score { mg: 5, eg: 15 },
+    Score { mg: 10, eg: 25 },
+    Score { mg: 20, eg: 45 },
+    Score { mg: 35, eg: 75 },
+    Score { mg: 60, eg: 120 },
+    Score { mg: 0, eg: 0 },
+];
+
+pub const CONNECTED: [Score; 8] = [
+    Score { mg: 0, eg: 0 },
+    Score { mg: 3, eg: 2 },
+    Score { mg: 6, eg: 4 },
+    Score { mg: 10, eg: 7 },
+    Score { mg: 16, eg: 12 },
+    Score { mg: 28, eg: 24 },
+    Score { mg: 45, eg: 40 },
+    Score { mg: 0, eg: 0 },
+];
+
+/* Penalties per pawn, or per island */
+pub const ISOLATED: Score = Score { mg: -10, eg: -15 };
+pub const DOUBLED: Score = Score { mg: -10, eg: -25 };
+pub const BACKWARD: Score = Score { mg: -8, eg: -10 };
+pub const ISLAND: Score = Score { mg: -5, eg: -10 };
+
+/* Pawns matching each term, per colour */
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Terms {
+    pub passed: [u64; 2],
+    pub isolated: [u64; 2],
+    pub doubled: [u64; 2], /* Pawns with a friendly pawn in front on the same file */
+    pub backward: [u64; 2],
+    pub connected: [u64; 2], /* Pawns defended by or beside a friendly pawn */
+    pub islands: [u32; 2],
+}
+
+fn relative_rank(c: Color, s: Square) -> usize {
+    match c {
+        Color::WHITE => s.rank(),
+        Color::BLACK => 7 - s.rank(),
+    }
+}
+
+impl Terms {
+    pub fn new(b: &Board) -> Terms {
+        let mut t = Terms::default();
+        let pawns = b.piece_occ(Type::PAWN);
+
+        for c in [Color::WHITE, Color::BLACK].iter() {
+            let c = *c;
+            let ours = pawns & b.color_occ(c);
+            let theirs = pawns & b.color_occ(c.flip());
+            let defended = bitboard::pawn_attacks(c, ours);
+            let guarded = bitboard::pawn_attacks(c.flip(), theirs);
+            let mut files = 0u32;
+
+            bitboard::for_each(ours, |s| {
+                let ahead = bitboard::forward_ranks(c, s.rank());
+                let file = bitboard::FILES[s.file()];
+                let adjacent = bitboard::adjacent_files(s.file());
+                let mask = s.mask();
+
+                files |= 1 << s.file();
+
+                if bitboard::passed_span(c, s) & theirs == 0 && ahead & file & ours == 0 {
+                    t.passed[c as usize] |= mask;
+                }
+
+                if ours & adjacent == 0 {
+                    t.isolated[c as usize] |= mask;
+                } else if ours & adjacent & !ahead == 0 {
+                    /* No pawn can come up to support it, and its stop square is guarded */
+                    let stop = match c {
+                        Color::WHITE => mask << 8,
+                        Color::BLACK => mask >> 8,
+                    };
+
+                    if stop & guarded != 0 {
+                        t.backward[c as usize] |= mask;
+                    }
+                }
+
+                if ahead & file & ours != 0 {
+                    t.doubled[c as usize] |= mask;
+                }
+
+                let phalanx = adjacent & bitboard::RANKS[s.rank()] & ours;
+
+                if defended & mask != 0 || phalanx != 0 {
+                    t.connected[c as usize] |= mask;
+                }
+            });
+
+            t.islands[c as usize] = (files & !(files << 1)).count_ones();
+        }
+
+        t
+    }
+}
+
+/* Passed, connected, isolated, doubled and backward pawns and pawn islands */
+impl TermGroup for Terms {
+    fn accumulate<A: Accumulator>(&self, acc: &mut A) {
+        for (c, sign) in [(Color::WHITE, 1), (Color::BLACK, -1)].iter() {
+            let i = *c as usize;
+
+            bitboard::for_each(self.passed[i], |s| {
+                acc.add(params::PASSED + relative_rank(*c, s), *sign)
+            });
+            bitboard::for_each(self.connected[i], |s| {
+                acc.add(params::CONNECTED + relative_rank(*c, s), *sign)
+            });
+
+            acc.add(
+                params::ISOLATED,
+                sign * self.isolated[i].count_ones() as i32,
+            );
+            acc.add(params::DOUBLED, sign * self.doubled[i].count_ones() as i32);
+            acc.add(
+                params::BACKWARD,
+                sign * self.backward[i].count_ones() as i32,
+            );
+            acc.add(params::ISLAND, sign * self.islands[i] as i32);
+        }
+    }
+}
+
+#[derive(Copy, Clone, Default)]
+struct Entry {
+    key: u64,
+    score: Score,
+    valid: bool,
+}
+
+/* Caches pawn structure scores by the board's pawn key */
+pub struct PawnTable {
+    entries: Vec<Entry>,
+    generation: usize, /* Parameters the cached scores were computed with */
+    probes: u64,
+    hits: u64,
+}
+
+impl PawnTable {
+    /* Creates a table with `entries` rounded up to a power of two */
+    pub fn new(entries: usize) -> PawnTable {
+        PawnTable {
+            entries: vec![Entry::default(); entries.max(1).next_power_of_two()],
+            generation: 0,
+            probes: 0,
+            hits: 0,
+        }
+    }
+
+    /* Pawn structure score from white's perspective, computed on a miss */
+    pub fn probe(&mut self, b: &Board, params: &Params) -> Score {
+        if params.generation != self.generation {
+            self.entries.iter_mut().for_each(|e| e.valid = false);
+            self.generation = params.generation;
+        }
+
+        let key = b.pawn_key();
+        let idx = key as usize & (self.entries.len() - 1);
+        let e = &mut self.entries[idx];
+
+        self.probes += 1;
+
+        if e.valid && e.key == key {
+            self.hits += 1;
+            return e.score;
+        }
+
+        *e = Entry {
+            key,
+            score: Terms::new(b).score(params),
+            valid: true,
+        };
+
+        e.score
+    }
+
+    pub fn probes(&self) -> u64 {
+        self.probes
+    }
+
+    pub fn hits(&self) -> u64 {
+        self.hits
+    }
+
+    pub fn hit_rate(&self) -> f64 {
+        self.hits as f64 / self.probes.max(1) as f64
+    }
+}
+
+impl Default for PawnTable {
+    fn default() -> PawnTable {
+        PawnTable::new(DEFAULT_ENTRIES)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gmove::*;
+    use crate::position::*;
+
+    fn terms(fen: &str) -> Terms {
+        Terms::new(Position::new(fen.to_string()).unwrap().board())
+    }
+
+    fn squares(names: &[&str]) -> u64 {
+        names
+            .iter()
+            .map(|n| Square::from_uci(&n.to_string()).unwrap().mask())
+            .fold(0, |a, b| a | b)
+    }
+
+    #[test]
+    fn pawns_passed_works() {
+        let t = terms("4k3/8/2p5/8/1P2P2P/8/P7/4K3 w - - 0 1");
+
+        /* b4 and c6 each guard the other's path */
+        assert_eq!(t.passed[0], squares(&["a2", "e4", "h4"]));
+        assert_eq!(t.passed[1], 0);
+
+        let t = terms("4k3/8/8/8/8/8/p7/4K3 b - - 0 1");
+        assert_eq!(t.passed[1], squares(&["a2"]));
+    }
+
+    #[test]
+    fn pawns_isolated_and_doubled_work() {
+        let t = terms("4k3/8/8/8/4P3/4P3/P1P5/4K3 w - - 0 1");
+
+        assert_eq!(t.isolated[0], squares(&["a2", "c2", "e3", "e4"]));
+        assert_eq!(t.doubled[0], squares(&["e3"]));
+        assert_eq!(t.islands[0], 3);
+    }
+
+    #[test]
+    fn pawns_backward_works() {
+        /* d3 cannot be supported by c4/e4 and d4 is covered by e5 */
+        let t = terms("4k3/8/8/4p3/2P1P3/3P4/8/4K3 w - - 0 1");
+
+        assert_eq!(t.backward[0], squares(&["d3"]));
+        assert_eq!(t.backward[1], 0);
+    }
+
+    #[test]
+    fn pawns_connected_works() {
+        let t = terms("4k3/8/8/8/3PP3/5P2/P7/4K3 w - - 0 1");
+
+        assert_eq!(t.connected[0], squares(&["d4", "e4"]));
+        assert_eq!(t.islands[0], 2);
+
+        let t = terms("4k3/8/8/8/4P3/5P2/8/4K3 w - - 0 1");
+        assert_eq!(t.connected[0], squares(&["e4"]));
+    }
+
+    #[test]
+    fn pawns_score_works() {
+        let params = Params::default();
+        let t = terms("4k3/8/8/8/8/8/P7/4K3 w - - 0 1");
+
+        assert_eq!(t.score(&params), PASSED[1] + ISOLATED + ISLAND);
+    }
+
+    #[test]
+    fn pawns_table_caches_scores() {
+        let mut p = Position::new(STARTPOS.to_string()).unwrap();
+        let mut table = PawnTable::new(1000);
+        let mut params = Params::default();
+        let start = table.probe(p.board(), &params);
+
+        assert_eq!(table.entries.len(), 1024);
+        assert_eq!(start, Score::default());
+        assert_eq!(table.probe(p.board(), &params), start);
+        assert_eq!((table.probes(), table.hits()), (2, 1));
+
+        /* Piece moves keep the pawn key; pawn moves change it */
+        let nf3 = Move::from_uci(&"g1f3".to_string()).unwrap();
+        let key = p.board().pawn_key();
+
+        p.make_move(nf3);
+        assert_eq!(p.board().pawn_key(), key);
+
+        p.make_move(Move::from_uci(&"e7e5".to_string()).unwrap());
+        assert_ne!(p.board().pawn_key(), key);
+
+        assert_eq!(
+            table.probe(p.board(), &params),
+            Terms::new(p.board()).score(&params)
+        );
+        assert_eq!(table.hits(), 1);
+        assert!((table.hit_rate() - 1.0 / 3.0).abs() < 1e-9);
+
+        /* New parameters invalidate every cached score */
+        params.generation += 1;
+        table.probe(p.board(), &params);
+        assert_eq!(table.hits(), 1);
+    }
+}
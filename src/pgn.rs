@@ -0,0 +1,769 @@
+use crate::encode;
+use crate::gmove::*;
+use crate::piece::*;
+use crate::policy;
+use crate::position::*;
+use crate::record::*;
+use crate::square::*;
+
+use std::collections::HashSet;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+
+/* Formats a legal move in standard algebraic notation */
+pub fn to_san(p: &mut Position, m: Move) -> String {
+    let piece = p.board().piece_at(m.src()).unwrap().get_type();
+    let capture = p.is_capture(m);
+    let mut out = String::new();
+
+    if piece == Type::KING && (m.src().file() as i32 - m.dst().file() as i32).abs() == 2 {
+        out.push_str(if m.dst().file() == 6 { "O-O" } else { "O-O-O" });
+    } else if piece == Type::PAWN {
+        if capture {
+            out.push((b'a' + m.src().file() as u8) as char);
+            out.push('x');
+        }
+
+        out.push_str(&m.dst().to_str());
+
+        if let Some(t) = m.ptype() {
+            out.push('=');
+            out.push(t.to_fen().to_ascii_uppercase());
+        }
+    } else {
+        out.push(piece.to_fen().to_ascii_uppercase());
+
+        /* Disambiguate between pieces of the same type reaching the same square */
+        let others: Vec<Move> = p
+            .gen_legal_moves()
+            .into_iter()
+            .filter(|o| {
+                o.dst() == m.dst()
+                    && o.src() != m.src()
+                    && p.board().piece_at(o.src()).map(|x| x.get_type()) == Some(piece)
+            })
+            .collect();
+
+        if !others.is_empty() {
+            let src = m.src().to_str();
+
+            if others.iter().all(|o| o.src().file() != m.src().file()) {
+                out.push_str(&src[0..1]);
+            } else if others.iter().all(|o| o.src().rank() != m.src().rank()) {
+                out.push_str(&src[1..2]);
+            } else {
+                out.push_str(&src);
+            }
+        }
+
+        if capture {
+            out.push('x');
+        }
+
+        out.push_str(&m.dst().to_str());
+    }
+
+    p.make_move(m);
+
+    if p.in_check() {
+        out.push(if p.gen_legal_moves().is_empty() {
+            '#'
+        } else {
+            '+'
+        });
+    }
+
+    p.unmake_move(m);
+
+    out
+}
+
+/*
+ * Finds the legal move matching a SAN token, ignoring check marks and
+ * annotations. The token is split into piece, disambiguation, destination
+ * and promotion and matched against the legal moves in one pass; castling
+ * may be written with zeros and promotions without the '='.
+ */
+pub fn parse_san(p: &mut Position, san: &str) -> Option<Move> {
+    let san = san.trim_end_matches(['+', '#', '!', '?']);
+    let legal = p.gen_legal_moves();
+    let b = p.board();
+    let piece_type = |m: &Move| b.piece_at(m.src()).map(|pc| pc.get_type());
+    let castles = |m: &Move| {
+        piece_type(m) == Some(Type::KING)
+            && (m.src().file() as i32 - m.dst().file() as i32).abs() == 2
+    };
+
+    let castle = match san {
+        "O-O" | "0-0" => Some(6),
+        "O-O-O" | "0-0-0" => Some(2),
+        _ => None,
+    };
+
+    if let Some(file) = castle {
+        return legal
+            .into_iter()
+            .find(|m| castles(m) && m.dst().file() == file);
+    }
+
+    let mut chars: Vec<char> = san.chars().filter(|c| *c != 'x' && *c != '=').collect();
+
+    let piece = match chars.first() {
+        Some(c) if c.is_ascii_uppercase() => {
+            let t = Type::from_fen(c.to_ascii_lowercase())?;
+            chars.remove(0);
+            t
+        }
+        _ => Type::PAWN,
+    };
+
+    let promotion = match chars.last() {
+        Some(c) if piece == Type::PAWN && c.is_ascii_uppercase() => {
+            let t = Type::from_fen(c.to_ascii_lowercase())?;
+            chars.pop();
+            Some(t)
+        }
+        _ => None,
+    };
+
+    if chars.len() < 2 || chars.len() > 4 {
+        return None;
+    }
+
+    let dst: String = chars.split_off(chars.len() - 2).into_iter().collect();
+    let dst = Square::from_uci(&dst)?;
+
+    let mut file = None;
+    let mut rank = None;
+
+    for c in chars {
+        match c {
+            'a'..='h' if file.is_none() => file = Some(c as usize - 'a' as usize),
+            '1'..='8' if rank.is_none() => rank = Some(c as usize - '1' as usize),
+            _ => return None,
+        }
+    }
+
+    let mut found = legal.into_iter().filter(|m| {
+        m.dst() == dst
+            && m.ptype() == promotion
+            && piece_type(m) == Some(piece)
+            && file.is_none_or(|f| m.src().file() == f)
+            && rank.is_none_or(|r| m.src().rank() == r)
+            && !castles(m)
+    });
+
+    /* An ambiguous token matches nothing */
+    match (found.next(), found.next()) {
+        (Some(m), None) => Some(m),
+        _ => None,
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Game {
+    pub tags: Vec<(String, String)>,
+    pub moves: Vec<String>, /* SAN tokens */
+    pub result: String,
+}
+
+impl Game {
+    pub fn tag(&self, name: &str) -> Option<&str> {
+        self.tags
+            .iter()
+            .find(|(k, _)| k == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /* Final result from white's perspective, if the game finished */
+    pub fn score(&self) -> Option<f32> {
+        let result = self.tag("Result").unwrap_or(&self.result);
+
+        match result {
+            "1-0" => Some(1.0),
+            "0-1" => Some(-1.0),
+            "1/2-1/2" => Some(0.0),
+            _ => None,
+        }
+    }
+}
+
+/* Writes the game as PGN, wrapping movetext at 80 columns */
+impl fmt::Display for Game {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (k, v) in self.tags.iter() {
+            writeln!(f, "[{} \"{}\"]", k, v.replace('"', "\\\""))?;
+        }
+
+        /* Move numbering follows the starting position, if one is given */
+        let fen: Vec<&str> = self.tag("FEN").unwrap_or(STARTPOS).split(' ').collect();
+        let black = fen.get(1) == Some(&"b");
+        let first = fen
+            .get(5)
+            .and_then(|n| n.parse::<usize>().ok())
+            .unwrap_or(1);
+
+        let mut tokens = Vec::new();
+
+        for (i, san) in self.moves.iter().enumerate() {
+            let ply = i + black as usize;
+
+            if ply.is_multiple_of(2) {
+                tokens.push(format!("{}.", first + ply / 2));
+            } else if i == 0 {
+                tokens.push(format!("{}...", first));
+            }
+
+            tokens.push(san.clone());
+        }
+
+        tokens.push(self.result.clone());
+
+        let mut line = String::new();
+
+        writeln!(f)?;
+
+        for t in tokens {
+            if !line.is_empty() && line.len() + t.len() >= 80 {
+                writeln!(f, "{}", line)?;
+                line.clear();
+            }
+
+            if !line.is_empty() {
+                line.push(' ');
+            }
+
+            line.push_str(&t);
+        }
+
+        writeln!(f, "{}", line)
+    }
+}
+
+fn parse_tag(line: &str) -> Option<(String, String)> {
+    let inner = line.trim().strip_prefix('[')?.strip_suffix(']')?;
+    let (name, value) = inner.split_once(' ')?;
+
+    Some((
+        name.to_string(),
+        value.trim().trim_matches('"').replace("\\\"", "\""),
+    ))
+}
+
+/* Extracts SAN tokens from movetext, skipping comments, variations and NAGs */
+fn parse_movetext(text: &str, game: &mut Game) {
+    let mut depth = 0;
+    let mut comment = false;
+    let mut token = String::new();
+
+    let flush = |token: &mut String, game: &mut Game| {
+        /* Drop a move number prefix such as '12.' or '12...', but not the digits of '0-0' */
+        let t = match token.find('.') {
+            Some(i) if token[..i].chars().all(|c| c.is_ascii_digit()) => {
+                token[i..].trim_start_matches('.')
+            }
+            _ => token.as_str(),
+        };
+
+        if ["1-0", "0-1", "1/2-1/2", "*"].contains(&token.as_str()) {
+            game.result = token.clone();
+        } else if !t.is_empty() && !t.starts_with('$') {
+            game.moves.push(t.to_string());
+        }
+
+        token.clear();
+    };
+
+    for line in text.lines() {
+        if line.starts_with('%') {
+            continue;
+        }
+
+        for c in line.chars() {
+            if comment {
+                comment = c != '}';
+                continue;
+            }
+
+            match c {
+                '{' => comment = true,
+                ';' => break,
+                '(' => depth += 1,
+                ')' => depth -= 1,
+                _ if depth > 0 => (),
+                c if c.is_whitespace() => flush(&mut token, game),
+                c => token.push(c),
+            }
+        }
+
+        flush(&mut token, game);
+    }
+}
+
+/* Reads games one at a time from a PGN stream */
+pub struct Games<R: BufRead> {
+    input: R,
+    pending: Option<String>,
+}
+
+impl<R: BufRead> Games<R> {
+    pub fn new(input: R) -> Games<R> {
+        Games {
+            input,
+            pending: None,
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for Games<R> {
+    type Item = Game;
+
+    fn next(&mut self) -> Option<Game> {
+        let mut game = Game::default();
+        let mut movetext = String::new();
+        let mut line = self.pending.take().unwrap_or_default();
+
+        loop {
+            if line.is_empty() {
+                match self.input.read_line(&mut line) {
+                    Ok(0) | Err(_) => break,
+                    Ok(_) => (),
+                }
+            }
+
+            if line.trim_start().starts_with('[') {
+                /* A tag after movetext starts the next game */
+                if !movetext.trim().is_empty() {
+                    self.pending = Some(line);
+                    break;
+                }
+
+                if let Some(t) = parse_tag(&line) {
+                    game.tags.push(t);
+                }
+            } else {
+                movetext.push_str(&line);
+            }
+
+            line.clear();
+        }
+
+        if game.tags.is_empty() && movetext.trim().is_empty() {
+            return None;
+        }
+
+        parse_movetext(&movetext, &mut game);
+        Some(game)
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Filter {
+    pub min_rating: u32,  /* Both players, when nonzero */
+    pub min_seconds: u64, /* Estimated game length: base + 40 * increment */
+    pub skip_plies: usize,
+}
+
+impl Filter {
+    pub fn accepts(&self, g: &Game) -> bool {
+        if self.min_rating > 0 {
+            for tag in ["WhiteElo", "BlackElo"].iter() {
+                match g.tag(tag).and_then(|r| r.parse::<u32>().ok()) {
+                    Some(r) if r >= self.min_rating => (),
+                    _ => return false,
+                }
+            }
+        }
+
+        if self.min_seconds > 0 {
+            let tc = g.tag("TimeControl").unwrap_or("-");
+            let (base, inc) = tc.split_once('+').unwrap_or((tc, "0"));
+
+            match (base.parse::<u64>(), inc.parse::<u64>()) {
+                (Ok(b), Ok(i)) if b + 40 * i >= self.min_seconds => (),
+                _ => return false,
+            }
+        }
+
+        true
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Stats {
+    pub games: usize,
+    pub filtered: usize, /* Rejected by the filter or unfinished */
+    pub invalid: usize,  /* Bad FEN or illegal moves */
+    pub samples: usize,
+    pub duplicates: usize,
+}
+
+/* Turns games into training samples, skipping positions already emitted */
+pub struct Extractor {
+    filter: Filter,
+    seen: HashSet<u64>,
+    pub stats: Stats,
+}
+
+impl Extractor {
+    pub fn new(filter: Filter) -> Extractor {
+        Extractor {
+            filter,
+            seen: HashSet::new(),
+            stats: Stats::default(),
+        }
+    }
+
+    pub fn extract(&mut self, g: &Game) -> Vec<Sample> {
+        self.stats.games += 1;
+
+        let score = match g.score() {
+            Some(s) if self.filter.accepts(g) => s,
+            _ => {
+                self.stats.filtered += 1;
+                return Vec::new();
+            }
+        };
+
+        let fen = g.tag("FEN").unwrap_or(STARTPOS);
+        let mut p = match Position::new(fen.to_string()) {
+            Some(p) => p,
+            None => {
+                self.stats.invalid += 1;
+                return Vec::new();
+            }
+        };
+
+        /* Replay the whole game first so an illegal move leaves `seen` untouched */
+        let mut positions = Vec::new();
+
+        for (ply, san) in g.moves.iter().enumerate() {
+            let m = match parse_san(&mut p, san) {
+                Some(m) => m,
+                None => {
+                    warn!("Illegal move '{}' at ply {}, dropping game", san, ply);
+                    self.stats.invalid += 1;
+                    return Vec::new();
+                }
+            };
+
+            if ply >= self.filter.skip_plies {
+                let result = match p.ctm() {
+                    Color::WHITE => score,
+                    Color::BLACK => -score,
+                };
+
+                positions.push((
+                    p.key(),
+                    Sample {
+                        planes: encode::encode(&p),
+                        policy: vec![(policy::encode(p.ctm(), m).unwrap() as u16, 1.0)],
+                        result,
+                    },
+                ));
+            }
+
+            p.make_move(m);
+        }
+
+        let mut out = Vec::new();
+
+        for (key, sample) in positions {
+            if self.seen.insert(key) {
+                out.push(sample);
+            } else {
+                self.stats.duplicates += 1;
+            }
+        }
+
+        self.stats.samples += out.len();
+        out
+    }
+}
+
+/* Extracts every game in `input` into a record file at `output` */
+pub fn extract_file(input: &str, output: &str, filter: Filter) -> io::Result<Stats> {
+    let games = Games::new(BufReader::new(File::open(input)?));
+    let mut out = Writer::create(output, true)?;
+    let mut ex = Extractor::new(filter);
+
+    for g in games {
+        for s in ex.extract(&g) {
+            out.write(&s)?;
+        }
+    }
+
+    out.finish()?;
+
+    Ok(ex.stats)
+}
+
+/* Entry point for `nc pgn <input.pgn> <output.rec> [min_rating] [min_seconds] [skip_plies]` */
+pub fn cli(args: &[String]) {
+    let nums: Vec<Option<u64>> = args.iter().skip(2).map(|a| a.parse().ok()).collect();
+
+    if args.len() < 2 || args.len() > 5 || nums.contains(&None) {
+        error!("usage: nc pgn <input.pgn> <output.rec> [min_rating] [min_seconds] [skip_plies]");
+        return;
+    }
+
+    let arg = |i: usize| nums.get(i).cloned().flatten().unwrap_or(0);
+    let filter = Filter {
+        min_rating: arg(0) as u32,
+        min_seconds: arg(1),
+        skip_plies: arg(2) as usize,
+    };
+
+    match extract_file(&args[0], &args[1], filter) {
+        Ok(s) => info!("Extraction finished: {:?}", s),
+        Err(e) => error!("Extraction failed: {}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PGN: &str = r#"[Event "Test"]
+[White "A"]
+[Black "B"]
+[WhiteElo "2400"]
+[BlackElo "2300"]
+[TimeControl "180+2"]
+[Result "1-0"]
+
+1. e4 e5 2. Nf3 {a comment} Nc6 3. Bb5 (3. Bc4 Bc5) a6 $1 4. Ba4 Nf6
+5. O-O Be7 1-0
+
+[Event "Test 2"]
+[WhiteElo "1500"]
+[BlackElo "2300"]
+[Result "1/2-1/2"]
+
+1. e4 e5 ; rest of line
+2. Nf3 Nc6 1/2-1/2
+
+[Event "Unfinished"]
+[Result "*"]
+
+1. d4 *
+"#;
+
+    fn games() -> Vec<Game> {
+        Games::new(PGN.as_bytes()).collect()
+    }
+
+    #[test]
+    fn pgn_games_parse() {
+        let g = games();
+
+        assert_eq!(g.len(), 3);
+        assert_eq!(g[0].tag("White"), Some("A"));
+        assert_eq!(
+            g[0].moves,
+            vec!["e4", "e5", "Nf3", "Nc6", "Bb5", "a6", "Ba4", "Nf6", "O-O", "Be7"]
+        );
+        assert_eq!(g[0].score(), Some(1.0));
+        assert_eq!(g[1].moves, vec!["e4", "e5", "Nf3", "Nc6"]);
+        assert_eq!(g[1].score(), Some(0.0));
+        assert_eq!(g[2].score(), None);
+    }
+
+    #[test]
+    fn pgn_writing_roundtrips() {
+        for g in games() {
+            let text = g.to_string();
+            assert_eq!(Games::new(text.as_bytes()).collect::<Vec<_>>(), vec![g]);
+        }
+
+        let g = Game {
+            tags: vec![(
+                "FEN".to_string(),
+                "6k1/8/8/8/8/8/8/R5K1 b - - 0 40".to_string(),
+            )],
+            moves: vec!["Kh7".to_string(), "Ra7+".to_string()],
+            result: "*".to_string(),
+        };
+
+        assert!(g.to_string().ends_with("\n40... Kh7 41. Ra7+ *\n"));
+    }
+
+    #[test]
+    fn pgn_san_roundtrip_works() {
+        let mut p = Position::new(
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1".to_string(),
+        )
+        .unwrap();
+
+        for m in p.gen_legal_moves() {
+            let san = to_san(&mut p, m);
+            assert_eq!(parse_san(&mut p, &san), Some(m), "{}", san);
+        }
+
+        let mv = |s: &str| Move::from_uci(&s.to_string()).unwrap();
+
+        assert_eq!(to_san(&mut p, mv("e1g1")), "O-O");
+        assert_eq!(to_san(&mut p, mv("e1c1")), "O-O-O");
+        assert_eq!(to_san(&mut p, mv("e5f7")), "Nxf7");
+        assert_eq!(to_san(&mut p, mv("d5e6")), "dxe6");
+        assert_eq!(to_san(&mut p, mv("c3b1")), "Nb1");
+
+        /* Rooks on a1 and e1 both reach d1 */
+        let mut p = Position::new("7k/8/8/8/8/8/8/R3R1K1 w - - 0 1".to_string()).unwrap();
+        assert_eq!(to_san(&mut p, mv("a1d1")), "Rad1");
+
+        let mut p = Position::new("6k1/5ppp/8/8/8/8/1p6/R5K1 b - - 0 1".to_string()).unwrap();
+        assert_eq!(to_san(&mut p, mv("b2a1q")), "bxa1=Q+");
+        assert_eq!(parse_san(&mut p, "bxa1=N"), Some(mv("b2a1n")));
+
+        let mut p = Position::new("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1".to_string()).unwrap();
+        assert_eq!(to_san(&mut p, mv("a1a8")), "Ra8#");
+    }
+
+    #[test]
+    fn pgn_san_parsing_works() {
+        let mv = |s: &str| Move::from_uci(&s.to_string()).unwrap();
+        let mut p = Position::new(
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1".to_string(),
+        )
+        .unwrap();
+
+        /* Zero-written castling, captures without 'x' and long disambiguation */
+        assert_eq!(parse_san(&mut p, "0-0"), Some(mv("e1g1")));
+        assert_eq!(parse_san(&mut p, "0-0-0+"), Some(mv("e1c1")));
+        assert_eq!(parse_san(&mut p, "Nf7"), Some(mv("e5f7")));
+        assert_eq!(parse_san(&mut p, "Ne5f7!?"), Some(mv("e5f7")));
+        assert_eq!(parse_san(&mut p, "Kg1"), None);
+
+        /* Two knights reach d3, so the bare piece letter is ambiguous */
+        let mut p = Position::new("7k/8/8/8/1N6/8/5N2/6K1 w - - 0 1".to_string()).unwrap();
+        assert_eq!(parse_san(&mut p, "Nd3"), None);
+        assert_eq!(parse_san(&mut p, "Nbd3"), Some(mv("b4d3")));
+        assert_eq!(parse_san(&mut p, "N2d3"), Some(mv("f2d3")));
+
+        /* Promotions need a piece, with or without '=' */
+        let mut p = Position::new("6k1/5ppp/8/8/8/8/1p6/R5K1 b - - 0 1".to_string()).unwrap();
+        assert_eq!(parse_san(&mut p, "bxa1Q"), Some(mv("b2a1q")));
+        assert_eq!(parse_san(&mut p, "b1=R"), Some(mv("b2b1r")));
+        assert_eq!(parse_san(&mut p, "b1"), None);
+        assert_eq!(parse_san(&mut p, "Zb1"), None);
+        assert_eq!(parse_san(&mut p, "b9"), None);
+    }
+
+    #[test]
+    fn pgn_zero_castling_is_extracted() {
+        let text = "[Result \"*\"]\n\n1. e4 e5 2. Nf3 Nc6 3. Bc4 Bc5 4. 0-0 Nf6 5.d3 0-0 *\n";
+        let g: Vec<Game> = Games::new(text.as_bytes()).collect();
+
+        assert_eq!(g[0].moves[6..], ["0-0", "Nf6", "d3", "0-0"]);
+
+        let mut p = Position::new(STARTPOS.to_string()).unwrap();
+
+        for san in g[0].moves.iter() {
+            let m = parse_san(&mut p, san).unwrap();
+            p.make_move(m);
+        }
+
+        assert!(p
+            .to_fen()
+            .starts_with("r1bq1rk1/pppp1ppp/2n2n2/2b1p3/2B1P3/3P1N2/PPP2PPP/RNBQ1RK1 w"));
+    }
+
+    #[test]
+    fn pgn_filters_work() {
+        let g = games();
+        let f = |min_rating, min_seconds| Filter {
+            min_rating,
+            min_seconds,
+            skip_plies: 0,
+        };
+
+        assert!(f(2000, 0).accepts(&g[0]));
+        assert!(!f(2000, 0).accepts(&g[1]));
+        assert!(f(0, 260).accepts(&g[0]));
+        assert!(!f(0, 261).accepts(&g[0]));
+        assert!(!f(0, 1).accepts(&g[1]));
+    }
+
+    #[test]
+    fn pgn_extraction_works() {
+        let mut ex = Extractor::new(Filter {
+            skip_plies: 2,
+            ..Filter::default()
+        });
+
+        let g = games();
+        let a = ex.extract(&g[0]);
+
+        /* Ten plies, two skipped; white won */
+        assert_eq!(a.len(), 8);
+        assert_eq!(a[0].result, 1.0);
+        assert_eq!(a[1].result, -1.0);
+
+        let nf3 = Move::from_uci(&"g1f3".to_string()).unwrap();
+        let i = policy::encode(Color::WHITE, nf3).unwrap() as u16;
+        assert_eq!(a[0].policy, vec![(i, 1.0)]);
+
+        /* Game 2 only repeats positions already seen, game 3 is unfinished */
+        assert!(ex.extract(&g[1]).is_empty());
+        assert!(ex.extract(&g[2]).is_empty());
+
+        assert_eq!(
+            ex.stats,
+            Stats {
+                games: 3,
+                filtered: 1,
+                invalid: 0,
+                samples: 8,
+                duplicates: 2,
+            }
+        );
+    }
+
+    #[test]
+    fn pgn_illegal_game_does_not_mark_positions_seen() {
+        let mut ex = Extractor::new(Filter::default());
+        let game = |moves: &[&str]| Game {
+            tags: Vec::new(),
+            moves: moves.iter().map(|m| m.to_string()).collect(),
+            result: "1-0".to_string(),
+        };
+
+        /* Shares its first two plies with the valid game, then goes wrong */
+        assert!(ex.extract(&game(&["e4", "e5", "Ke3"])).is_empty());
+        assert_eq!(ex.extract(&game(&["e4", "e5", "Nf3"])).len(), 3);
+
+        assert_eq!(
+            ex.stats,
+            Stats {
+                games: 2,
+                filtered: 0,
+                invalid: 1,
+                samples: 3,
+                duplicates: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn pgn_extract_file_works() {
+        let input = std::env::temp_dir().join("nc_pgn_test.pgn");
+        let output = std::env::temp_dir().join("nc_pgn_test.rec");
+
+        std::fs::write(&input, PGN).unwrap();
+
+        let stats = extract_file(
+            input.to_str().unwrap(),
+            output.to_str().unwrap(),
+            Filter::default(),
+        )
+        .unwrap();
+
+        assert_eq!(stats.samples, 10);
+        assert_eq!(Reader::open(&output).unwrap().len(), 10);
+
+        std::fs::remove_file(input).unwrap();
+        std::fs::remove_file(output).unwrap();
+    }
+}
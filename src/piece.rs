@@ -33,6 +33,18 @@ impl Type {
             _ => None,
         }
     }
+
+    pub fn from_index(i: usize) -> Option<Type> {
+        match i {
+            0 => Some(Type::PAWN),
+            1 => Some(Type::BISHOP),
+            2 => Some(Type::KNIGHT),
+            3 => Some(Type::ROOK),
+            4 => Some(Type::QUEEN),
+            5 => Some(Type::KING),
+            _ => None,
+        }
+    }
 }
 
 /* Piece colors */
@@ -158,6 +170,15 @@ mod tests {
         assert_eq!(Type::from_fen('a'), None);
     }
 
+    #[test]
+    fn type_from_index_works() {
+        for i in 0..6 {
+            assert_eq!(Type::from_index(i).unwrap() as usize, i);
+        }
+
+        assert_eq!(Type::from_index(6), None);
+    }
+
     #[test]
     fn piece_to_fen_works() {
         assert_eq!(Piece::from(Type::PAWN, Color::WHITE).to_fen(), 'P');
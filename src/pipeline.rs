@@ -0,0 +1,385 @@
+use crate::eval::Classical;
+use crate::gate;
+use crate::mcts::Evaluator;
+use crate::nn::*;
+use crate::position::*;
+use crate::record;
+use crate::selfplay;
+use crate::train;
+
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+
+/*
+ * A pipeline directory holds everything needed to resume:
+ *
+ *   records/NNNNNN.rec   self-play games of iteration N
+ *   nets/NNNNNN.nn       network trained in iteration N - 1
+ *   games/NNNNNN.pgn     gating games of network N
+ *   state                last completed iteration and best generation
+ *   pipeline.log         one line per step
+ *
+ * Generation 0 is the classical evaluator used before any network exists.
+ * Files are written under a temporary name and renamed once complete, so an
+ * interrupted step is simply redone.
+ */
+#[derive(Copy, Clone, Debug)]
+pub struct Config {
+    pub iterations: usize,
+    pub games: usize, /* Self-play games per iteration */
+    pub threads: usize,
+    pub shape: Shape,
+    pub selfplay: selfplay::Config,
+    pub train: train::Config,
+    pub gate: gate::Config,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            iterations: 100,
+            games: 500,
+            threads: thread::available_parallelism().map_or(1, |n| n.get()),
+            shape: Shape::default(),
+            selfplay: selfplay::Config::default(),
+            train: train::Config::default(),
+            gate: gate::Config::default(),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct State {
+    pub iteration: usize, /* Iterations completed */
+    pub best: usize,      /* Generation of the best network */
+}
+
+impl State {
+    fn load(dir: &Path) -> io::Result<State> {
+        let text = match fs::read_to_string(dir.join("state")) {
+            Ok(t) => t,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(State::default()),
+            Err(e) => return Err(e),
+        };
+
+        let mut state = State::default();
+
+        for line in text.lines() {
+            let value = |v: &str| {
+                v.trim().parse::<usize>().map_err(|_| {
+                    io::Error::new(io::ErrorKind::InvalidData, format!("bad state '{}'", line))
+                })
+            };
+
+            match line.split_once(' ') {
+                Some(("iteration", v)) => state.iteration = value(v)?,
+                Some(("best", v)) => state.best = value(v)?,
+                _ => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("bad state '{}'", line),
+                    ))
+                }
+            }
+        }
+
+        Ok(state)
+    }
+
+    fn save(&self, dir: &Path) -> io::Result<()> {
+        let text = format!("iteration {}\nbest {}\n", self.iteration, self.best);
+        let tmp = dir.join("state.tmp");
+
+        fs::write(&tmp, text)?;
+        fs::rename(tmp, dir.join("state"))
+    }
+}
+
+fn file(dir: &Path, sub: &str, n: usize, ext: &str) -> PathBuf {
+    dir.join(sub).join(format!("{:06}.{}", n, ext))
+}
+
+fn tmp(path: &Path) -> PathBuf {
+    path.with_extension("tmp")
+}
+
+fn log(dir: &Path, msg: &str) -> io::Result<()> {
+    info!("{}", msg);
+
+    let mut f = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(dir.join("pipeline.log"))?;
+
+    writeln!(f, "{}", msg)
+}
+
+fn evaluator(dir: &Path, generation: usize) -> io::Result<Arc<dyn Evaluator>> {
+    if generation == 0 {
+        return Ok(Arc::new(Classical::new()));
+    }
+
+    let path = file(dir, "nets", generation, "nn");
+
+    Ok(Arc::new(Network::load(path.to_str().unwrap())?))
+}
+
+/* Runs one self-play, train and gate iteration, returning the new state */
+fn iteration(dir: &Path, config: &Config, state: State) -> io::Result<State> {
+    let n = state.iteration;
+    let best = evaluator(dir, state.best)?;
+
+    let records = file(dir, "records", n, record::EXTENSION);
+
+    if !records.exists() {
+        let play = selfplay::Config {
+            seed: config.selfplay.seed.wrapping_add((n * config.games) as u64),
+            ..config.selfplay
+        };
+
+        let stats = selfplay::run(
+            best.clone(),
+            &play,
+            config.games,
+            config.threads,
+            tmp(&records).to_str().unwrap(),
+        )?;
+
+        fs::rename(tmp(&records), &records)?;
+        log(
+            dir,
+            &format!(
+                "iteration {}: self-play with generation {}: {} games, {} positions, +{} ={} -{}",
+                n,
+                state.best,
+                stats.games,
+                stats.positions,
+                stats.white_wins,
+                stats.draws,
+                stats.black_wins
+            ),
+        )?;
+    }
+
+    let candidate = n + 1;
+    let net = file(dir, "nets", candidate, "nn");
+
+    if !net.exists() {
+        let input = file(dir, "nets", state.best, "nn");
+
+        train::train_dir(
+            dir.join("records").to_str().unwrap(),
+            Some(input.to_str().unwrap()).filter(|_| state.best > 0),
+            config.shape,
+            tmp(&net).to_str().unwrap(),
+            config.train,
+        )?;
+
+        fs::rename(tmp(&net), &net)?;
+        log(
+            dir,
+            &format!(
+                "iteration {}: trained generation {} for {} steps",
+                n, candidate, config.train.steps
+            ),
+        )?;
+    }
+
+    let openings: Vec<Position> = gate::OPENINGS
+        .iter()
+        .map(|o| gate::opening(o).unwrap())
+        .collect();
+
+    let pgn = file(dir, "games", candidate, "pgn");
+    let score = gate::run(
+        evaluator(dir, candidate)?,
+        best,
+        &config.gate,
+        &openings,
+        config.threads,
+        Some(pgn.to_str().unwrap()),
+    )?;
+
+    let promoted = score.passes(config.gate.threshold);
+
+    log(
+        dir,
+        &format!(
+            "iteration {}: generation {} vs {}: +{} ={} -{} ({:.1}%), {}",
+            n,
+            candidate,
+            state.best,
+            score.wins,
+            score.draws,
+            score.losses,
+            100.0 * score.score(),
+            if promoted { "promoted" } else { "rejected" }
+        ),
+    )?;
+
+    Ok(State {
+        iteration: n + 1,
+        best: if promoted { candidate } else { state.best },
+    })
+}
+
+/* Runs iterations until `config.iterations` are complete, resuming from `dir` */
+pub fn run<P: AsRef<Path>>(dir: P, config: &Config) -> io::Result<State> {
+    let dir = dir.as_ref();
+
+    for sub in ["records", "nets", "games"].iter() {
+        fs::create_dir_all(dir.join(sub))?;
+    }
+
+    let mut state = State::load(dir)?;
+
+    if state.iteration > 0 {
+        log(
+            dir,
+            &format!(
+                "resuming after iteration {} with generation {}",
+                state.iteration - 1,
+                state.best
+            ),
+        )?;
+    }
+
+    while state.iteration < config.iterations {
+        state = iteration(dir, config, state)?;
+        state.save(dir)?;
+    }
+
+    Ok(state)
+}
+
+/* Entry point for `nc pipeline <dir> [iterations]` */
+pub fn cli(args: &[String]) {
+    let iterations = args.get(1).map(|s| s.parse::<usize>());
+
+    if args.is_empty() || args.len() > 2 || iterations.as_ref().is_some_and(|i| i.is_err()) {
+        error!("usage: nc pipeline <dir> [iterations]");
+        return;
+    }
+
+    let mut config = Config::default();
+
+    if let Some(Ok(i)) = iterations {
+        config.iterations = i;
+    }
+
+    config.selfplay.seed = rand::random();
+
+    match run(&args[0], &config) {
+        Ok(s) => info!(
+            "Pipeline finished after {} iterations, best generation {}",
+            s.iteration, s.best
+        ),
+        Err(e) => error!("Pipeline failed: {}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tiny() -> Config {
+        Config {
+            iterations: 1,
+            games: 2,
+            threads: 2,
+            shape: Shape {
+                filters: 4,
+                blocks: 1,
+                policy_channels: 1,
+                value_channels: 1,
+                value_hidden: 8,
+            },
+            selfplay: selfplay::Config {
+                playouts: 8,
+                max_plies: 12,
+                ..selfplay::Config::default()
+            },
+            train: train::Config {
+                batch_size: 8,
+                steps: 2,
+                ..train::Config::default()
+            },
+            gate: gate::Config {
+                games: 2,
+                play: selfplay::Config {
+                    playouts: 8,
+                    max_plies: 12,
+                    ..gate::Config::default().play
+                },
+                ..gate::Config::default()
+            },
+        }
+    }
+
+    #[test]
+    fn pipeline_state_roundtrips() {
+        let dir = std::env::temp_dir().join("nc_pipeline_state");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir(&dir).unwrap();
+
+        assert_eq!(State::load(&dir).unwrap(), State::default());
+
+        let s = State {
+            iteration: 7,
+            best: 4,
+        };
+
+        s.save(&dir).unwrap();
+        assert_eq!(State::load(&dir).unwrap(), s);
+
+        fs::write(dir.join("state"), "iteration x\n").unwrap();
+        assert!(State::load(&dir).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn pipeline_runs_and_resumes() {
+        let dir = std::env::temp_dir().join("nc_pipeline_run");
+        let _ = fs::remove_dir_all(&dir);
+
+        let state = run(&dir, &tiny()).unwrap();
+
+        assert_eq!(state.iteration, 1);
+        assert!(file(&dir, "records", 0, record::EXTENSION).exists());
+        assert!(file(&dir, "nets", 1, "nn").exists());
+        assert!(file(&dir, "games", 1, "pgn").exists());
+
+        /* An interrupted iteration keeps its finished steps */
+        let records = file(&dir, "records", 1, record::EXTENSION);
+        fs::copy(file(&dir, "records", 0, record::EXTENSION), &records).unwrap();
+        let written = fs::metadata(&records).unwrap().modified().unwrap();
+
+        let config = Config {
+            iterations: 2,
+            ..tiny()
+        };
+
+        let state = run(&dir, &config).unwrap();
+
+        assert_eq!(state.iteration, 2);
+        assert!(state.best <= 2);
+        assert_eq!(fs::metadata(&records).unwrap().modified().unwrap(), written);
+        assert!(file(&dir, "nets", 2, "nn").exists());
+        assert!(!dir.join("state.tmp").exists());
+
+        let log = fs::read_to_string(dir.join("pipeline.log")).unwrap();
+
+        assert!(log.contains("resuming after iteration 0"));
+        assert!(!log.contains("iteration 1: self-play"));
+        assert!(log.contains("iteration 1: trained generation 2"));
+
+        /* Nothing is left to do */
+        assert_eq!(run(&dir, &config).unwrap(), state);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
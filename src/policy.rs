@@ -0,0 +1,241 @@
+use crate::encode::orient;
+use crate::gmove::*;
+use crate::piece::*;
+use crate::position::*;
+use crate::square::*;
+
+/*
+ * AlphaZero style policy layout: 73 move planes for each of the 64 source
+ * squares. Planes 0-55 are queen-like moves (8 directions x 7 distances),
+ * 56-63 are knight moves and 64-72 are underpromotions (3 pieces x 3
+ * directions). Queen promotions use the queen-like planes. Squares are
+ * oriented from the side to move, so black moves are flipped vertically.
+ */
+pub const MOVE_PLANES: usize = 73;
+pub const POLICY_SIZE: usize = MOVE_PLANES * 64;
+
+const QUEEN_PLANES: usize = 56;
+const KNIGHT_PLANES: usize = 8;
+
+/* (rank, file) steps: N, NE, E, SE, S, SW, W, NW */
+const DIRECTIONS: [(i32, i32); 8] = [
+    (1, 0),
+    (1, 1),
+    (0, 1),
+    (-1, 1),
+    (-1, 0),
+    (-1, -1),
+    (0, -1),
+    (1, -1),
+];
+
+const KNIGHT_JUMPS: [(i32, i32); 8] = [
+    (2, 1),
+    (1, 2),
+    (-1, 2),
+    (-2, 1),
+    (-2, -1),
+    (-1, -2),
+    (1, -2),
+    (2, -1),
+];
+
+const UNDERPROMOTIONS: [Type; 3] = [Type::KNIGHT, Type::BISHOP, Type::ROOK];
+
+/* Maps a move to its policy index, from the perspective of `ctm` */
+pub fn encode(ctm: Color, m: Move) -> Option<usize> {
+    let src = orient(ctm, m.src().index());
+    let dst = orient(ctm, m.dst().index());
+
+    let dr = (dst / 8) as i32 - (src / 8) as i32;
+    let df = (dst % 8) as i32 - (src % 8) as i32;
+
+    let plane = match m.ptype() {
+        Some(t) if t != Type::QUEEN => {
+            let piece = UNDERPROMOTIONS.iter().position(|u| *u == t)?;
+
+            if dr != 1 || df.abs() > 1 {
+                return None;
+            }
+
+            QUEEN_PLANES + KNIGHT_PLANES + piece * 3 + (df + 1) as usize
+        }
+        _ => {
+            if let Some(k) = KNIGHT_JUMPS.iter().position(|j| *j == (dr, df)) {
+                QUEEN_PLANES + k
+            } else {
+                let dist = dr.abs().max(df.abs());
+
+                if dist == 0 || (dr != 0 && df != 0 && dr.abs() != df.abs()) {
+                    return None;
+                }
+
+                let dir = DIRECTIONS
+                    .iter()
+                    .position(|d| *d == (dr / dist, df / dist))?;
+
+                dir * 7 + (dist - 1) as usize
+            }
+        }
+    };
+
+    Some(plane * 64 + src)
+}
+
+/*
+ * Maps a policy index back to a move in `p`. The position is needed to tell
+ * queen promotions apart from ordinary pawn pushes.
+ */
+#[allow(dead_code)]
+pub fn decode(p: &Position, idx: usize) -> Option<Move> {
+    if idx >= POLICY_SIZE {
+        return None;
+    }
+
+    let ctm = p.ctm();
+    let plane = idx / 64;
+    let src = idx % 64;
+
+    let (dr, df, mut ptype) = if plane < QUEEN_PLANES {
+        let (r, f) = DIRECTIONS[plane / 7];
+        let dist = (plane % 7) as i32 + 1;
+
+        (r * dist, f * dist, None)
+    } else if plane < QUEEN_PLANES + KNIGHT_PLANES {
+        let (r, f) = KNIGHT_JUMPS[plane - QUEEN_PLANES];
+
+        (r, f, None)
+    } else {
+        let u = plane - QUEEN_PLANES - KNIGHT_PLANES;
+
+        (1, (u % 3) as i32 - 1, Some(UNDERPROMOTIONS[u / 3]))
+    };
+
+    let rank = (src / 8) as i32 + dr;
+    let file = (src % 8) as i32 + df;
+
+    if !(0..8).contains(&rank) || !(0..8).contains(&file) {
+        return None;
+    }
+
+    let src = Square::from_index(orient(ctm, src))?;
+    let dst = Square::from_index(orient(ctm, (rank * 8 + file) as usize))?;
+
+    let pawn = p
+        .board()
+        .piece_at(src)
+        .is_some_and(|pc| pc.get_type() == Type::PAWN);
+
+    if ptype.is_none() && pawn && rank == 7 {
+        ptype = Some(Type::QUEEN);
+    }
+
+    Some(Move::new(src, dst, ptype))
+}
+
+/* Flags the policy indices of every legal move in `p` */
+#[allow(dead_code)]
+pub fn legal_mask(p: &mut Position) -> Vec<bool> {
+    let mut mask = vec![false; POLICY_SIZE];
+    let ctm = p.ctm();
+
+    for m in p.gen_legal_moves() {
+        if let Some(i) = encode(ctm, m) {
+            mask[i] = true;
+        }
+    }
+
+    mask
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    fn mv(uci: &str) -> Move {
+        Move::from_uci(&uci.to_string()).unwrap()
+    }
+
+    #[test]
+    fn policy_encode_known_moves_works() {
+        let mut p = Position::new(STARTPOS.to_string()).unwrap();
+
+        /* North, distance 2, from e2 */
+        assert_eq!(encode(Color::WHITE, mv("e2e4")), Some(64 + 12));
+
+        /* The same push for black is oriented to the same index */
+        p.make_move(mv("e2e4"));
+        assert_eq!(encode(Color::BLACK, mv("e7e5")), Some(64 + 12));
+        assert_eq!(decode(&p, 64 + 12), Some(mv("e7e5")));
+
+        /* Knights and underpromotions */
+        assert_eq!(encode(Color::WHITE, mv("g1f3")), Some((56 + 7) * 64 + 6));
+        assert_eq!(encode(Color::WHITE, mv("a7a8n")), Some(65 * 64 + 48));
+        assert_eq!(encode(Color::WHITE, mv("b7a8r")), Some(70 * 64 + 49));
+
+        /* Not representable */
+        assert_eq!(encode(Color::WHITE, mv("a1c2b")), None);
+        assert_eq!(encode(Color::WHITE, mv("a1b4")), None);
+    }
+
+    #[test]
+    fn policy_decode_queen_promotion_works() {
+        let p = Position::new("8/1P2k3/8/8/8/8/8/4K3 w - - 0 1".to_string()).unwrap();
+        let i = encode(Color::WHITE, mv("b7b8q")).unwrap();
+
+        assert_eq!(decode(&p, i), Some(mv("b7b8q")));
+        assert_eq!(
+            decode(&p, encode(Color::WHITE, mv("e1e2")).unwrap()),
+            Some(mv("e1e2"))
+        );
+        assert_eq!(decode(&p, POLICY_SIZE), None);
+
+        /* Off the board */
+        assert_eq!(decode(&p, 4 * 7 * 64 + 4), None);
+    }
+
+    #[test]
+    fn policy_random_positions_roundtrip() {
+        let mut rng = StdRng::seed_from_u64(0x5eed);
+
+        /* Include starts rich in castling, en passant and promotions */
+        let fens = [
+            STARTPOS,
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "n1n5/PPPk4/8/8/8/8/4Kppp/5N1N b - - 0 1",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+        ];
+
+        for i in 0..60 {
+            let mut p = Position::new(fens[i % fens.len()].to_string()).unwrap();
+
+            for _ in 0..120 {
+                let moves = p.gen_legal_moves();
+
+                if moves.is_empty() {
+                    break;
+                }
+
+                let mask = legal_mask(&mut p);
+                let mut seen = vec![false; POLICY_SIZE];
+
+                for m in moves.iter() {
+                    let i = encode(p.ctm(), *m).unwrap();
+
+                    assert!(!seen[i], "{} collides in {}", m.to_uci(), p.to_fen());
+                    assert!(mask[i]);
+                    assert_eq!(decode(&p, i), Some(*m));
+
+                    seen[i] = true;
+                }
+
+                assert_eq!(mask.iter().filter(|b| **b).count(), moves.len());
+
+                p.make_move(moves[rng.gen_range(0, moves.len())]);
+            }
+        }
+    }
+}
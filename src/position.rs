@@ -2,10 +2,16 @@ use crate::attacks;
 use crate::bitboard;
 use crate::board::*;
 use crate::gmove::*;
+use crate::nnue::Network;
+use crate::params::{self, Params};
 use crate::piece::*;
 use crate::square::*;
 use crate::zobrist::*;
 
+use std::sync::Arc;
+
+pub const STARTPOS: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+
 #[derive(Copy, Clone)]
 pub enum Castling {
     QUEENSIDE = 0,
@@ -21,6 +27,7 @@ pub struct State {
     hm_clock: u32,                  /* Halfmove clock */
     castling: [[bool; 2]; 2],       /* Castling rights */
     last_move: Option<Move>,        /* Last move */
+    key: u64,                       /* Position hash */
 }
 
 #[derive(Clone, Eq, PartialEq, Debug)]
@@ -179,21 +186,138 @@ impl Position {
 
         let fm_number = fm_number.unwrap();
 
+        let mut state = State {
+            ep_target: ep_target,
+            captured: None,
+            capture_square: None,
+            fm_number: fm_number,
+            hm_clock: hm_clock,
+            castling: rights,
+            last_move: None,
+            key: 0u64,
+        };
+
+        state.key = Position::compute_key(&b, ctm, &state);
+
         Some(Position {
-            ply: vec![State {
-                ep_target: ep_target,
-                captured: None,
-                capture_square: None,
-                fm_number: fm_number,
-                hm_clock: hm_clock,
-                castling: rights,
-                last_move: None,
-            }],
+            ply: vec![state],
             b: b,
             ctm: ctm,
         })
     }
 
+    fn compute_key(b: &Board, ctm: Color, st: &State) -> u64 {
+        let mut key = b.key();
+
+        if ctm == Color::BLACK {
+            key ^= *BLACK_TO_MOVE;
+        }
+
+        for c in 0..2 {
+            for side in 0..2 {
+                if st.castling[c][side] {
+                    key ^= CASTLE[c][side];
+                }
+            }
+        }
+
+        if let Some(ep) = st.ep_target {
+            key ^= EN_PASSANT[ep.file()];
+        }
+
+        key
+    }
+
+    pub fn key(&self) -> u64 {
+        self.ply.last().unwrap().key
+    }
+
+    pub fn board(&self) -> &Board {
+        &self.b
+    }
+
+    /* Attaches an NNUE network to evaluate this position with, or detaches it */
+    pub fn set_network(&mut self, network: Option<Arc<Network>>) {
+        self.b.set_network(network);
+    }
+
+    pub fn ctm(&self) -> Color {
+        self.ctm
+    }
+
+    pub fn halfmove_clock(&self) -> u32 {
+        self.ply.last().unwrap().hm_clock
+    }
+
+    pub fn last_move(&self) -> Option<Move> {
+        self.ply.last().unwrap().last_move
+    }
+
+    /* Moves leading from `earlier` to this position, if this position continues its history */
+    pub fn moves_since(&self, earlier: &Position) -> Option<Vec<Move>> {
+        let n = earlier.ply.len();
+
+        if self.ply.len() < n || self.ply[..n] != earlier.ply[..] {
+            return None;
+        }
+
+        self.ply[n..].iter().map(|s| s.last_move).collect()
+    }
+
+    pub fn in_check(&self) -> bool {
+        let king = self.b.piece_occ(Type::KING) & self.b.color_occ(self.ctm);
+
+        if king == 0u64 {
+            return false;
+        }
+
+        let s = Square::from_index(king.trailing_zeros() as usize).unwrap();
+
+        self.b.attack_count(self.ctm.flip(), s) > 0
+    }
+
+    /* Returns the type of the piece captured by a move, if any */
+    pub fn captured_type(&self, m: Move) -> Option<Type> {
+        if let Some(p) = self.b.piece_at(m.dst()) {
+            return Some(p.get_type());
+        }
+
+        if self.b.piece_occ(Type::PAWN) & m.src().mask() != 0u64
+            && Some(m.dst()) == self.ply.last().unwrap().ep_target
+        {
+            return Some(Type::PAWN);
+        }
+
+        None
+    }
+
+    pub fn is_capture(&self, m: Move) -> bool {
+        self.captured_type(m).is_some()
+    }
+
+    /* Counts earlier occurrences of the current position since the last irreversible move */
+    pub fn repetitions(&self) -> usize {
+        let top = self.ply.last().unwrap();
+        let window = (top.hm_clock as usize).min(self.ply.len() - 1);
+
+        (2..=window)
+            .step_by(2)
+            .filter(|i| self.ply[self.ply.len() - 1 - i].key == top.key)
+            .count()
+    }
+
+    pub fn is_repetition(&self) -> bool {
+        self.repetitions() > 0
+    }
+
+    pub fn castling(&self, c: Color, side: Castling) -> bool {
+        self.ply.last().unwrap().castling[c as usize][side as usize]
+    }
+
+    pub fn is_draw(&self) -> bool {
+        self.halfmove_clock() >= 100 || self.is_repetition()
+    }
+
     pub fn to_fen(&self) -> String {
         let mut output: String = self.b.to_fen_string();
 
@@ -219,6 +343,10 @@ impl Position {
             output.push('q');
         }
 
+        if top.castling == [[false; 2]; 2] {
+            output.push('-');
+        }
+
         output.push(' ');
 
         /* Add ep target */
@@ -234,6 +362,11 @@ impl Position {
     }
 
     pub fn make_move(&mut self, m: Move) -> bool {
+        params::with(|params| self.make_move_with(m, params))
+    }
+
+    /* Evaluation parameters are fetched once per move rather than for every piece moved */
+    fn make_move_with(&mut self, m: Move, params: &Params) -> bool {
         //println!("making move {}, current ply = {}", m.to_uci(), self.ply.len());
         let last_state = self.ply.last().unwrap();
         let mut new_state = last_state.clone();
@@ -245,14 +378,20 @@ impl Position {
         new_state.last_move = Some(m.clone());
         new_state.hm_clock += 1;
 
-        if self.ctm == Color::WHITE {
+        if self.ctm == Color::BLACK {
             new_state.fm_number += 1;
         }
 
+        /* Pawn moves are irreversible */
+        if self.b.piece_occ(Type::PAWN) & m.src().mask() != 0u64 {
+            new_state.hm_clock = 0;
+        }
+
         /* Test if move is capture */
         if self.b.color_occ(self.ctm.flip()) & m.dst().mask() != 0u64 {
-            new_state.captured = Some(self.b.remove(m.dst()));
+            new_state.captured = Some(self.b.remove_with(m.dst(), params));
             new_state.capture_square = Some(m.dst());
+            new_state.hm_clock = 0;
         }
 
         /* Test for ep capture */
@@ -267,7 +406,7 @@ impl Position {
                 )
                 .unwrap();
 
-                new_state.captured = Some(self.b.remove(ep_square));
+                new_state.captured = Some(self.b.remove_with(ep_square, params));
                 new_state.capture_square = Some(ep_square);
             }
         }
@@ -289,12 +428,12 @@ impl Position {
                 /* Place the rook on the new square */
                 match castle_side {
                     Castling::QUEENSIDE => {
-                        let p = self.b.remove(Square::at(m.src().rank(), 0));
-                        self.b.place(Square::at(m.src().rank(), 3), p);
+                        let p = self.b.remove_with(Square::at(m.src().rank(), 0), params);
+                        self.b.place_with(Square::at(m.src().rank(), 3), p, params);
                     }
                     Castling::KINGSIDE => {
-                        let p = self.b.remove(Square::at(m.src().rank(), 7));
-                        self.b.place(Square::at(m.src().rank(), 5), p);
+                        let p = self.b.remove_with(Square::at(m.src().rank(), 7), params);
+                        self.b.place_with(Square::at(m.src().rank(), 5), p, params);
                     }
                 }
             }
@@ -303,14 +442,14 @@ impl Position {
             new_state.castling[self.ctm as usize] = [false, false];
         }
 
-        /* Revoke castling rights on corner moves */
-        if self.b.piece_occ(Type::ROOK) & m.src().mask() != 0u64 {
-            match m.src().index() {
+        /* Revoke castling rights on moves from or to the corners */
+        for s in [m.src(), m.dst()].iter() {
+            match s.index() {
                 0 => {
                     new_state.castling[Color::WHITE as usize][Castling::QUEENSIDE as usize] = false
                 }
                 7 => new_state.castling[Color::WHITE as usize][Castling::KINGSIDE as usize] = false,
-                54 => {
+                56 => {
                     new_state.castling[Color::BLACK as usize][Castling::QUEENSIDE as usize] = false
                 }
                 63 => {
@@ -331,14 +470,16 @@ impl Position {
         }
 
         /* All moves remove the src piece */
-        let mut pc = self.b.remove(m.src());
+        let mut pc = self.b.remove_with(m.src(), params);
 
         /* Promoting moves replace the piece */
         if m.ptype().is_some() {
             pc = Piece::from(m.ptype().unwrap(), self.ctm);
         }
 
-        self.b.place(m.dst(), pc);
+        self.b.place_with(m.dst(), pc, params);
+
+        new_state.key = Position::compute_key(&self.b, self.ctm.flip(), &new_state);
 
         let mut is_legal = true;
 
@@ -346,7 +487,7 @@ impl Position {
         bitboard::for_each(
             (self.b.piece_occ(Type::KING) & self.b.color_occ(self.ctm)) | castle_att_mask,
             |s| {
-                if self.b.get_ad()[self.ctm.flip() as usize][s.index()] > 0 {
+                if self.b.attack_count(self.ctm.flip(), s) > 0 {
                     is_legal = false;
                 }
             },
@@ -360,6 +501,10 @@ impl Position {
     }
 
     pub fn unmake_move(&mut self, m: Move) {
+        params::with(|params| self.unmake_move_with(m, params))
+    }
+
+    fn unmake_move_with(&mut self, m: Move, params: &Params) {
         //println!("unmaking move {}, current ply = {}", m.to_uci(), self.ply.len());
         let last_state = self.ply.pop().unwrap();
 
@@ -377,31 +522,31 @@ impl Position {
                 /* Place the rook on the old square */
                 match castle_side {
                     Castling::QUEENSIDE => {
-                        let p = self.b.remove(Square::at(m.src().rank(), 3));
-                        self.b.place(Square::at(m.src().rank(), 0), p);
+                        let p = self.b.remove_with(Square::at(m.src().rank(), 3), params);
+                        self.b.place_with(Square::at(m.src().rank(), 0), p, params);
                     }
                     Castling::KINGSIDE => {
-                        let p = self.b.remove(Square::at(m.src().rank(), 5));
-                        self.b.place(Square::at(m.src().rank(), 7), p);
+                        let p = self.b.remove_with(Square::at(m.src().rank(), 5), params);
+                        self.b.place_with(Square::at(m.src().rank(), 7), p, params);
                     }
                 }
             }
         }
 
         /* Undo move */
-        let mut p = self.b.remove(m.dst());
+        let mut p = self.b.remove_with(m.dst(), params);
 
         /* If move was promotion, replace with pawn */
         if m.ptype().is_some() {
             p = Piece::from(Type::PAWN, self.ctm.flip());
         }
 
-        self.b.place(m.src(), p);
+        self.b.place_with(m.src(), p, params);
 
         /* If last move was capture, replace piece */
         match last_state.capture_square {
             Some(sq) => {
-                self.b.place(sq, last_state.captured.unwrap());
+                self.b.place_with(sq, last_state.captured.unwrap(), params);
             }
             None => {}
         }
@@ -622,6 +767,20 @@ impl Position {
         output
     }
 
+    pub fn gen_legal_moves(&mut self) -> Vec<Move> {
+        let mut output: Vec<Move> = Vec::new();
+
+        for m in self.gen_pseudolegal_moves() {
+            if self.make_move(m) {
+                output.push(m);
+            }
+
+            self.unmake_move(m);
+        }
+
+        output
+    }
+
     pub fn perft(&mut self, d: usize, p: usize) -> usize {
         if d == 0 {
             return 1;
@@ -644,9 +803,65 @@ impl Position {
 }
 
 #[cfg(test)]
-mod tests {
+pub mod tests {
     use super::*;
 
+    /* Mirrors a FEN vertically and swaps piece colors and the side to move */
+    pub fn mirror_fen(fen: &str) -> String {
+        let parts: Vec<&str> = fen.split(' ').collect();
+        let swap = |s: &str| -> String {
+            s.chars()
+                .map(|c| {
+                    if c.is_uppercase() {
+                        c.to_ascii_lowercase()
+                    } else {
+                        c.to_ascii_uppercase()
+                    }
+                })
+                .collect()
+        };
+
+        let board: Vec<&str> = parts[0].split('/').rev().collect();
+        let ctm = if parts[1] == "w" { "b" } else { "w" };
+        let ep = match Square::from_uci(&parts[3].to_string()) {
+            Some(s) => Square::at(7 - s.rank(), s.file()).to_str(),
+            None => "-".to_string(),
+        };
+
+        format!(
+            "{} {} {} {} {} {}",
+            swap(&board.join("/")),
+            ctm,
+            swap(parts[2]),
+            ep,
+            parts[4],
+            parts[5]
+        )
+    }
+
+    #[test]
+    fn position_mirror_fen_works() {
+        assert_eq!(
+            mirror_fen("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w Kq e3 0 1"),
+            "r3k2r/pppbbppp/2n2q1P/1P2p3/3pn3/BN2PNP1/P1PPQPB1/R3K2R b kQ e6 0 1"
+        );
+    }
+
+    #[test]
+    fn position_repetitions_works() {
+        let mut p = Position::new(STARTPOS.to_string()).unwrap();
+
+        for _ in 0..2 {
+            for m in ["g1f3", "g8f6", "f3g1", "f6g8"].iter() {
+                assert_eq!(p.repetitions() > 0, p.is_repetition());
+                p.make_move(Move::from_uci(&m.to_string()).unwrap());
+            }
+        }
+
+        assert_eq!(p.repetitions(), 2);
+        assert!(p.is_draw());
+    }
+
     #[test]
     fn position_parse_fen_works() {
         Position::new("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1".to_string())
@@ -712,6 +927,63 @@ mod tests {
         .is_none());
     }
 
+    #[test]
+    fn position_halfmove_clock_resets() {
+        let mut p = Position::new(
+            "rnbqkbnr/ppp1pppp/8/3p4/4P3/8/PPPP1PPP/RNBQKBNR w KQkq - 3 2".to_string(),
+        )
+        .unwrap();
+
+        /* Captures reset the clock */
+        p.make_move(Move::from_uci(&"e4d5".to_string()).unwrap());
+        assert!(p.to_fen().ends_with(" 0 2"));
+
+        /* Pawn pushes reset it, piece moves advance it */
+        p.make_move(Move::from_uci(&"g8f6".to_string()).unwrap());
+        assert!(p.to_fen().ends_with(" 1 3"));
+        p.make_move(Move::from_uci(&"c2c4".to_string()).unwrap());
+        assert!(p.to_fen().ends_with(" 0 3"));
+    }
+
+    #[test]
+    fn position_fullmove_number_increments_after_black() {
+        let mut p =
+            Position::new("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1".to_string())
+                .unwrap();
+
+        p.make_move(Move::from_uci(&"g1f3".to_string()).unwrap());
+        assert_eq!(
+            p.to_fen(),
+            "rnbqkbnr/pppppppp/8/8/8/5N2/PPPPPPPP/RNBQKB1R b KQkq - 1 1"
+        );
+
+        p.make_move(Move::from_uci(&"g8f6".to_string()).unwrap());
+        assert_eq!(
+            p.to_fen(),
+            "rnbqkb1r/pppppppp/5n2/8/8/5N2/PPPPPPPP/RNBQKB1R w KQkq - 2 2"
+        );
+    }
+
+    #[test]
+    fn position_corner_capture_revokes_castling() {
+        /* Capturing on a8 and h8 takes black's rights even though the rooks never moved */
+        let mut p = Position::new("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1".to_string()).unwrap();
+
+        p.make_move(Move::from_uci(&"a1a8".to_string()).unwrap());
+        assert_eq!(p.to_fen(), "R3k2r/8/8/8/8/8/8/4K2R b Kk - 0 1");
+
+        let mut p = Position::new("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1".to_string()).unwrap();
+
+        p.make_move(Move::from_uci(&"h1h8".to_string()).unwrap());
+        assert_eq!(p.to_fen(), "r3k2R/8/8/8/8/8/8/R3K3 b Qq - 0 1");
+
+        /* With no rights left the field is written as '-' */
+        let mut p = Position::new("r3k3/8/8/8/8/8/8/R3K3 w Qq - 0 1".to_string()).unwrap();
+
+        p.make_move(Move::from_uci(&"a1a8".to_string()).unwrap());
+        assert_eq!(p.to_fen(), "R3k3/8/8/8/8/8/8/4K3 b - - 0 1");
+    }
+
     #[test]
     fn position_pseudolegal_gen_standard_count() {
         assert_eq!(
@@ -758,6 +1030,16 @@ mod tests {
         assert_eq!(p.perft(4, 0), 197281);
     }
 
+    #[test]
+    fn position_pawn_attacks_do_not_wrap() {
+        /* An h-file pawn must not attack a4, nor an a-file pawn h5 */
+        let mut p = Position::new("8/8/8/k7/8/8/7P/7K b - - 0 1".to_string()).unwrap();
+        assert_eq!(p.perft(1, 0), 5);
+
+        let mut p = Position::new("7k/p7/8/8/7K/8/8/8 w - - 0 1".to_string()).unwrap();
+        assert_eq!(p.perft(1, 0), 5);
+    }
+
     #[test]
     fn position_perft2_nodes() {
         let mut p: Position = Position::new(
@@ -831,4 +1113,24 @@ mod tests {
 
         assert_eq!(p, pclone);
     }
+
+    #[test]
+    fn position_moves_since_works() {
+        let mv = |s: &str| Move::from_uci(&s.to_string()).unwrap();
+        let start = Position::new(STARTPOS.to_string()).unwrap();
+        let mut p = start.clone();
+
+        assert_eq!(p.moves_since(&start), Some(vec![]));
+
+        p.make_move(mv("e2e4"));
+        p.make_move(mv("e7e5"));
+
+        assert_eq!(p.moves_since(&start), Some(vec![mv("e2e4"), mv("e7e5")]));
+        assert_eq!(start.moves_since(&p), None);
+
+        let mut other = start.clone();
+        other.make_move(mv("d2d4"));
+
+        assert_eq!(p.moves_since(&other), None);
+    }
 }
@@ -0,0 +1,472 @@
+use crate::encode;
+
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use memmap2::Mmap;
+use rand::seq::SliceRandom;
+use rand::Rng;
+
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Error, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+
+/*
+ * Training record files: a fixed 32 byte header followed by fixed-size
+ * records, optionally gzip compressed (the header never is).
+ *
+ * header: magic "NCTR", version, flags, history steps, planes per step,
+ *         aux planes, policy size, record size (all u32, little endian)
+ *
+ * record: input planes as one bitboard each (u64 x PLANES), halfmove clock
+ *         (u8), result (i8), policy entry count (u16), then MAX_POLICY
+ *         (index u16, probability u16) pairs, zero padded.
+ */
+const MAGIC: &[u8; 4] = b"NCTR";
+pub const VERSION: u32 = 1;
+
+pub const EXTENSION: &str = "rec";
+
+const HEADER_SIZE: usize = 32;
+const FLAG_GZIP: u32 = 1;
+
+/* More than the maximum number of legal moves in any position */
+pub const MAX_POLICY: usize = 224;
+
+pub const RECORD_SIZE: usize = encode::PLANES * 8 + 4 + MAX_POLICY * 4;
+
+/* A single training position */
+#[derive(Clone, Debug, PartialEq)]
+pub struct Sample {
+    pub planes: Vec<f32>,
+    pub policy: Vec<(u16, f32)>, /* Target distribution by policy index */
+    pub result: f32,             /* Game result for the side to move */
+}
+
+/* Packs a sample into a record. Planes other than the halfmove clock are binary. */
+pub fn pack(s: &Sample) -> Vec<u8> {
+    let mut out = Vec::with_capacity(RECORD_SIZE);
+
+    for p in 0..encode::PLANES {
+        let mut bits = 0u64;
+
+        for (sq, v) in s.planes[p * 64..(p + 1) * 64].iter().enumerate() {
+            if *v != 0.0 {
+                bits |= 1 << sq;
+            }
+        }
+
+        out.extend_from_slice(&bits.to_le_bytes());
+    }
+
+    let hm = s.planes[encode::HALFMOVE_PLANE * 64] * 100.0;
+
+    out.push(hm.round().clamp(0.0, 255.0) as u8);
+    out.push(s.result.round().clamp(-1.0, 1.0) as i8 as u8);
+
+    let count = s.policy.len().min(MAX_POLICY);
+    out.extend_from_slice(&(count as u16).to_le_bytes());
+
+    for (i, p) in s.policy.iter().take(count) {
+        out.extend_from_slice(&i.to_le_bytes());
+        out.extend_from_slice(&((p.clamp(0.0, 1.0) * 65535.0).round() as u16).to_le_bytes());
+    }
+
+    out.resize(RECORD_SIZE, 0);
+    out
+}
+
+pub fn unpack(data: &[u8]) -> Sample {
+    let mut planes = vec![0.0; encode::INPUT_SIZE];
+    let u16_at = |i: usize| u16::from_le_bytes([data[i], data[i + 1]]);
+
+    for p in 0..encode::PLANES {
+        let mut b = [0u8; 8];
+        b.copy_from_slice(&data[p * 8..p * 8 + 8]);
+
+        let bits = u64::from_le_bytes(b);
+
+        for sq in 0..64 {
+            if bits >> sq & 1 != 0 {
+                planes[p * 64 + sq] = 1.0;
+            }
+        }
+    }
+
+    let tail = encode::PLANES * 8;
+    let hm = data[tail] as f32 / 100.0;
+
+    for v in planes[encode::HALFMOVE_PLANE * 64..(encode::HALFMOVE_PLANE + 1) * 64].iter_mut() {
+        *v = hm;
+    }
+
+    let count = (u16_at(tail + 2) as usize).min(MAX_POLICY);
+    let policy = (0..count)
+        .map(|i| {
+            let at = tail + 4 + i * 4;
+            (u16_at(at), u16_at(at + 2) as f32 / 65535.0)
+        })
+        .collect();
+
+    Sample {
+        planes,
+        policy,
+        result: data[tail + 1] as i8 as f32,
+    }
+}
+
+fn header(flags: u32) -> Vec<u8> {
+    let mut out = MAGIC.to_vec();
+
+    for w in [
+        VERSION,
+        flags,
+        encode::HISTORY as u32,
+        encode::PLANES_PER_STEP as u32,
+        encode::AUX_PLANES as u32,
+        crate::policy::POLICY_SIZE as u32,
+        RECORD_SIZE as u32,
+    ]
+    .iter()
+    {
+        out.extend_from_slice(&w.to_le_bytes());
+    }
+
+    out
+}
+
+enum Body {
+    Plain(BufWriter<File>),
+    Gzip(GzEncoder<BufWriter<File>>),
+}
+
+/* Streams records to a new file */
+pub struct Writer {
+    body: Body,
+}
+
+impl Writer {
+    pub fn create<P: AsRef<Path>>(path: P, compress: bool) -> io::Result<Writer> {
+        let mut f = BufWriter::new(File::create(path)?);
+
+        f.write_all(&header(if compress { FLAG_GZIP } else { 0 }))?;
+
+        let body = if compress {
+            Body::Gzip(GzEncoder::new(f, Compression::default()))
+        } else {
+            Body::Plain(f)
+        };
+
+        Ok(Writer { body })
+    }
+
+    pub fn write(&mut self, s: &Sample) -> io::Result<()> {
+        let data = pack(s);
+
+        match &mut self.body {
+            Body::Plain(w) => w.write_all(&data)?,
+            Body::Gzip(w) => w.write_all(&data)?,
+        }
+
+        Ok(())
+    }
+
+    /* Flushes all records. Must be called for the file to be complete. */
+    pub fn finish(self) -> io::Result<()> {
+        let mut f = match self.body {
+            Body::Plain(w) => w,
+            Body::Gzip(w) => w.finish()?,
+        };
+
+        f.flush()
+    }
+}
+
+enum Data {
+    Mapped(Mmap),
+    Owned(Vec<u8>),
+}
+
+/* Random access to the records in a file. Uncompressed files are memory-mapped. */
+pub struct Reader {
+    data: Data,
+    count: usize,
+}
+
+impl Reader {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Reader> {
+        let invalid = |msg: String| Error::new(ErrorKind::InvalidData, msg);
+        let mut f = File::open(path)?;
+        let mut h = [0u8; HEADER_SIZE];
+
+        f.read_exact(&mut h)?;
+
+        if &h[0..4] != MAGIC {
+            return Err(invalid("not a training record file".to_string()));
+        }
+
+        let flags = u32::from_le_bytes([h[8], h[9], h[10], h[11]]);
+
+        if h[..] != header(flags)[..] {
+            return Err(invalid("incompatible record version or layout".to_string()));
+        }
+
+        let data = if flags & FLAG_GZIP != 0 {
+            let mut body = Vec::new();
+            GzDecoder::new(f).read_to_end(&mut body)?;
+            Data::Owned(body)
+        } else {
+            /* Safety: record files are not modified once written */
+            Data::Mapped(unsafe { Mmap::map(&f)? })
+        };
+
+        let len = match &data {
+            Data::Mapped(m) => m.len() - HEADER_SIZE,
+            Data::Owned(v) => v.len(),
+        };
+
+        if !len.is_multiple_of(RECORD_SIZE) {
+            return Err(invalid("truncated record file".to_string()));
+        }
+
+        Ok(Reader {
+            data,
+            count: len / RECORD_SIZE,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    pub fn get(&self, i: usize) -> Sample {
+        let body = match &self.data {
+            Data::Mapped(m) => &m[HEADER_SIZE..],
+            Data::Owned(v) => &v[..],
+        };
+
+        unpack(&body[i * RECORD_SIZE..(i + 1) * RECORD_SIZE])
+    }
+}
+
+/* The `window` most recent record files in `dir`, ordered by name */
+pub fn window<P: AsRef<Path>>(dir: P, window: usize) -> io::Result<Vec<PathBuf>> {
+    let mut paths: Vec<PathBuf> = fs::read_dir(dir)?
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|p| p.extension().is_some_and(|e| e == EXTENSION))
+        .collect();
+
+    paths.sort();
+
+    Ok(paths.split_off(paths.len().saturating_sub(window)))
+}
+
+/*
+ * Draws shuffled batches across several files. Every record in the window is
+ * visited once per epoch, in a fresh random order each time.
+ */
+pub struct Sampler {
+    readers: Vec<Reader>,
+    order: Vec<(u32, u32)>,
+    next: usize,
+}
+
+impl Sampler {
+    pub fn new<P: AsRef<Path>>(paths: &[P]) -> io::Result<Sampler> {
+        let readers = paths
+            .iter()
+            .map(Reader::open)
+            .collect::<io::Result<Vec<Reader>>>()?;
+
+        let order = readers
+            .iter()
+            .enumerate()
+            .flat_map(|(f, r)| (0..r.len()).map(move |i| (f as u32, i as u32)))
+            .collect::<Vec<_>>();
+
+        let next = order.len();
+
+        Ok(Sampler {
+            readers,
+            order,
+            next,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.order.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.order.is_empty()
+    }
+
+    pub fn batch<R: Rng>(&mut self, rng: &mut R, size: usize) -> Vec<Sample> {
+        let mut out = Vec::with_capacity(size);
+
+        if self.order.is_empty() {
+            return out;
+        }
+
+        while out.len() < size {
+            if self.next >= self.order.len() {
+                self.order.shuffle(rng);
+                self.next = 0;
+            }
+
+            let (f, i) = self.order[self.next];
+
+            out.push(self.readers[f as usize].get(i as usize));
+            self.next += 1;
+        }
+
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gmove::*;
+    use crate::policy;
+    use crate::position::*;
+
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn sample(moves: &[&str], result: f32) -> Sample {
+        let mut p = Position::new(STARTPOS.to_string()).unwrap();
+
+        for m in moves.iter() {
+            p.make_move(Move::from_uci(&m.to_string()).unwrap());
+        }
+
+        let legal = p.gen_legal_moves();
+
+        Sample {
+            planes: encode::encode(&p),
+            policy: legal
+                .iter()
+                .map(|m| {
+                    let i = policy::encode(p.ctm(), *m).unwrap() as u16;
+                    (i, 1.0 / legal.len() as f32)
+                })
+                .collect(),
+            result,
+        }
+    }
+
+    fn assert_close(a: &Sample, b: &Sample) {
+        assert_eq!(a.planes, b.planes);
+        assert_eq!(a.result, b.result);
+        assert_eq!(a.policy.len(), b.policy.len());
+
+        for (x, y) in a.policy.iter().zip(b.policy.iter()) {
+            assert_eq!(x.0, y.0);
+            assert!((x.1 - y.1).abs() < 1e-4);
+        }
+    }
+
+    fn temp(name: &str) -> PathBuf {
+        std::env::temp_dir().join(name)
+    }
+
+    #[test]
+    fn record_pack_roundtrip_works() {
+        let s = sample(&["e2e4", "c7c5", "g1f3"], -1.0);
+        let data = pack(&s);
+
+        assert_eq!(data.len(), RECORD_SIZE);
+        assert_close(&unpack(&data), &s);
+    }
+
+    #[test]
+    fn record_files_roundtrip() {
+        let samples = [
+            sample(&[], 0.0),
+            sample(&["e2e4"], 1.0),
+            sample(&["e2e4", "e7e5"], -1.0),
+        ];
+
+        for compress in [false, true].iter() {
+            let path = temp(&format!("nc_record_{}.rec", compress));
+            let mut w = Writer::create(&path, *compress).unwrap();
+
+            for s in samples.iter() {
+                w.write(s).unwrap();
+            }
+
+            w.finish().unwrap();
+
+            let r = Reader::open(&path).unwrap();
+
+            assert_eq!(r.len(), 3);
+
+            for (i, s) in samples.iter().enumerate() {
+                assert_close(&r.get(i), s);
+            }
+
+            if *compress {
+                assert!(fs::metadata(&path).unwrap().len() < (RECORD_SIZE * 3) as u64);
+            }
+
+            fs::remove_file(&path).unwrap();
+        }
+    }
+
+    #[test]
+    fn record_rejects_bad_files() {
+        let path = temp("nc_record_bad.rec");
+
+        fs::write(&path, b"not a record file at all, clearly").unwrap();
+        assert!(Reader::open(&path).is_err());
+
+        let mut w = Writer::create(&path, false).unwrap();
+        w.write(&sample(&[], 0.0)).unwrap();
+        w.finish().unwrap();
+
+        let mut data = fs::read(&path).unwrap();
+        data.pop();
+        fs::write(&path, &data).unwrap();
+        assert!(Reader::open(&path).is_err());
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn record_sampler_covers_window() {
+        let dir = temp("nc_record_window");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir(&dir).unwrap();
+
+        /* Three files of 1, 2 and 3 records with distinct results per file */
+        for f in 0..3 {
+            let mut w = Writer::create(dir.join(format!("{}.rec", f)), f % 2 == 0).unwrap();
+
+            for _ in 0..=f {
+                w.write(&sample(&[], f as f32 - 1.0)).unwrap();
+            }
+
+            w.finish().unwrap();
+        }
+
+        fs::write(dir.join("notes.txt"), "ignored").unwrap();
+
+        let paths = window(&dir, 2).unwrap();
+        assert_eq!(paths, vec![dir.join("1.rec"), dir.join("2.rec")]);
+
+        let mut s = Sampler::new(&paths).unwrap();
+        let mut rng = StdRng::seed_from_u64(3);
+
+        assert_eq!(s.len(), 5);
+
+        /* One epoch visits every record exactly once */
+        let mut results: Vec<f32> = s.batch(&mut rng, 5).iter().map(|x| x.result).collect();
+        results.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        assert_eq!(results, vec![0.0, 0.0, 1.0, 1.0, 1.0]);
+
+        assert_eq!(s.batch(&mut rng, 12).len(), 12);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,202 @@
+use crate::attacks;
+use crate::bitboard;
+use crate::board::*;
+use crate::eval::Score;
+use crate::params::{self, Accumulator, TermGroup};
+use crate::piece::*;
+use crate::square::*;
+
+/* Attack units contributed by each piece type reaching the enemy king zone */
+pub const ATTACKER: [i32; 6] = [0, 20, 20, 40, 80, 0];
+
+/* Attack units per attack on a king zone square, counted from the attack map */
+pub const ZONE_ATTACK: i32 = 6;
+
+/* Danger grows with the square of the attack units, up to this bound */
+pub const DANGER_SCALE: i32 = 256;
+pub const MAX_DANGER: i32 = 600;
+
+/* Default bonuses per shielding pawn one and two ranks in front of the king */
+pub const SHIELD: [Score; 2] = [Score { mg: 15, eg: 0 }, Score { mg: 8, eg: 0 }];
+
+/* Penalties per file on or beside the king without our pawns, or without any */
+pub const SEMI_OPEN: Score = Score { mg: -15, eg: 0 };
+pub const OPEN: Score = Score { mg: -25, eg: 0 };
+
+/* King safety inputs per colour, describing the threats to that colour's king */
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Terms {
+    pub zone: [u64; 2],         /* Squares around the king and in front of them */
+    pub zone_attacks: [u32; 2], /* Enemy attacks on the zone, from the attack map */
+    pub attackers: [u32; 2],    /* Enemy pieces attacking the zone */
+    pub units: [i32; 2],        /* ATTACKER summed over those pieces */
+    pub shield: [[u32; 2]; 2],  /* Own pawns one and two ranks in front of the king */
+    pub semi_open: [u32; 2],
+    pub open: [u32; 2],
+}
+
+/* The rank `n` ranks in front of `rank` from `c`'s point of view, if on the board */
+fn rank_ahead(c: Color, rank: usize, n: usize) -> u64 {
+    let r = match c {
+        Color::WHITE => rank + n,
+        Color::BLACK => rank.wrapping_sub(n),
+    };
+
+    bitboard::RANKS.get(r).cloned().unwrap_or(0)
+}
+
+fn attacks(b: &Board, t: Type, s: Square) -> u64 {
+    match t {
+        Type::BISHOP => attacks::bishop(s, b.global_occ()),
+        Type::KNIGHT => attacks::knight(s),
+        Type::ROOK => attacks::rook(s, b.global_occ()),
+        Type::QUEEN => attacks::queen(s, b.global_occ()),
+        Type::PAWN | Type::KING => 0,
+    }
+}
+
+impl Terms {
+    pub fn new(b: &Board) -> Terms {
+        let mut t = Terms::default();
+        let pawns = b.piece_occ(Type::PAWN);
+
+        for c in [Color::WHITE, Color::BLACK].iter() {
+            let c = *c;
+            let i = c as usize;
+            let king = b.piece_occ(Type::KING) & b.color_occ(c);
+
+            if king == 0 {
+                continue;
+            }
+
+            let ksq = Square::from_index(king.trailing_zeros() as usize).unwrap();
+            let around = king | attacks::king(ksq);
+            let ahead = match c {
+                Color::WHITE => around << 8,
+                Color::BLACK => around >> 8,
+            };
+
+            t.zone[i] = around | ahead;
+
+            bitboard::for_each(t.zone[i], |s| {
+                t.zone_attacks[i] += b.attack_count(c.flip(), s) as u32;
+            });
+
+            bitboard::for_each(b.color_occ(c.flip()), |s| {
+                let ty = b.piece_at(s).unwrap().get_type();
+
+                if attacks(b, ty, s) & t.zone[i] != 0 {
+                    t.attackers[i] += 1;
+                    t.units[i] += ATTACKER[ty as usize];
+                }
+            });
+
+            let files = bitboard::FILES[ksq.file()] | bitboard::adjacent_files(ksq.file());
+            let ours = pawns & b.color_occ(c);
+
+            for n in 0..2 {
+                t.shield[i][n] = (ours & files & rank_ahead(c, ksq.rank(), n + 1)).count_ones();
+            }
+
+            for f in ksq.file().saturating_sub(1)..=(ksq.file() + 1).min(7) {
+                if pawns & bitboard::FILES[f] == 0 {
+                    t.open[i] += 1;
+                } else if ours & bitboard::FILES[f] == 0 {
+                    t.semi_open[i] += 1;
+                }
+            }
+        }
+
+        t
+    }
+
+    /* Midgame penalty for the attack on `c`'s king; a lone attacker is ignored */
+    pub fn danger(&self, c: Color) -> i32 {
+        let i = c as usize;
+
+        if self.attackers[i] < 2 {
+            return 0;
+        }
+
+        let units = self.units[i] + ZONE_ATTACK * self.zone_attacks[i] as i32;
+
+        (units * units / DANGER_SCALE).min(MAX_DANGER)
+    }
+}
+
+/* Pawn shield, open files next to the king and the attack on it */
+impl TermGroup for Terms {
+    fn accumulate<A: Accumulator>(&self, acc: &mut A) {
+        for (c, sign) in [(Color::WHITE, 1), (Color::BLACK, -1)].iter() {
+            let i = *c as usize;
+
+            acc.add(params::SHIELD, sign * self.shield[i][0] as i32);
+            acc.add(params::SHIELD + 1, sign * self.shield[i][1] as i32);
+            acc.add(params::SEMI_OPEN, sign * self.semi_open[i] as i32);
+            acc.add(params::OPEN, sign * self.open[i] as i32);
+            acc.constant(Score::new(-sign * self.danger(*c), 0));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::params::Params;
+    use crate::position::*;
+
+    fn terms(fen: &str) -> Terms {
+        Terms::new(Position::new(fen.to_string()).unwrap().board())
+    }
+
+    #[test]
+    fn safety_zone_attacks_work() {
+        let fen = "6k1/5ppp/8/6N1/8/3Q4/8/4K3 w - - 0 1";
+        let p = Position::new(fen.to_string()).unwrap();
+        let t = Terms::new(p.board());
+
+        /* The knight hits f7 and h7; the queen reaches g6 */
+        assert_eq!(t.attackers[1], 2);
+        assert_eq!(t.units[1], ATTACKER[2] + ATTACKER[4]);
+
+        let mut attacks = 0;
+        bitboard::for_each(t.zone[1], |s| {
+            attacks += p.board().attack_count(Color::WHITE, s) as u32
+        });
+
+        assert_eq!(t.zone_attacks[1], attacks);
+        assert!(t.danger(Color::BLACK) > 0);
+        assert_eq!(t.danger(Color::WHITE), 0);
+    }
+
+    #[test]
+    fn safety_shield_and_files_work() {
+        let t = terms("4k3/8/8/8/8/8/5PPP/6K1 w - - 0 1");
+        assert_eq!(t.shield[0], [3, 0]);
+        assert_eq!((t.semi_open[0], t.open[0]), (0, 0));
+
+        /* g-pawn advanced two ranks, h-pawn gone */
+        let t = terms("4k3/8/8/8/8/6P1/5P2/6K1 w - - 0 1");
+        assert_eq!(t.shield[0], [1, 1]);
+        assert_eq!((t.semi_open[0], t.open[0]), (0, 1));
+
+        /* Only an enemy pawn on the h-file */
+        let t = terms("4k3/7p/8/8/8/8/5PP1/6K1 w - - 0 1");
+        assert_eq!((t.semi_open[0], t.open[0]), (1, 0));
+
+        /* Kings on the edge only look at two files */
+        let t = terms("k7/8/8/8/8/8/8/7K w - - 0 1");
+        assert_eq!((t.open[0], t.open[1]), (2, 2));
+    }
+
+    #[test]
+    fn safety_prefers_sheltered_king() {
+        let sheltered = terms("r5k1/5ppp/8/8/8/8/5PPP/R5K1 w - - 0 1");
+        let exposed = terms("r5k1/5ppp/8/8/8/8/8/R3K3 w - - 0 1");
+
+        let params = Params::default();
+
+        assert_eq!(sheltered.score(&params), Score::default());
+        assert!(exposed.score(&params).mg < 0);
+    }
+}
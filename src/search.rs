@@ -0,0 +1,667 @@
+use crate::eval;
+use crate::gmove::*;
+use crate::movepick::{self, Heuristics, MovePicker, SEE_VALUE};
+use crate::params;
+use crate::pawns::PawnTable;
+use crate::piece::*;
+use crate::position::*;
+use crate::timeman::*;
+use crate::tt::*;
+
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+
+pub const MAX_PLY: usize = 128;
+pub const INFINITY: i32 = 32000;
+pub const MATE: i32 = 31000;
+
+/* Scores at or beyond this bound are mate scores */
+pub const MATE_BOUND: i32 = MATE - MAX_PLY as i32;
+
+/* How often (in nodes) the clock is polled and shared node counts are published */
+const CHECK_INTERVAL: u64 = 1024;
+
+/* Depth staggering for helper threads: helper i skips depths by SKIP_SIZE/SKIP_PHASE */
+const SKIP_SIZE: [usize; 20] = [1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 3, 3, 4, 4, 4, 4, 4, 4, 4, 4];
+const SKIP_PHASE: [usize; 20] = [0, 1, 0, 1, 2, 3, 0, 1, 2, 3, 4, 5, 0, 1, 2, 3, 4, 5, 6, 7];
+
+/* Safety margin for delta pruning in quiescence */
+const DELTA_MARGIN: i32 = 200;
+
+/* Delta pruning is off once the opponent's material falls to this, where a single capture can decide the game */
+const DELTA_MIN_MATERIAL: i32 = 1300;
+
+/* Attacker ordering for MVV-LVA, indexed by piece type */
+const LVA_ORDER: [i32; 6] = [0, 1, 1, 2, 3, 4];
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Limits {
+    pub depth: Option<usize>,
+    pub nodes: Option<u64>,
+    pub movetime: Option<u64>,
+    pub wtime: Option<u64>,
+    pub btime: Option<u64>,
+    pub winc: Option<u64>,
+    pub binc: Option<u64>,
+    pub movestogo: Option<u64>,
+    pub infinite: bool,
+    pub overhead: u64, /* Move overhead in milliseconds, set by the front-end */
+}
+
+#[derive(Clone, Debug)]
+pub struct Info {
+    pub depth: usize,
+    pub score: i32,
+    pub nodes: u64,
+    pub time: u64, /* Elapsed milliseconds */
+    pub hashfull: usize,
+    pub pv: Vec<Move>,
+}
+
+impl Info {
+    pub fn nps(&self) -> u64 {
+        self.nodes * 1000 / self.time.max(1)
+    }
+
+    pub fn to_uci(&self) -> String {
+        let pv: Vec<String> = self.pv.iter().map(|m| m.to_uci()).collect();
+
+        format!(
+            "info depth {} score {} nodes {} nps {} time {} hashfull {} pv {}",
+            self.depth,
+            score_to_uci(self.score),
+            self.nodes,
+            self.nps(),
+            self.time,
+            self.hashfull,
+            pv.join(" ")
+        )
+    }
+}
+
+/* MVV-LVA ordering key for captures and promotions */
+pub fn mvv_lva(p: &Position, m: Move) -> i32 {
+    let attacker = p.board().piece_at(m.src()).unwrap().get_type();
+    let victim = p.captured_type(m).map_or(0, |t| SEE_VALUE[t as usize]);
+    let promotion = m.ptype().map_or(0, |t| SEE_VALUE[t as usize]);
+
+    (victim + promotion) * 8 - LVA_ORDER[attacker as usize]
+}
+
+/* Formats a score as either 'cp <x>' or 'mate <moves>' */
+pub fn score_to_uci(score: i32) -> String {
+    if score >= MATE_BOUND {
+        format!("mate {}", (MATE - score + 1) / 2)
+    } else if score <= -MATE_BOUND {
+        format!("mate -{}", (MATE + score) / 2)
+    } else {
+        format!("cp {}", score)
+    }
+}
+
+/* Result of the last completed iteration */
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Completed {
+    pub best: Option<Move>,
+    pub score: i32,
+    pub depth: usize,
+}
+
+pub struct Search {
+    id: usize, /* Thread index, 0 is the main thread */
+    stop: Arc<AtomicBool>,
+    tt: Arc<TranspositionTable>,
+    counter: Arc<AtomicU64>, /* Node count shared by all threads */
+    limits: Limits,
+    tm: TimeManager,
+    nodes: u64,
+    aborted: bool,
+    completed: Completed,
+    pv: Vec<Vec<Move>>, /* Triangular PV table, indexed by ply */
+    pawns: PawnTable,
+    heuristics: Heuristics,
+}
+
+impl Search {
+    pub fn new(
+        id: usize,
+        stop: Arc<AtomicBool>,
+        tt: Arc<TranspositionTable>,
+        counter: Arc<AtomicU64>,
+    ) -> Search {
+        Search {
+            id,
+            stop,
+            tt,
+            counter,
+            limits: Limits::default(),
+            tm: TimeManager::new(&Limits::default(), Color::WHITE),
+            nodes: 0,
+            aborted: false,
+            completed: Completed {
+                best: None,
+                score: 0,
+                depth: 0,
+            },
+            pv: vec![Vec::new(); MAX_PLY + 1],
+            pawns: PawnTable::default(),
+            heuristics: Heuristics::new(),
+        }
+    }
+
+    pub fn completed(&self) -> Completed {
+        self.completed
+    }
+
+    /* Nodes searched by this thread in the last `go` */
+    pub fn nodes(&self) -> u64 {
+        self.nodes
+    }
+
+    /* Total nodes across all threads: published batches plus our unpublished remainder */
+    fn total_nodes(&self) -> u64 {
+        self.counter.load(Ordering::Relaxed) + (self.nodes & (CHECK_INTERVAL - 1))
+    }
+
+    fn count_node(&mut self) {
+        self.nodes += 1;
+
+        if self.nodes & (CHECK_INTERVAL - 1) == 0 {
+            self.counter.fetch_add(CHECK_INTERVAL, Ordering::Relaxed);
+        }
+    }
+
+    pub fn go<F>(&mut self, p: &mut Position, limits: Limits, mut report: F) -> Option<Move>
+    where
+        F: FnMut(&Info),
+    {
+        self.tm = TimeManager::new(&limits, p.ctm());
+        self.limits = limits;
+        self.nodes = 0;
+        self.aborted = false;
+        self.completed = Completed {
+            best: None,
+            score: 0,
+            depth: 0,
+        };
+
+        let max_depth = self.limits.depth.unwrap_or(MAX_PLY - 1).min(MAX_PLY - 1);
+        let mut best: Option<Move> = None;
+
+        for depth in 1..=max_depth {
+            /* Helpers skip some depths so threads spread over different iterations */
+            if self.id > 0 && depth > 1 {
+                let i = (self.id - 1) % SKIP_SIZE.len();
+
+                if (depth + SKIP_PHASE[i]) / SKIP_SIZE[i] % 2 == 1 {
+                    continue;
+                }
+            }
+
+            let score = self.negamax(p, depth, 0, -INFINITY, INFINITY);
+
+            /* Partial iterations are only trusted when nothing else is available */
+            if self.aborted && best.is_some() {
+                break;
+            }
+
+            if let Some(m) = self.pv[0].first() {
+                best = Some(*m);
+            }
+
+            if self.aborted {
+                break;
+            }
+
+            self.completed = Completed { best, score, depth };
+
+            report(&Info {
+                depth,
+                score,
+                nodes: self.total_nodes(),
+                time: self.tm.elapsed(),
+                hashfull: self.tt.hashfull(),
+                pv: self.pv[0].clone(),
+            });
+
+            /* Stop once a forced mate is fully resolved */
+            if !self.limits.infinite && MATE - score.abs() < depth as i32 {
+                break;
+            }
+
+            if let Some(m) = best {
+                self.tm.update(m, score);
+            }
+
+            if self.tm.soft_exceeded() {
+                break;
+            }
+        }
+
+        if best.is_none() {
+            best = p.gen_legal_moves().first().cloned();
+        }
+
+        debug!(
+            "Thread {} pawn table: {} probes, {} hits ({:.1}%)",
+            self.id,
+            self.pawns.probes(),
+            self.pawns.hits(),
+            100.0 * self.pawns.hit_rate()
+        );
+
+        best
+    }
+
+    fn should_stop(&mut self) -> bool {
+        if self.aborted {
+            return true;
+        }
+
+        /* The node limit applies to all threads together */
+        if let Some(n) = self.limits.nodes {
+            if self.total_nodes() >= n {
+                self.aborted = true;
+            }
+        }
+
+        if self.nodes & (CHECK_INTERVAL - 1) == 0 {
+            if self.stop.load(Ordering::Relaxed) {
+                self.aborted = true;
+            }
+
+            if self.tm.hard_exceeded() {
+                self.aborted = true;
+            }
+        }
+
+        self.aborted
+    }
+
+    fn negamax(
+        &mut self,
+        p: &mut Position,
+        depth: usize,
+        ply: usize,
+        mut alpha: i32,
+        beta: i32,
+    ) -> i32 {
+        self.pv[ply].clear();
+
+        if self.should_stop() {
+            return 0;
+        }
+
+        if ply > 0 && p.is_draw() {
+            return 0;
+        }
+
+        if depth == 0 || ply >= MAX_PLY - 1 {
+            return self.quiesce(p, ply, alpha, beta);
+        }
+
+        self.count_node();
+
+        let pv_node = beta - alpha > 1;
+        let mut hash_move: Option<Move> = None;
+
+        if let Some(e) = self.tt.probe(p.key()) {
+            hash_move = e.mv;
+
+            if !pv_node && e.depth as usize >= depth {
+                let score = score_from_tt(e.score, ply);
+
+                match e.bound {
+                    Bound::EXACT => return score,
+                    Bound::LOWER if score >= beta => return score,
+                    Bound::UPPER if score <= alpha => return score,
+                    _ => (),
+                }
+            }
+        }
+
+        let mut picker = MovePicker::new(p, hash_move, &self.heuristics, ply);
+        let mut quiets: Vec<Move> = Vec::new();
+
+        let alpha_orig = alpha;
+        let mut legal = 0;
+        let mut best = -INFINITY;
+        let mut best_move: Option<Move> = None;
+
+        while let Some(m) = picker.next(p, &self.heuristics) {
+            let quiet = !movepick::is_noisy(p, m);
+
+            if !p.make_move(m) {
+                p.unmake_move(m);
+                continue;
+            }
+
+            legal += 1;
+
+            let score = if legal == 1 {
+                -self.negamax(p, depth - 1, ply + 1, -beta, -alpha)
+            } else {
+                /* Null-window probe, re-search on fail high */
+                let s = -self.negamax(p, depth - 1, ply + 1, -alpha - 1, -alpha);
+
+                if s > alpha && s < beta {
+                    -self.negamax(p, depth - 1, ply + 1, -beta, -alpha)
+                } else {
+                    s
+                }
+            };
+
+            p.unmake_move(m);
+
+            if self.aborted {
+                return 0;
+            }
+
+            if score > best {
+                best = score;
+                best_move = Some(m);
+
+                if score > alpha {
+                    alpha = score;
+
+                    let (head, tail) = self.pv.split_at_mut(ply + 1);
+                    head[ply].clear();
+                    head[ply].push(m);
+                    head[ply].extend_from_slice(&tail[0]);
+
+                    if alpha >= beta {
+                        if quiet {
+                            self.heuristics.update(p, ply, depth, m, &quiets);
+                        }
+
+                        break;
+                    }
+                }
+            }
+
+            if quiet {
+                quiets.push(m);
+            }
+        }
+
+        if legal == 0 {
+            return if p.in_check() { -MATE + ply as i32 } else { 0 };
+        }
+
+        let bound = if best >= beta {
+            Bound::LOWER
+        } else if alpha > alpha_orig {
+            Bound::EXACT
+        } else {
+            Bound::UPPER
+        };
+
+        self.tt.store(
+            p.key(),
+            depth as u8,
+            bound,
+            score_to_tt(best, ply),
+            best_move,
+        );
+
+        best
+    }
+
+    /* Searches captures and promotions (or all evasions when in check) until quiet */
+    fn quiesce(&mut self, p: &mut Position, ply: usize, mut alpha: i32, beta: i32) -> i32 {
+        if self.should_stop() {
+            return 0;
+        }
+
+        self.count_node();
+
+        let in_check = p.in_check();
+
+        if ply >= MAX_PLY - 1 {
+            return eval::evaluate(p, &mut self.pawns);
+        }
+
+        let mut best = -INFINITY;
+        let mut moves = p.gen_pseudolegal_moves();
+
+        if !in_check {
+            let stand_pat = eval::evaluate(p, &mut self.pawns);
+
+            if stand_pat >= beta {
+                return stand_pat;
+            }
+
+            if stand_pat > alpha {
+                alpha = stand_pat;
+            }
+
+            best = stand_pat;
+
+            /* Underpromotions are left to the main search */
+            moves.retain(|m| match m.ptype() {
+                Some(t) => t == Type::QUEEN,
+                None => p.is_capture(*m),
+            });
+        }
+
+        moves.sort_by_key(|m| -mvv_lva(p, *m));
+
+        /* Victim values tapered like the evaluation, and whether delta pruning is safe */
+        let (values, delta) = params::with(|params| {
+            let b = p.board();
+            let phase = eval::phase(b);
+            let mut values = [0; 6];
+
+            for (t, v) in values.iter_mut().enumerate() {
+                *v = params.material(Type::from_index(t).unwrap()).taper(phase);
+            }
+
+            let material = b.material(p.ctm().flip(), params).taper(phase);
+
+            (values, !in_check && material > DELTA_MIN_MATERIAL)
+        });
+
+        let mut legal = 0;
+
+        for m in moves {
+            /* Delta pruning: skip captures that cannot raise alpha */
+            if delta && m.ptype().is_none() {
+                let gain = p.captured_type(m).map_or(0, |t| values[t as usize]);
+
+                if best + gain + DELTA_MARGIN <= alpha {
+                    continue;
+                }
+            }
+
+            if !p.make_move(m) {
+                p.unmake_move(m);
+                continue;
+            }
+
+            legal += 1;
+
+            let score = -self.quiesce(p, ply + 1, -beta, -alpha);
+
+            p.unmake_move(m);
+
+            if self.aborted {
+                return 0;
+            }
+
+            if score > best {
+                best = score;
+
+                if score > alpha {
+                    alpha = score;
+
+                    if alpha >= beta {
+                        break;
+                    }
+                }
+            }
+        }
+
+        if in_check && legal == 0 {
+            return -MATE + ply as i32;
+        }
+
+        best
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn searcher() -> Search {
+        Search::new(
+            0,
+            Arc::new(AtomicBool::new(false)),
+            Arc::new(TranspositionTable::new(1)),
+            Arc::new(AtomicU64::new(0)),
+        )
+    }
+
+    fn search(fen: &str, depth: usize) -> (Option<Move>, Info) {
+        let mut p = Position::new(fen.to_string()).unwrap();
+        let mut s = searcher();
+        let mut last: Option<Info> = None;
+
+        let best = s.go(
+            &mut p,
+            Limits {
+                depth: Some(depth),
+                ..Limits::default()
+            },
+            |i| last = Some(i.clone()),
+        );
+
+        (best, last.unwrap())
+    }
+
+    #[test]
+    fn search_score_to_uci_works() {
+        assert_eq!(score_to_uci(35), "cp 35");
+        assert_eq!(score_to_uci(MATE - 1), "mate 1");
+        assert_eq!(score_to_uci(MATE - 3), "mate 2");
+        assert_eq!(score_to_uci(-MATE + 2), "mate -1");
+    }
+
+    #[test]
+    fn search_finds_mate_in_one() {
+        let (best, info) = search("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1", 3);
+
+        assert_eq!(best.unwrap().to_uci(), "a1a8");
+        assert_eq!(info.score, MATE - 1);
+    }
+
+    #[test]
+    fn search_finds_mate_in_two() {
+        let (best, info) = search("k7/8/1K6/8/8/8/8/1R6 w - - 0 1", 4);
+
+        assert_eq!(info.score, MATE - 3);
+        assert!(best.is_some());
+        assert_eq!(info.pv.len(), 3);
+    }
+
+    #[test]
+    fn search_stalemate_is_draw() {
+        let mut p = Position::new("7k/5Q2/6K1/8/8/8/8/8 b - - 0 1".to_string()).unwrap();
+        let mut s = searcher();
+
+        assert_eq!(s.negamax(&mut p, 2, 0, -INFINITY, INFINITY), 0);
+    }
+
+    #[test]
+    fn search_leaves_position_unchanged() {
+        let fen = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1";
+        let mut p = Position::new(fen.to_string()).unwrap();
+        let mut s = searcher();
+
+        s.go(
+            &mut p,
+            Limits {
+                depth: Some(2),
+                ..Limits::default()
+            },
+            |_| {},
+        );
+
+        assert_eq!(p.to_fen(), fen);
+    }
+
+    fn quiesce(fen: &str) -> i32 {
+        let mut p = Position::new(fen.to_string()).unwrap();
+        let mut s = searcher();
+
+        s.quiesce(&mut p, 0, -INFINITY, INFINITY)
+    }
+
+    #[test]
+    fn search_quiesce_takes_hanging_piece() {
+        let after = Position::new("4k3/8/8/3R4/8/8/8/4K3 b - - 0 1".to_string()).unwrap();
+
+        assert_eq!(
+            quiesce("4k3/8/8/3q4/8/8/8/3RK3 w - - 0 1"),
+            -eval::evaluate(&after, &mut PawnTable::default())
+        );
+    }
+
+    #[test]
+    fn search_quiesce_avoids_defended_piece() {
+        let fen = "4k3/4p3/3n4/8/8/8/8/3QK3 w - - 0 1";
+        let p = Position::new(fen.to_string()).unwrap();
+
+        assert_eq!(quiesce(fen), eval::evaluate(&p, &mut PawnTable::default()));
+    }
+
+    #[test]
+    fn search_quiesce_promotes() {
+        let after = Position::new("Q7/4k3/8/8/8/8/8/4K3 b - - 0 1".to_string()).unwrap();
+
+        assert_eq!(
+            quiesce("8/P3k3/8/8/8/8/8/4K3 w - - 0 1"),
+            -eval::evaluate(&after, &mut PawnTable::default())
+        );
+    }
+
+    #[test]
+    fn search_quiesce_detects_mate_in_check() {
+        assert_eq!(quiesce("R5k1/5ppp/8/8/8/8/8/6K1 b - - 0 1"), -MATE);
+    }
+
+    #[test]
+    fn search_avoids_poisoned_pawn() {
+        let (best, info) = search("4k3/8/4p3/3p4/8/8/8/3QK3 w - - 0 1", 1);
+
+        assert_ne!(best.unwrap().to_uci(), "d1d5");
+        assert!(info.score > 0);
+    }
+
+    #[test]
+    fn search_mvv_lva_orders_captures() {
+        let p = Position::new("4k3/8/2r1q3/3P4/8/8/8/4K3 w - - 0 1".to_string()).unwrap();
+        let pxq = Move::from_uci(&"d5e6".to_string()).unwrap();
+        let pxr = Move::from_uci(&"d5c6".to_string()).unwrap();
+
+        assert!(mvv_lva(&p, pxq) > mvv_lva(&p, pxr));
+    }
+
+    #[test]
+    fn search_respects_node_limit() {
+        let mut p = Position::new(STARTPOS.to_string()).unwrap();
+        let mut s = searcher();
+
+        let best = s.go(
+            &mut p,
+            Limits {
+                nodes: Some(5000),
+                ..Limits::default()
+            },
+            |_| {},
+        );
+
+        assert!(best.is_some());
+        assert!(s.nodes <= 5000);
+    }
+}
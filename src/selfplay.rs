@@ -0,0 +1,425 @@
+use crate::encode;
+use crate::eval::Classical;
+use crate::gmove::*;
+use crate::mcts::{self, Evaluator, Mcts};
+use crate::nn::*;
+use crate::piece::*;
+use crate::policy;
+use crate::position::*;
+use crate::record::*;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use std::io;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+
+#[derive(Copy, Clone, Debug)]
+pub struct Config {
+    pub playouts: usize,
+    pub temperature_plies: usize, /* Plies played proportionally to visits */
+    pub noise_alpha: f32,
+    pub noise_epsilon: f32,
+    pub resign_threshold: f32, /* Root value below which the side to move resigns */
+    pub resign_playthrough: f32, /* Fraction of games where resigning is disabled */
+    pub max_plies: usize,
+    pub seed: u64,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            playouts: 800,
+            temperature_plies: 30,
+            noise_alpha: 0.3,
+            noise_epsilon: 0.25,
+            resign_threshold: -0.9,
+            resign_playthrough: 0.1,
+            max_plies: 512,
+            seed: 0,
+        }
+    }
+}
+
+pub struct Game {
+    pub start: Position,
+    pub moves: Vec<Move>,
+    pub samples: Vec<Sample>,
+    pub winner: Option<Color>,
+    pub plies: usize,
+    pub resigned: bool,
+    pub playthrough: bool,  /* Resigning was disabled for this game */
+    pub false_resign: bool, /* A playthrough game where the would-be resigner did not lose */
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Stats {
+    pub games: usize,
+    pub positions: usize,
+    pub white_wins: usize,
+    pub black_wins: usize,
+    pub draws: usize,
+    pub resigned: usize,
+    pub playthroughs: usize,
+    pub false_resigns: usize,
+}
+
+impl Stats {
+    fn add(&mut self, g: &Game) {
+        self.games += 1;
+        self.positions += g.samples.len();
+
+        match g.winner {
+            Some(Color::WHITE) => self.white_wins += 1,
+            Some(Color::BLACK) => self.black_wins += 1,
+            None => self.draws += 1,
+        }
+
+        self.resigned += g.resigned as usize;
+        self.playthroughs += g.playthrough as usize;
+        self.false_resigns += g.false_resign as usize;
+    }
+}
+
+/* Picks a move proportionally to visit counts, or the most visited one */
+pub fn choose<R: Rng>(visits: &[(Move, u32)], rng: &mut R, proportional: bool) -> Move {
+    let total: u32 = visits.iter().map(|v| v.1).sum();
+
+    if proportional && total > 0 {
+        let mut pick = rng.gen_range(0, total);
+
+        for (m, n) in visits.iter() {
+            if pick < *n {
+                return *m;
+            }
+
+            pick -= n;
+        }
+    }
+
+    let mut best = visits[0];
+
+    for v in visits.iter().skip(1) {
+        if v.1 > best.1 {
+            best = *v;
+        }
+    }
+
+    best.0
+}
+
+/* Plays a single game of the evaluator against itself */
+pub fn play_game<R: Rng>(eval: Arc<dyn Evaluator>, config: &Config, rng: &mut R) -> Game {
+    let start = Position::new(STARTPOS.to_string()).unwrap();
+    play([eval.clone(), eval], &start, config, rng)
+}
+
+/* Plays a game from `start`, with `players` indexed by colour */
+pub fn play<R: Rng>(
+    players: [Arc<dyn Evaluator>; 2],
+    start: &Position,
+    config: &Config,
+    rng: &mut R,
+) -> Game {
+    let [white, black] = players;
+    let mut trees = [
+        Mcts::new(white, mcts::Config::default()),
+        Mcts::new(black, mcts::Config::default()),
+    ];
+    let mut pos = start.clone();
+    let mut moves = Vec::new();
+    let mut samples: Vec<(Sample, Color)> = Vec::new();
+
+    let playthrough = rng.gen::<f32>() < config.resign_playthrough;
+    let mut would_resign: Option<Color> = None;
+    let mut resigned = false;
+
+    let winner = loop {
+        if pos.gen_legal_moves().is_empty() {
+            break if pos.in_check() {
+                Some(pos.ctm().flip())
+            } else {
+                None
+            };
+        }
+
+        if pos.is_draw() || samples.len() >= config.max_plies {
+            break None;
+        }
+
+        let tree = &mut trees[pos.ctm() as usize];
+
+        tree.advance(&pos);
+
+        if config.noise_epsilon > 0.0 {
+            tree.add_noise(rng, config.noise_alpha, config.noise_epsilon);
+        }
+
+        tree.search(config.playouts.saturating_sub(tree.playouts() as usize));
+
+        if tree.root_value() < config.resign_threshold {
+            if !playthrough {
+                resigned = true;
+                break Some(pos.ctm().flip());
+            }
+
+            would_resign = would_resign.or(Some(pos.ctm()));
+        }
+
+        let visits = tree.root_visits();
+        let total: u32 = visits.iter().map(|v| v.1).sum::<u32>().max(1);
+
+        samples.push((
+            Sample {
+                planes: encode::encode(&pos),
+                policy: visits
+                    .iter()
+                    .filter(|v| v.1 > 0)
+                    .map(|(m, n)| {
+                        let i = policy::encode(pos.ctm(), *m).unwrap();
+                        (i as u16, *n as f32 / total as f32)
+                    })
+                    .collect(),
+                result: 0.0,
+            },
+            pos.ctm(),
+        ));
+
+        let proportional = samples.len() <= config.temperature_plies;
+        let m = choose(&visits, rng, proportional);
+
+        pos.make_move(m);
+        moves.push(m);
+    };
+
+    let plies = samples.len();
+
+    Game {
+        start: start.clone(),
+        moves,
+        samples: samples
+            .into_iter()
+            .map(|(mut s, c)| {
+                s.result = match winner {
+                    Some(w) if w == c => 1.0,
+                    Some(_) => -1.0,
+                    None => 0.0,
+                };
+                s
+            })
+            .collect(),
+        winner,
+        plies,
+        resigned,
+        playthrough,
+        false_resign: would_resign.is_some_and(|c| winner != Some(c.flip())),
+    }
+}
+
+/* Plays `games` games on `threads` threads, writing every sample to a record file */
+pub fn run(
+    eval: Arc<dyn Evaluator>,
+    config: &Config,
+    games: usize,
+    threads: usize,
+    path: &str,
+) -> io::Result<Stats> {
+    let mut out = Writer::create(path, true)?;
+    let mut stats = Stats::default();
+    let next = AtomicUsize::new(0);
+    let (tx, rx) = mpsc::channel::<Game>();
+
+    thread::scope(|sc| {
+        for _ in 0..threads.max(1) {
+            let tx = tx.clone();
+            let eval = eval.clone();
+            let next = &next;
+
+            sc.spawn(move || loop {
+                let i = next.fetch_add(1, Ordering::Relaxed);
+
+                if i >= games {
+                    break;
+                }
+
+                let mut rng = StdRng::seed_from_u64(config.seed.wrapping_add(i as u64));
+
+                if tx.send(play_game(eval.clone(), config, &mut rng)).is_err() {
+                    break;
+                }
+            });
+        }
+
+        drop(tx);
+
+        for g in rx.iter() {
+            for s in g.samples.iter() {
+                out.write(s)?;
+            }
+
+            stats.add(&g);
+
+            info!(
+                "Game {}/{}: {} in {} plies{}",
+                stats.games,
+                games,
+                match g.winner {
+                    Some(Color::WHITE) => "1-0",
+                    Some(Color::BLACK) => "0-1",
+                    None => "1/2-1/2",
+                },
+                g.plies,
+                if g.resigned { " (resigned)" } else { "" }
+            );
+        }
+
+        Ok::<(), io::Error>(())
+    })?;
+
+    out.finish()?;
+
+    Ok(stats)
+}
+
+/* Loads an evaluator from "classical", "uniform" or a weights file path */
+pub fn evaluator(spec: &str) -> io::Result<Arc<dyn Evaluator>> {
+    Ok(match spec {
+        "classical" => Arc::new(Classical::new()),
+        "uniform" => Arc::new(mcts::Uniform),
+        path => Arc::new(Network::load(path)?),
+    })
+}
+
+/* Entry point for `nc selfplay <output.rec> <games> [evaluator]` */
+pub fn cli(args: &[String]) {
+    if args.len() < 2 || args.len() > 3 {
+        error!("usage: nc selfplay <output.rec> <games> [classical|uniform|weights]");
+        return;
+    }
+
+    let games = match args[1].parse::<usize>() {
+        Ok(g) => g,
+        Err(_) => {
+            error!("Invalid game count '{}'", args[1]);
+            return;
+        }
+    };
+
+    let spec = args.get(2).map_or("classical", |s| s.as_str());
+    let eval = match evaluator(spec) {
+        Ok(e) => e,
+        Err(e) => {
+            error!("Failed to load evaluator '{}': {}", spec, e);
+            return;
+        }
+    };
+
+    let threads = thread::available_parallelism().map_or(1, |n| n.get());
+    let config = Config {
+        seed: rand::thread_rng().gen(),
+        ..Config::default()
+    };
+
+    match run(eval, &config, games, threads, &args[0]) {
+        Ok(s) => info!("Self-play finished: {:?}", s),
+        Err(e) => error!("Self-play failed: {}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn quick() -> Config {
+        Config {
+            playouts: 16,
+            max_plies: 40,
+            resign_playthrough: 0.0,
+            ..Config::default()
+        }
+    }
+
+    #[test]
+    fn selfplay_game_samples_are_consistent() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let g = play_game(Arc::new(mcts::Uniform), &quick(), &mut rng);
+
+        assert_eq!(g.samples.len(), g.plies);
+        assert!(g.plies <= 40);
+
+        for (i, s) in g.samples.iter().enumerate() {
+            let total: f32 = s.policy.iter().map(|p| p.1).sum();
+
+            assert_eq!(s.planes.len(), encode::INPUT_SIZE);
+            assert!((total - 1.0).abs() < 1e-4);
+
+            /* Results alternate in sign with the side to move */
+            if let Some(w) = g.winner {
+                let ctm = if i % 2 == 0 {
+                    Color::WHITE
+                } else {
+                    Color::BLACK
+                };
+                assert_eq!(s.result, if ctm == w { 1.0 } else { -1.0 });
+            } else {
+                assert_eq!(s.result, 0.0);
+            }
+        }
+    }
+
+    /* White is always lost, black is always winning */
+    struct WhiteLoses;
+
+    impl Evaluator for WhiteLoses {
+        fn evaluate(&self, p: &Position, moves: &[Move]) -> (Vec<f32>, f32) {
+            let v = match p.ctm() {
+                Color::WHITE => -1.0,
+                Color::BLACK => 1.0,
+            };
+
+            (vec![1.0; moves.len()], v)
+        }
+    }
+
+    #[test]
+    fn selfplay_resignation_works() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let g = play_game(Arc::new(WhiteLoses), &quick(), &mut rng);
+
+        assert!(g.resigned);
+        assert_eq!(g.winner, Some(Color::BLACK));
+        assert_eq!(g.plies, 0);
+
+        /* Played out, the game is drawn by the ply limit: a false resign */
+        let config = Config {
+            resign_playthrough: 1.0,
+            ..quick()
+        };
+
+        let g = play_game(Arc::new(WhiteLoses), &config, &mut rng);
+
+        assert!(g.playthrough && !g.resigned);
+        assert_eq!(g.winner, None);
+        assert!(g.false_resign);
+    }
+
+    #[test]
+    fn selfplay_run_writes_samples() {
+        let path = std::env::temp_dir().join("nc_selfplay_test.rec");
+        let path = path.to_str().unwrap();
+
+        let stats = run(Arc::new(mcts::Uniform), &quick(), 3, 2, path).unwrap();
+
+        assert_eq!(stats.games, 3);
+        assert_eq!(
+            stats.white_wins + stats.black_wins + stats.draws,
+            stats.games
+        );
+
+        assert_eq!(Reader::open(path).unwrap().len(), stats.positions);
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
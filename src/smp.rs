@@ -0,0 +1,236 @@
+use crate::gmove::*;
+use crate::position::*;
+use crate::search::*;
+use crate::tt::*;
+
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+
+pub const DEFAULT_THREADS: usize = 1;
+pub const MAX_THREADS: usize = 256;
+
+/* Vote bias so that the worst scoring thread still counts */
+const VOTE_BIAS: i64 = 14;
+
+/*
+ * Lazy SMP: every thread searches its own copy of the position and shares only
+ * the transposition table. The main thread runs on the caller, watches `stop`
+ * and is the only one to report; helpers are stopped through a flag of their
+ * own once it finishes, so `stop` is left to the caller.
+ */
+pub fn go<F>(
+    p: &Position,
+    limits: Limits,
+    threads: usize,
+    stop: Arc<AtomicBool>,
+    tt: Arc<TranspositionTable>,
+    report: F,
+) -> Option<Move>
+where
+    F: FnMut(&Info),
+{
+    let counter = Arc::new(AtomicU64::new(0));
+    let done = Arc::new(AtomicBool::new(false));
+
+    tt.new_search();
+
+    thread::scope(|sc| {
+        let helpers: Vec<_> = (1..threads.max(1))
+            .map(|id| {
+                let mut pos = p.clone();
+                let limits = limits.clone();
+                let mut s = Search::new(id, done.clone(), tt.clone(), counter.clone());
+
+                sc.spawn(move || {
+                    s.go(&mut pos, limits, |_| {});
+                    s.completed()
+                })
+            })
+            .collect();
+
+        let mut pos = p.clone();
+        let mut main = Search::new(0, stop, tt.clone(), counter.clone());
+        let fallback = main.go(&mut pos, limits, report);
+
+        done.store(true, Ordering::Relaxed);
+
+        let mut results = vec![main.completed()];
+        results.extend(helpers.into_iter().map(|h| h.join().unwrap()));
+
+        vote(&results).or(fallback)
+    })
+}
+
+/* Picks the move backed by the most (score, depth)-weighted thread votes */
+pub fn vote(results: &[Completed]) -> Option<Move> {
+    let min_score = results
+        .iter()
+        .filter(|r| r.best.is_some())
+        .map(|r| r.score)
+        .min()?;
+
+    let mut votes: Vec<(Move, i64)> = Vec::new();
+
+    for r in results.iter() {
+        if let Some(m) = r.best {
+            let weight = (r.score - min_score) as i64 + VOTE_BIAS;
+            let weight = weight * r.depth as i64;
+
+            match votes.iter_mut().find(|(v, _)| *v == m) {
+                Some((_, w)) => *w += weight,
+                None => votes.push((m, weight)),
+            }
+        }
+    }
+
+    /* Ties go to the earliest thread, so the main thread wins by default */
+    let mut best = votes[0];
+
+    for v in votes.iter().skip(1) {
+        if v.1 > best.1 {
+            best = *v;
+        }
+    }
+
+    Some(best.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(fen: &str, depth: usize, threads: usize) -> (Option<Move>, Vec<String>) {
+        let p = Position::new(fen.to_string()).unwrap();
+        let mut infos = Vec::new();
+
+        let best = go(
+            &p,
+            Limits {
+                depth: Some(depth),
+                ..Limits::default()
+            },
+            threads,
+            Arc::new(AtomicBool::new(false)),
+            Arc::new(TranspositionTable::new(1)),
+            |i| infos.push(format!("{} {} {} {:?}", i.depth, i.score, i.nodes, i.pv)),
+        );
+
+        (best, infos)
+    }
+
+    #[test]
+    fn smp_single_thread_is_deterministic() {
+        let fen = "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1";
+        let p = Position::new(fen.to_string()).unwrap();
+        let limits = Limits {
+            depth: Some(5),
+            ..Limits::default()
+        };
+
+        let mut nodes = Vec::new();
+
+        for _ in 0..2 {
+            let mut s = Search::new(
+                0,
+                Arc::new(AtomicBool::new(false)),
+                Arc::new(TranspositionTable::new(1)),
+                Arc::new(AtomicU64::new(0)),
+            );
+
+            s.go(&mut p.clone(), limits.clone(), |_| {});
+            nodes.push(s.completed());
+        }
+
+        assert_eq!(nodes[0], nodes[1]);
+        assert_eq!(run(fen, 5, 1), run(fen, 5, 1));
+    }
+
+    #[test]
+    fn smp_single_thread_node_count_is_fixed() {
+        /* Hash keys are seeded, so the count is the same in every process */
+        let mut p = Position::new(STARTPOS.to_string()).unwrap();
+        let mut s = Search::new(
+            0,
+            Arc::new(AtomicBool::new(false)),
+            Arc::new(TranspositionTable::new(1)),
+            Arc::new(AtomicU64::new(0)),
+        );
+
+        s.go(
+            &mut p,
+            Limits {
+                depth: Some(5),
+                ..Limits::default()
+            },
+            |_| {},
+        );
+
+        assert_eq!(s.nodes(), 14122);
+    }
+
+    #[test]
+    fn smp_node_limit_is_shared() {
+        let p = Position::new(STARTPOS.to_string()).unwrap();
+        let counter = Arc::new(AtomicU64::new(0));
+        let limits = Limits {
+            nodes: Some(20000),
+            ..Limits::default()
+        };
+
+        let threads = 4;
+        let total: u64 = thread::scope(|sc| {
+            let searches: Vec<_> = (0..threads)
+                .map(|id| {
+                    let mut pos = p.clone();
+                    let limits = limits.clone();
+                    let mut s = Search::new(
+                        id,
+                        Arc::new(AtomicBool::new(false)),
+                        Arc::new(TranspositionTable::new(1)),
+                        counter.clone(),
+                    );
+
+                    sc.spawn(move || {
+                        s.go(&mut pos, limits, |_| {});
+                        s.nodes()
+                    })
+                })
+                .collect();
+
+            searches.into_iter().map(|h| h.join().unwrap()).sum()
+        });
+
+        /* Each thread may overshoot by its unpublished batch at most */
+        assert!(total >= 20000, "{}", total);
+        assert!(total <= 20000 + threads as u64 * 1024, "{}", total);
+    }
+
+    #[test]
+    fn smp_helpers_find_mate() {
+        let (best, _) = run("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1", 3, 4);
+
+        assert_eq!(best.unwrap().to_uci(), "a1a8");
+    }
+
+    #[test]
+    fn smp_vote_works() {
+        let a = Move::from_uci(&"e2e4".to_string());
+        let b = Move::from_uci(&"d2d4".to_string());
+
+        let r = |best, score, depth| Completed { best, score, depth };
+
+        assert_eq!(vote(&[]), None);
+        assert_eq!(vote(&[r(None, 0, 0)]), None);
+        assert_eq!(vote(&[r(a, 10, 5)]), a);
+
+        /* Two agreeing threads outvote one */
+        assert_eq!(vote(&[r(a, 10, 5), r(b, 10, 5), r(b, 10, 5)]), b);
+
+        /* A clearly better score outweighs a single vote */
+        assert_eq!(vote(&[r(a, 10, 5), r(b, 200, 5), r(a, 10, 5)]), b);
+
+        /* Ties go to the main thread */
+        assert_eq!(vote(&[r(a, 10, 5), r(b, 10, 5)]), a);
+    }
+}
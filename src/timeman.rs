@@ -0,0 +1,212 @@
+use crate::gmove::*;
+use crate::piece::*;
+use crate::search::*;
+
+use std::time::Instant;
+
+pub const DEFAULT_OVERHEAD: u64 = 30;
+pub const MAX_OVERHEAD: u64 = 5000;
+
+/* Assumed moves left when the GUI does not send movestogo */
+const DEFAULT_MOVESTOGO: u64 = 30;
+
+/* Upper bound on moves to go, so the budget never becomes vanishingly small */
+const MAX_MOVESTOGO: u64 = 50;
+
+/* Hard limit as a multiple of the soft limit */
+const HARD_RATIO: u64 = 4;
+
+/* Score drop (cp) between iterations which buys more time */
+const SCORE_DROP: i32 = 30;
+
+pub struct TimeManager {
+    start: Instant,
+    soft: Option<u64>,
+    hard: Option<u64>,
+    stable: usize, /* Iterations the best move has been unchanged */
+    last_best: Option<Move>,
+    last_score: Option<i32>,
+    dropped: bool,
+}
+
+impl TimeManager {
+    pub fn new(limits: &Limits, ctm: Color) -> TimeManager {
+        let mut soft: Option<u64> = None;
+        let mut hard: Option<u64> = None;
+
+        let (time, inc) = match ctm {
+            Color::WHITE => (limits.wtime, limits.winc),
+            Color::BLACK => (limits.btime, limits.binc),
+        };
+
+        if !limits.infinite {
+            if let Some(t) = time {
+                let available = t.saturating_sub(limits.overhead).max(1);
+                let mtg = limits
+                    .movestogo
+                    .unwrap_or(DEFAULT_MOVESTOGO)
+                    .clamp(1, MAX_MOVESTOGO);
+
+                let base = available / mtg + inc.unwrap_or(0) * 3 / 4;
+                let h = (base * HARD_RATIO).min(available * 3 / 4).max(1);
+
+                soft = Some(base.min(h));
+                hard = Some(h);
+            }
+
+            /* A fixed movetime is a hard limit only */
+            if let Some(mt) = limits.movetime {
+                let mt = mt.saturating_sub(limits.overhead).max(1);
+
+                soft = None;
+                hard = Some(hard.map_or(mt, |h| h.min(mt)));
+            }
+        }
+
+        TimeManager {
+            start: Instant::now(),
+            soft,
+            hard,
+            stable: 0,
+            last_best: None,
+            last_score: None,
+            dropped: false,
+        }
+    }
+
+    pub fn elapsed(&self) -> u64 {
+        self.start.elapsed().as_millis() as u64
+    }
+
+    pub fn hard_exceeded(&self) -> bool {
+        self.hard.is_some_and(|h| self.elapsed() >= h)
+    }
+
+    /* Records the result of a completed iteration */
+    pub fn update(&mut self, best: Move, score: i32) {
+        if self.last_best == Some(best) {
+            self.stable += 1;
+        } else {
+            self.stable = 0;
+        }
+
+        self.dropped = self.last_score.is_some_and(|s| s - score > SCORE_DROP);
+        self.last_best = Some(best);
+        self.last_score = Some(score);
+    }
+
+    /* Soft limit scaled by best-move stability and score trend */
+    pub fn soft_limit(&self) -> Option<u64> {
+        let stability = match self.stable {
+            0 => 1.4,
+            1 => 1.0,
+            2 => 0.8,
+            _ => 0.65,
+        };
+
+        let trend = if self.dropped { 1.5 } else { 1.0 };
+
+        self.soft.map(|s| {
+            let scaled = (s as f64 * stability * trend) as u64;
+            self.hard.map_or(scaled, |h| scaled.min(h))
+        })
+    }
+
+    /* Tests if another iteration should be started */
+    pub fn soft_exceeded(&self) -> bool {
+        self.soft_limit().is_some_and(|s| self.elapsed() >= s)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limits(wtime: u64, winc: u64, movestogo: Option<u64>) -> Limits {
+        Limits {
+            wtime: Some(wtime),
+            btime: Some(1),
+            winc: Some(winc),
+            movestogo,
+            overhead: 0,
+            ..Limits::default()
+        }
+    }
+
+    #[test]
+    fn timeman_budget_works() {
+        let tm = TimeManager::new(&limits(60000, 0, None), Color::WHITE);
+
+        assert_eq!(tm.soft, Some(2000));
+        assert_eq!(tm.hard, Some(8000));
+
+        let tm = TimeManager::new(&limits(60000, 1000, Some(10)), Color::WHITE);
+
+        assert_eq!(tm.soft, Some(6750));
+        assert_eq!(tm.hard, Some(27000));
+    }
+
+    #[test]
+    fn timeman_uses_side_to_move_clock() {
+        let tm = TimeManager::new(&limits(60000, 0, None), Color::BLACK);
+
+        assert_eq!(tm.hard, Some(1));
+    }
+
+    #[test]
+    fn timeman_last_move_never_flags() {
+        let tm = TimeManager::new(&limits(1000, 0, Some(1)), Color::WHITE);
+
+        assert!(tm.hard.unwrap() < 1000);
+        assert!(tm.soft.unwrap() <= tm.hard.unwrap());
+    }
+
+    #[test]
+    fn timeman_respects_overhead() {
+        let mut l = limits(60000, 0, None);
+        l.overhead = 30000;
+
+        let tm = TimeManager::new(&l, Color::WHITE);
+
+        assert_eq!(tm.soft, Some(1000));
+
+        l.movetime = Some(500);
+        l.overhead = 100;
+
+        let tm = TimeManager::new(&l, Color::WHITE);
+
+        assert_eq!(tm.soft, None);
+        assert_eq!(tm.hard, Some(400));
+    }
+
+    #[test]
+    fn timeman_infinite_has_no_limits() {
+        let mut l = limits(60000, 0, None);
+        l.infinite = true;
+
+        let tm = TimeManager::new(&l, Color::WHITE);
+
+        assert_eq!(tm.soft_limit(), None);
+        assert!(!tm.hard_exceeded());
+    }
+
+    #[test]
+    fn timeman_stability_and_drops_scale_soft_limit() {
+        let mut tm = TimeManager::new(&limits(60000, 0, None), Color::WHITE);
+        let a = Move::from_uci(&"e2e4".to_string()).unwrap();
+        let b = Move::from_uci(&"d2d4".to_string()).unwrap();
+
+        tm.update(a, 20);
+        let unstable = tm.soft_limit().unwrap();
+
+        tm.update(a, 20);
+        tm.update(a, 20);
+        tm.update(a, 20);
+        let stable = tm.soft_limit().unwrap();
+
+        assert!(stable < unstable);
+
+        tm.update(b, -50);
+        assert!(tm.soft_limit().unwrap() > unstable);
+    }
+}
@@ -0,0 +1,275 @@
+use crate::bitboard;
+use crate::board::*;
+use crate::eval::{self, Score, MAX_PHASE, TEMPO};
+use crate::params::{self, Accumulator, Params, Sum};
+use crate::piece::*;
+use crate::position::*;
+use crate::uci;
+
+/* Name of the line holding the terms passed as constants, which are all king danger */
+pub const DANGER: &str = "king_danger";
+
+/*
+ * Breaks the evaluation down into one line per parameter group, with each
+ * colour's share from its own point of view. Terms counted for white arrive
+ * with a positive count and black's with a negative one; the only constant
+ * term is king danger, a penalty to the side whose king is under attack.
+ */
+pub struct Trace<'a> {
+    params: &'a Params,
+    pub lines: Vec<(&'static str, [Score; 2])>,
+}
+
+impl<'a> Trace<'a> {
+    pub fn new(params: &'a Params) -> Trace<'a> {
+        let mut lines: Vec<(&'static str, [Score; 2])> = params::LAYOUT
+            .iter()
+            .map(|(name, _, _)| (*name, [Score::default(); 2]))
+            .collect();
+
+        lines.push((DANGER, [Score::default(); 2]));
+
+        Trace { params, lines }
+    }
+
+    fn credit(&mut self, line: usize, white: bool, score: Score) {
+        if white {
+            self.lines[line].1[Color::WHITE as usize] += score;
+        } else {
+            self.lines[line].1[Color::BLACK as usize] -= score;
+        }
+    }
+
+    /* Sum of every line from white's perspective, before tapering */
+    pub fn total(&self) -> Score {
+        self.lines
+            .iter()
+            .fold(Score::default(), |t, (_, s)| t + s[0] - s[1])
+    }
+}
+
+impl<'a> Accumulator for Trace<'a> {
+    fn add(&mut self, index: usize, count: i32) {
+        let score = self.params.values[index] * count;
+
+        self.credit(params::group(index), count > 0, score);
+    }
+
+    fn constant(&mut self, score: Score) {
+        let line = self.lines.len() - 1;
+
+        self.credit(line, score.mg < 0 || score.eg < 0, score);
+    }
+}
+
+/* Classical score from white's perspective, tapered and without the tempo bonus */
+fn tapered(b: &Board, params: &Params) -> i32 {
+    let mut sum = Sum::new(params);
+
+    eval::accumulate(b, &mut sum);
+    sum.score.taper(eval::phase(b))
+}
+
+/*
+ * How much each piece is worth to white: the tapered score lost when it is
+ * taken off the board. Kings cannot be removed and are left at zero.
+ */
+pub fn heat_map(b: &Board, params: &Params) -> [Option<i32>; 64] {
+    let base = tapered(b, params);
+    let mut map = [None; 64];
+
+    bitboard::for_each(b.global_occ(), |s| {
+        map[s.index()] = Some(match b.piece_at(s).unwrap().get_type() {
+            Type::KING => 0,
+            _ => {
+                let mut without = b.clone();
+
+                without.set_network(None);
+                without.remove(s);
+                base - tapered(&without, params)
+            }
+        })
+    });
+
+    map
+}
+
+fn row(name: &str, s: [Score; 2]) -> String {
+    let total = s[0] - s[1];
+
+    format!(
+        "{:<16}| {:>6} {:>6} | {:>6} {:>6} | {:>6} {:>6}\n",
+        name, s[0].mg, s[0].eg, s[1].mg, s[1].eg, total.mg, total.eg
+    )
+}
+
+/* Renders the term breakdown, phase, final score and heat map for `p` */
+pub fn trace(p: &Position) -> String {
+    let b = p.board();
+
+    params::with(|params| {
+        let mut t = Trace::new(params);
+        let rule = format!("{0:-<16}+{0:-<15}+{0:-<15}+{0:-<14}\n", "");
+
+        eval::accumulate(b, &mut t);
+
+        let mut out = format!(
+            "{:<16}| {:^13} | {:^13} | {:^13}\n{:<16}| {:>6} {:>6} | {:>6} {:>6} | {:>6} {:>6}\n{}",
+            "Term", "White", "Black", "Total", "", "MG", "EG", "MG", "EG", "MG", "EG", rule
+        );
+
+        for (name, s) in t.lines.iter() {
+            out += &row(name, *s);
+        }
+
+        let total = t.total();
+        let phase = eval::phase(b);
+        let white = total.taper(phase);
+        let stm = match p.ctm() {
+            Color::WHITE => white,
+            Color::BLACK => -white,
+        };
+
+        out += &rule;
+        out += &format!(
+            "{:<16}| {:>13} | {:>13} | {:>6} {:>6}\n\n",
+            "Total", "", "", total.mg, total.eg
+        );
+        out += &format!("Phase: {}/{}\n", phase, MAX_PHASE);
+        out += &format!("Tapered score (white): {:+}\n", white);
+        out += &format!(
+            "Classical evaluation (side to move): {:+} with tempo {}\n",
+            stm + TEMPO,
+            TEMPO
+        );
+
+        if let Some(score) = b.nnue(p.ctm()) {
+            out += &format!("NNUE evaluation (side to move): {:+}\n", score);
+        }
+
+        let map = heat_map(b, params);
+        let grid = bitboard::to_grid(|s| match (b.piece_at(s), map[s.index()]) {
+            (Some(pc), _) if pc.get_type() == Type::KING => format!("{:>7}", pc.to_fen()),
+            (_, Some(v)) => format!("{:>+7}", v),
+            _ => format!("{:>7}", "."),
+        });
+
+        out += "\nPiece values (white):\n";
+
+        for (i, line) in grid.lines().enumerate() {
+            out += &format!("{} {}\n", 8 - i, line);
+        }
+
+        out += "  ";
+
+        for f in "abcdefgh".chars() {
+            out += &format!("{:>7}", f);
+        }
+
+        out + "\n"
+    })
+}
+
+/* Prints the trace for a position given as UCI 'position' arguments, or the start position */
+pub fn cli(args: &[String]) {
+    let args: Vec<&str> = args.iter().map(|a| a.as_str()).collect();
+    let p = if args.is_empty() {
+        uci::parse_position(&["startpos"])
+    } else {
+        uci::parse_position(&args)
+    };
+
+    match p {
+        Some(p) => print!("{}", trace(&p)),
+        None => error!("usage: nc eval [startpos | fen <fen>] [moves <move>...]"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pawns::PawnTable;
+    use crate::position::tests::mirror_fen;
+    use crate::square::*;
+
+    const FENS: [&str; 4] = [
+        STARTPOS,
+        "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+        "6k1/5ppp/8/6N1/8/3Q4/8/4K3 b - - 0 1",
+        "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+    ];
+
+    fn lines(fen: &str) -> Vec<(&'static str, [Score; 2])> {
+        let p = Position::new(fen.to_string()).unwrap();
+        let params = Params::default();
+        let mut t = Trace::new(&params);
+
+        eval::accumulate(p.board(), &mut t);
+        t.lines
+    }
+
+    #[test]
+    fn trace_matches_evaluate() {
+        let params = Params::default();
+
+        for fen in FENS.iter() {
+            let p = Position::new(fen.to_string()).unwrap();
+            let mut t = Trace::new(&params);
+
+            eval::accumulate(p.board(), &mut t);
+
+            let white = t.total().taper(eval::phase(p.board()));
+            let stm = match p.ctm() {
+                Color::WHITE => white,
+                Color::BLACK => -white,
+            };
+
+            assert_eq!(
+                stm + TEMPO,
+                eval::evaluate(&p, &mut PawnTable::default()),
+                "{}",
+                fen
+            );
+        }
+    }
+
+    #[test]
+    fn trace_splits_colours() {
+        for fen in FENS.iter() {
+            let mirrored = lines(&mirror_fen(fen));
+
+            for (a, b) in lines(fen).iter().zip(mirrored.iter()) {
+                assert_eq!(a.1, [b.1[1], b.1[0]], "{} {}", fen, a.0);
+            }
+        }
+
+        /* Black is attacked here, so only black pays for king danger */
+        let danger = lines(FENS[2]).last().cloned().unwrap();
+
+        assert_eq!(danger.0, DANGER);
+        assert_eq!(danger.1[0], Score::default());
+        assert!(danger.1[1].mg < 0);
+    }
+
+    #[test]
+    fn trace_heat_map_works() {
+        let p = Position::new("4k3/8/8/8/8/8/8/3QK3 w - - 0 1".to_string()).unwrap();
+        let map = heat_map(p.board(), &Params::default());
+        let d1 = Square::from_uci(&"d1".to_string()).unwrap();
+
+        assert!(map[d1.index()].unwrap() > 800);
+        assert_eq!(map.iter().filter(|v| v.is_some()).count(), 3);
+
+        let text = trace(&p);
+
+        assert!(text.contains("Phase: 4/24"));
+        assert!(text.contains(&format!("{:>+7}", map[d1.index()].unwrap())));
+        assert!(text.contains(&format!(
+            "1 {:>7}{:>7}{:>7}{:>+7}",
+            ".",
+            ".",
+            ".",
+            map[3].unwrap()
+        )));
+    }
+}
@@ -0,0 +1,719 @@
+use crate::autograd::*;
+use crate::encode;
+use crate::nn::*;
+use crate::policy;
+use crate::record::*;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use std::io;
+
+/* Learning rate as a function of the step number */
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Schedule {
+    Constant(f32),
+    Step { lr: f32, factor: f32, every: usize },
+    Cosine { lr: f32, min: f32, steps: usize },
+}
+
+impl Schedule {
+    pub fn at(&self, step: usize) -> f32 {
+        match *self {
+            Schedule::Constant(lr) => lr,
+            Schedule::Step { lr, factor, every } => lr * factor.powi((step / every.max(1)) as i32),
+            Schedule::Cosine { lr, min, steps } => {
+                let t = step.min(steps) as f32 / steps.max(1) as f32;
+                min + (lr - min) * 0.5 * (1.0 + (std::f32::consts::PI * t).cos())
+            }
+        }
+    }
+
+    /*
+     * Parses 'constant:<lr>', 'step:<lr>:<factor>:<every>' or
+     * 'cosine:<lr>:<min>'. A cosine schedule decays over `steps` steps.
+     */
+    pub fn parse(spec: &str, steps: usize) -> Option<Schedule> {
+        let mut parts = spec.split(':');
+        let kind = parts.next()?;
+        let nums = parts
+            .map(|s| s.parse::<f32>().ok())
+            .collect::<Option<Vec<f32>>>()?;
+
+        match (kind, nums.as_slice()) {
+            ("constant", [lr]) => Some(Schedule::Constant(*lr)),
+            ("step", [lr, factor, every]) if every.fract() == 0.0 && *every >= 1.0 => {
+                Some(Schedule::Step {
+                    lr: *lr,
+                    factor: *factor,
+                    every: *every as usize,
+                })
+            }
+            ("cosine", [lr, min]) => Some(Schedule::Cosine {
+                lr: *lr,
+                min: *min,
+                steps,
+            }),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Config {
+    pub batch_size: usize,
+    pub steps: usize,
+    pub schedule: Schedule,
+    pub momentum: f32,
+    pub weight_decay: f32,
+    pub bn_momentum: f32, /* Weight of each batch in the running statistics */
+    pub window: usize,    /* Most recent record files sampled from */
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            batch_size: 256,
+            steps: 1000,
+            schedule: Schedule::Step {
+                lr: 0.02,
+                factor: 0.1,
+                every: 10000,
+            },
+            momentum: 0.9,
+            weight_decay: 1e-4,
+            bn_momentum: 0.1,
+            window: 20,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Losses {
+    pub policy: f32,
+    pub value: f32,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Role {
+    Weight, /* Decayed */
+    Bias,
+    Gamma,
+    RunningMean,
+    RunningVar,
+}
+
+/* Roles of the network tensors, in `Network::tensors` order */
+fn roles(shape: &Shape) -> Vec<Role> {
+    let conv_bn = [
+        Role::Weight,
+        Role::Bias,
+        Role::Gamma,
+        Role::Bias,
+        Role::RunningMean,
+        Role::RunningVar,
+    ];
+    let dense = [Role::Weight, Role::Bias];
+
+    let mut out = conv_bn.to_vec();
+
+    for _ in 0..shape.blocks * 2 {
+        out.extend_from_slice(&conv_bn);
+    }
+
+    for _ in 0..2 {
+        out.extend_from_slice(&conv_bn);
+        out.extend_from_slice(&dense);
+    }
+
+    out.extend_from_slice(&dense);
+    out
+}
+
+/* Builds the network forward pass over parameter leaves in `Network::tensors` order */
+struct Forward<'a> {
+    g: &'a mut Graph,
+    params: &'a [Var],
+    next: usize,
+    train: bool,
+    norms: Vec<(Var, usize)>, /* Batch norm nodes and the index of their running mean */
+}
+
+impl<'a> Forward<'a> {
+    fn conv_bn(&mut self, x: Var, cin: usize, cout: usize, k: usize) -> Var {
+        let p = &self.params[self.next..self.next + 6];
+        let y = self.g.conv(x, p[0], p[1], cin, cout, k);
+
+        let stats = if self.train {
+            None
+        } else {
+            Some((self.g.value(p[4]).to_vec(), self.g.value(p[5]).to_vec()))
+        };
+
+        let y = self.g.batch_norm(
+            y,
+            p[2],
+            p[3],
+            cout,
+            stats.as_ref().map(|(m, v)| (&m[..], &v[..])),
+        );
+
+        self.norms.push((y, self.next + 4));
+        self.next += 6;
+        y
+    }
+
+    fn dense(&mut self, x: Var, cin: usize, cout: usize) -> Var {
+        let p = &self.params[self.next..self.next + 2];
+        self.next += 2;
+        self.g.dense(x, p[0], p[1], cin, cout)
+    }
+
+    /* Same architecture as `Network::forward`; returns (policy logits, value) */
+    fn network(&mut self, shape: &Shape, input: Var) -> (Var, Var) {
+        let f = shape.filters;
+
+        let y = self.conv_bn(input, encode::PLANES, f, 3);
+        let mut x = self.g.relu(y);
+
+        for _ in 0..shape.blocks {
+            let y = self.conv_bn(x, f, f, 3);
+            let y = self.g.relu(y);
+            let y = self.conv_bn(y, f, f, 3);
+            let y = self.g.add(y, x);
+            x = self.g.relu(y);
+        }
+
+        let pc = shape.policy_channels;
+        let p = self.conv_bn(x, f, pc, 1);
+        let p = self.g.relu(p);
+        let p = self.dense(p, pc * 64, policy::POLICY_SIZE);
+
+        let vc = shape.value_channels;
+        let v = self.conv_bn(x, f, vc, 1);
+        let v = self.g.relu(v);
+        let v = self.dense(v, vc * 64, shape.value_hidden);
+        let v = self.g.relu(v);
+        let v = self.dense(v, shape.value_hidden, 1);
+        let v = self.g.tanh(v);
+
+        (p, v)
+    }
+}
+
+/* Uniform He initialisation of weights; biases and means are reset to 0, gammas and variances to 1 */
+pub fn initialize<R: Rng>(net: &mut Network, rng: &mut R) {
+    let roles = roles(&net.shape);
+    let fans: Vec<usize> = {
+        let mut fans = Vec::new();
+        let tensors = net.tensors();
+
+        for (i, t) in tensors.iter().enumerate() {
+            /* The bias following a weight tensor has one entry per output */
+            if roles[i] == Role::Weight {
+                fans.push(t.len() / tensors[i + 1].len());
+            }
+        }
+
+        fans
+    };
+
+    let mut fan = fans.into_iter();
+
+    for (t, role) in net.tensors_mut().into_iter().zip(roles.iter()) {
+        match role {
+            Role::Weight => {
+                let bound = (6.0 / fan.next().unwrap() as f32).sqrt();
+
+                for v in t.iter_mut() {
+                    *v = rng.gen_range(-bound, bound);
+                }
+            }
+            Role::Bias | Role::RunningMean => t.iter_mut().for_each(|v| *v = 0.0),
+            Role::Gamma | Role::RunningVar => t.iter_mut().for_each(|v| *v = 1.0),
+        }
+    }
+
+    net.fold();
+}
+
+pub struct Trainer {
+    pub net: Network,
+    config: Config,
+    velocity: Vec<Vec<f32>>,
+    step: usize,
+}
+
+impl Trainer {
+    pub fn new(net: Network, config: Config) -> Trainer {
+        let velocity = net.tensors().iter().map(|t| vec![0.0; t.len()]).collect();
+
+        Trainer {
+            net,
+            config,
+            velocity,
+            step: 0,
+        }
+    }
+
+    /* Runs one SGD step on a batch, returning the losses before the update */
+    pub fn step(&mut self, batch: &[Sample]) -> Losses {
+        let shape = self.net.shape;
+        let roles = roles(&shape);
+        let mut g = Graph::new();
+
+        let params: Vec<Var> = self
+            .net
+            .tensors_mut()
+            .into_iter()
+            .map(|t| g.leaf(std::mem::take(t)))
+            .collect();
+
+        let (input, policy_target, value_target) = targets(batch);
+        let input = g.leaf(input);
+
+        let mut fwd = Forward {
+            g: &mut g,
+            params: &params,
+            next: 0,
+            train: true,
+            norms: Vec::new(),
+        };
+
+        let (p, v) = fwd.network(&shape, input);
+        let norms = fwd.norms;
+
+        let policy_loss = g.softmax_xent(p, policy_target, policy::POLICY_SIZE);
+        let value_loss = g.mse(v, value_target);
+        let loss = g.add(policy_loss, value_loss);
+
+        g.backward(loss);
+
+        let losses = Losses {
+            policy: g.value(policy_loss)[0],
+            value: g.value(value_loss)[0],
+        };
+
+        /* Running statistics follow the batch statistics */
+        let m = self.config.bn_momentum;
+        let mut running: Vec<(usize, Vec<f32>)> = Vec::new();
+
+        for (bn, at) in norms {
+            let (mean, var) = g.batch_stats(bn);
+
+            for (i, stat) in [(at, mean), (at + 1, var)].iter() {
+                let old = g.value(params[*i]);
+                let new = old
+                    .iter()
+                    .zip(stat.iter())
+                    .map(|(o, s)| (1.0 - m) * o + m * s)
+                    .collect();
+
+                running.push((*i, new));
+            }
+        }
+
+        /* SGD with momentum */
+        let lr = self.config.schedule.at(self.step);
+        let mut values: Vec<Vec<f32>> = Vec::with_capacity(params.len());
+
+        for (i, p) in params.iter().enumerate() {
+            let grad = g.grad(*p).to_vec();
+            let mut w = g.take(*p);
+
+            if matches!(roles[i], Role::Weight | Role::Bias | Role::Gamma) && !grad.is_empty() {
+                let decay = if roles[i] == Role::Weight {
+                    self.config.weight_decay
+                } else {
+                    0.0
+                };
+
+                for ((w, v), d) in w.iter_mut().zip(self.velocity[i].iter_mut()).zip(grad) {
+                    *v = self.config.momentum * *v + d + decay * *w;
+                    *w -= lr * *v;
+                }
+            }
+
+            values.push(w);
+        }
+
+        for (i, stat) in running {
+            values[i] = stat;
+        }
+
+        for (t, v) in self.net.tensors_mut().into_iter().zip(values) {
+            *t = v;
+        }
+
+        self.net.fold();
+        self.step += 1;
+
+        losses
+    }
+}
+
+/* Stacks a batch into network inputs, dense policy targets and values */
+fn targets(batch: &[Sample]) -> (Vec<f32>, Vec<f32>, Vec<f32>) {
+    let mut input = Vec::with_capacity(batch.len() * encode::INPUT_SIZE);
+    let mut policy = vec![0.0; batch.len() * policy::POLICY_SIZE];
+
+    for (i, s) in batch.iter().enumerate() {
+        input.extend_from_slice(&s.planes);
+
+        let total: f32 = s.policy.iter().map(|p| p.1).sum();
+
+        for (idx, p) in s.policy.iter() {
+            policy[i * policy::POLICY_SIZE + *idx as usize] = p / total.max(1e-6);
+        }
+    }
+
+    (input, policy, batch.iter().map(|s| s.result).collect())
+}
+
+/* Trains `net` on batches drawn from `sampler` */
+pub fn train<R: Rng>(net: Network, sampler: &mut Sampler, config: Config, rng: &mut R) -> Network {
+    let mut t = Trainer::new(net, config);
+    let mut avg = Losses::default();
+
+    for step in 1..=config.steps {
+        let l = t.step(&sampler.batch(rng, config.batch_size));
+
+        avg.policy += l.policy;
+        avg.value += l.value;
+
+        if step % 100 == 0 || step == config.steps {
+            let n = if step % 100 == 0 { 100 } else { step % 100 };
+
+            info!(
+                "Step {}/{}: policy loss {:.4}, value loss {:.4}, lr {}",
+                step,
+                config.steps,
+                avg.policy / n as f32,
+                avg.value / n as f32,
+                config.schedule.at(step - 1)
+            );
+
+            avg = Losses::default();
+        }
+    }
+
+    t.net
+}
+
+/*
+ * Trains from the most recent record files in `dir`, starting from the
+ * weights in `input` or a freshly initialised network of `shape`.
+ */
+pub fn train_dir(
+    dir: &str,
+    input: Option<&str>,
+    shape: Shape,
+    output: &str,
+    config: Config,
+) -> io::Result<()> {
+    let mut rng = StdRng::from_entropy();
+
+    let net = match input {
+        Some(path) => Network::load(path)?,
+        None => {
+            let mut net = Network::new(shape);
+            initialize(&mut net, &mut rng);
+            net
+        }
+    };
+
+    let mut sampler = Sampler::new(&window(dir, config.window)?)?;
+
+    if sampler.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("no training records in '{}'", dir),
+        ));
+    }
+
+    info!("Training on {} positions", sampler.len());
+
+    train(net, &mut sampler, config, &mut rng).save(output)
+}
+
+/*
+ * Entry point for `nc train <records dir> <output.nn> [steps] [input.nn | -] [schedule]`,
+ * where '-' starts from a fresh network and the schedule is given as for
+ * `Schedule::parse`.
+ */
+pub fn cli(args: &[String]) {
+    let usage = "usage: nc train <records dir> <output.nn> [steps] [input.nn | -] [schedule]";
+    let steps = args.get(2).map(|s| s.parse::<usize>());
+
+    if args.len() < 2 || args.len() > 5 || steps.as_ref().is_some_and(|s| s.is_err()) {
+        error!("{}", usage);
+        return;
+    }
+
+    let mut config = Config::default();
+
+    if let Some(Ok(s)) = steps {
+        config.steps = s;
+    }
+
+    if let Some(spec) = args.get(4) {
+        match Schedule::parse(spec, config.steps) {
+            Some(s) => config.schedule = s,
+            None => {
+                error!("{}", usage);
+                return;
+            }
+        }
+    }
+
+    let input = args.get(3).map(|s| s.as_str()).filter(|s| *s != "-");
+
+    match train_dir(&args[0], input, Shape::default(), &args[1], config) {
+        Ok(()) => info!("Wrote network to '{}'", args[1]),
+        Err(e) => error!("Training failed: {}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gmove::*;
+    use crate::position::*;
+
+    fn tiny() -> Shape {
+        Shape {
+            filters: 4,
+            blocks: 1,
+            policy_channels: 1,
+            value_channels: 1,
+            value_hidden: 8,
+        }
+    }
+
+    fn samples() -> Vec<Sample> {
+        let mut p = Position::new(STARTPOS.to_string()).unwrap();
+        let mut out = Vec::new();
+
+        for (i, m) in ["e2e4", "e7e5", "g1f3", "b8c6"].iter().enumerate() {
+            let m = Move::from_uci(&m.to_string()).unwrap();
+
+            out.push(Sample {
+                planes: encode::encode(&p),
+                policy: vec![(policy::encode(p.ctm(), m).unwrap() as u16, 1.0)],
+                result: if i % 2 == 0 { 1.0 } else { -1.0 },
+            });
+
+            p.make_move(m);
+        }
+
+        out
+    }
+
+    #[test]
+    fn train_schedules_work() {
+        assert_eq!(Schedule::Constant(0.1).at(500), 0.1);
+
+        let s = Schedule::Step {
+            lr: 1.0,
+            factor: 0.5,
+            every: 10,
+        };
+
+        assert_eq!(s.at(9), 1.0);
+        assert_eq!(s.at(10), 0.5);
+        assert_eq!(s.at(25), 0.25);
+
+        let c = Schedule::Cosine {
+            lr: 1.0,
+            min: 0.0,
+            steps: 100,
+        };
+
+        assert_eq!(c.at(0), 1.0);
+        assert!((c.at(50) - 0.5).abs() < 1e-6);
+        assert!(c.at(100).abs() < 1e-6);
+        assert!(c.at(200).abs() < 1e-6);
+    }
+
+    #[test]
+    fn train_schedule_parse_works() {
+        assert_eq!(
+            Schedule::parse("constant:0.05", 10),
+            Some(Schedule::Constant(0.05))
+        );
+        assert_eq!(
+            Schedule::parse("step:0.02:0.1:500", 10),
+            Some(Schedule::Step {
+                lr: 0.02,
+                factor: 0.1,
+                every: 500,
+            })
+        );
+        assert_eq!(
+            Schedule::parse("cosine:0.02:0.001", 800),
+            Some(Schedule::Cosine {
+                lr: 0.02,
+                min: 0.001,
+                steps: 800,
+            })
+        );
+
+        for bad in [
+            "",
+            "constant",
+            "constant:x",
+            "step:0.02:0.1",
+            "step:0.1:0.5:2.5",
+            "linear:1",
+        ]
+        .iter()
+        {
+            assert_eq!(Schedule::parse(bad, 10), None, "{}", bad);
+        }
+    }
+
+    #[test]
+    fn train_roles_cover_tensors() {
+        let net = Network::new(tiny());
+        assert_eq!(roles(&net.shape).len(), net.tensors().len());
+    }
+
+    #[test]
+    fn train_initialize_resets_network() {
+        let mut rng = StdRng::seed_from_u64(3);
+        let mut net = Network::new(tiny());
+
+        for t in net.tensors_mut() {
+            t.iter_mut().for_each(|v| *v = 7.0);
+        }
+
+        initialize(&mut net, &mut rng);
+
+        let fresh = Network::new(tiny());
+        let roles = roles(&net.shape);
+
+        for (i, (t, f)) in net.tensors().iter().zip(fresh.tensors()).enumerate() {
+            match roles[i] {
+                Role::Weight => assert!(t.iter().all(|v| v.abs() < 7.0), "{}", i),
+                _ => assert_eq!(*t, f, "{}", i),
+            }
+        }
+    }
+
+    #[test]
+    fn train_eval_graph_matches_inference() {
+        let mut rng = StdRng::seed_from_u64(5);
+        let mut net = Network::new(tiny());
+
+        initialize(&mut net, &mut rng);
+
+        /* Non-trivial running statistics */
+        for cb in [&mut net.input, &mut net.policy_conv, &mut net.value_conv].iter_mut() {
+            for v in cb.bn.mean.iter_mut() {
+                *v = rng.gen_range(-0.5, 0.5);
+            }
+
+            for v in cb.bn.var.iter_mut() {
+                *v = rng.gen_range(0.5, 2.0);
+            }
+        }
+
+        net.fold();
+
+        let batch = samples();
+        let mut g = Graph::new();
+        let params: Vec<Var> = net.tensors().iter().map(|t| g.leaf(t.to_vec())).collect();
+        let input = g.leaf(targets(&batch).0);
+
+        let mut fwd = Forward {
+            g: &mut g,
+            params: &params,
+            next: 0,
+            train: false,
+            norms: Vec::new(),
+        };
+
+        let (p, v) = fwd.network(&tiny(), input);
+        let inputs: Vec<Vec<f32>> = batch.iter().map(|s| s.planes.clone()).collect();
+
+        for (i, out) in net.forward(&inputs).iter().enumerate() {
+            let logits = &g.value(p)[i * policy::POLICY_SIZE..(i + 1) * policy::POLICY_SIZE];
+
+            assert!((g.value(v)[i] - out.value).abs() < 1e-4);
+
+            for (a, b) in logits.iter().zip(out.policy.iter()) {
+                assert!((a - b).abs() < 1e-4);
+            }
+        }
+    }
+
+    #[test]
+    fn train_reduces_loss_and_saves_loadable_weights() {
+        let mut rng = StdRng::seed_from_u64(6);
+        let mut net = Network::new(tiny());
+
+        initialize(&mut net, &mut rng);
+
+        let config = Config {
+            schedule: Schedule::Constant(0.05),
+            ..Config::default()
+        };
+
+        let batch = samples();
+        let mut t = Trainer::new(net, config);
+        let first = t.step(&batch);
+
+        for _ in 0..30 {
+            t.step(&batch);
+        }
+
+        let last = t.step(&batch);
+
+        assert!(last.policy < first.policy * 0.5, "{:?} {:?}", first, last);
+        assert!(last.value < first.value, "{:?} {:?}", first, last);
+
+        let bytes = t.net.to_bytes();
+        let loaded = Network::from_bytes(&bytes).unwrap();
+        let input = [batch[0].planes.clone()];
+
+        assert_eq!(loaded.forward(&input), t.net.forward(&input));
+    }
+
+    #[test]
+    fn train_dir_works() {
+        let dir = std::env::temp_dir().join("nc_train_dir");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir(&dir).unwrap();
+
+        let mut w = Writer::create(dir.join("0.rec"), true).unwrap();
+
+        for s in samples() {
+            w.write(&s).unwrap();
+        }
+
+        w.finish().unwrap();
+
+        let output = dir.join("out.nn");
+        let config = Config {
+            batch_size: 4,
+            steps: 3,
+            ..Config::default()
+        };
+
+        train_dir(
+            dir.to_str().unwrap(),
+            None,
+            tiny(),
+            output.to_str().unwrap(),
+            config,
+        )
+        .unwrap();
+
+        assert_eq!(
+            Network::load(output.to_str().unwrap()).unwrap().shape,
+            tiny()
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
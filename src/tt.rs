@@ -0,0 +1,326 @@
+use crate::gmove::*;
+use crate::search::*;
+
+use std::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+
+pub const DEFAULT_MB: usize = 16;
+pub const MAX_MB: usize = 65536;
+
+/* Number of buckets sampled for the hashfull estimate */
+const HASHFULL_SAMPLE: usize = 1000;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Bound {
+    EXACT = 1,
+    LOWER = 2,
+    UPPER = 3,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Entry {
+    pub mv: Option<Move>,
+    pub score: i32,
+    pub depth: u8,
+    pub bound: Bound,
+}
+
+/*
+ * Each slot stores (key ^ data, data) so that torn writes from concurrent
+ * threads fail verification instead of returning corrupted entries.
+ *
+ * data layout: move (16) | score (16) | depth (8) | bound (2) | age (6)
+ */
+#[derive(Default)]
+struct Slot {
+    check: AtomicU64,
+    data: AtomicU64,
+}
+
+impl Slot {
+    fn load(&self) -> (u64, u64) {
+        let data = self.data.load(Ordering::Relaxed);
+        let check = self.check.load(Ordering::Relaxed);
+
+        (check ^ data, data)
+    }
+
+    fn store(&self, key: u64, data: u64) {
+        self.check.store(key ^ data, Ordering::Relaxed);
+        self.data.store(data, Ordering::Relaxed);
+    }
+}
+
+/* Slot 0 is depth-preferred, slot 1 is always replaced */
+#[derive(Default)]
+struct Bucket {
+    slots: [Slot; 2],
+}
+
+pub struct TranspositionTable {
+    buckets: Vec<Bucket>,
+    age: AtomicU8,
+}
+
+fn pack(mv: Option<Move>, score: i32, depth: u8, bound: Bound, age: u8) -> u64 {
+    let mv = mv.map_or(0, |m| m.to_u16()) as u64;
+
+    mv | (score as i16 as u16 as u64) << 16
+        | (depth as u64) << 32
+        | (bound as u64) << 40
+        | ((age & 63) as u64) << 42
+}
+
+fn unpack_depth(data: u64) -> u8 {
+    (data >> 32) as u8
+}
+
+fn unpack_age(data: u64) -> u8 {
+    (data >> 42) as u8 & 63
+}
+
+fn unpack(data: u64) -> Option<Entry> {
+    let bound = match (data >> 40) & 3 {
+        1 => Bound::EXACT,
+        2 => Bound::LOWER,
+        3 => Bound::UPPER,
+        _ => return None,
+    };
+
+    Some(Entry {
+        mv: Move::from_u16(data as u16),
+        score: (data >> 16) as u16 as i16 as i32,
+        depth: unpack_depth(data),
+        bound,
+    })
+}
+
+/* Converts a root-relative mate score into a node-relative one for storage */
+pub fn score_to_tt(score: i32, ply: usize) -> i32 {
+    if score >= MATE_BOUND {
+        score + ply as i32
+    } else if score <= -MATE_BOUND {
+        score - ply as i32
+    } else {
+        score
+    }
+}
+
+pub fn score_from_tt(score: i32, ply: usize) -> i32 {
+    if score >= MATE_BOUND {
+        score - ply as i32
+    } else if score <= -MATE_BOUND {
+        score + ply as i32
+    } else {
+        score
+    }
+}
+
+impl TranspositionTable {
+    pub fn new(mb: usize) -> TranspositionTable {
+        let count = (mb.max(1) << 20) / std::mem::size_of::<Bucket>();
+        let mut buckets = Vec::with_capacity(count);
+
+        buckets.resize_with(count, Bucket::default);
+
+        TranspositionTable {
+            buckets,
+            age: AtomicU8::new(0),
+        }
+    }
+
+    pub fn resize(&mut self, mb: usize) {
+        *self = TranspositionTable::new(mb);
+    }
+
+    pub fn clear(&self) {
+        for b in self.buckets.iter() {
+            for s in b.slots.iter() {
+                s.check.store(0, Ordering::Relaxed);
+                s.data.store(0, Ordering::Relaxed);
+            }
+        }
+
+        self.age.store(0, Ordering::Relaxed);
+    }
+
+    /* Called once per 'go' so that stale entries are replaced first */
+    pub fn new_search(&self) {
+        let age = self.age.load(Ordering::Relaxed);
+        self.age.store(age.wrapping_add(1) & 63, Ordering::Relaxed);
+    }
+
+    fn bucket(&self, key: u64) -> &Bucket {
+        let idx = ((key as u128 * self.buckets.len() as u128) >> 64) as usize;
+        &self.buckets[idx]
+    }
+
+    pub fn probe(&self, key: u64) -> Option<Entry> {
+        for s in self.bucket(key).slots.iter() {
+            let (k, data) = s.load();
+
+            if k == key && data != 0 {
+                return unpack(data);
+            }
+        }
+
+        None
+    }
+
+    pub fn store(&self, key: u64, depth: u8, bound: Bound, score: i32, mv: Option<Move>) {
+        let age = self.age.load(Ordering::Relaxed);
+        let b = self.bucket(key);
+        let loaded = [b.slots[0].load(), b.slots[1].load()];
+
+        /* A slot already holding this position is updated in place */
+        if let Some(i) = (0..2).find(|i| loaded[*i].0 == key && loaded[*i].1 != 0) {
+            /* Keep the previous best move when the new result has none */
+            let mv = mv.or_else(|| unpack(loaded[i].1).and_then(|e| e.mv));
+
+            b.slots[i].store(key, pack(mv, score, depth, bound, age));
+            return;
+        }
+
+        let (_, data) = loaded[0];
+        let new_data = pack(mv, score, depth, bound, age);
+
+        if data == 0 || depth >= unpack_depth(data) || unpack_age(data) != age {
+            b.slots[0].store(key, new_data);
+        } else {
+            b.slots[1].store(key, new_data);
+        }
+    }
+
+    /* Permille of sampled slots used by the current search */
+    pub fn hashfull(&self) -> usize {
+        let age = self.age.load(Ordering::Relaxed);
+        let sample = HASHFULL_SAMPLE.min(self.buckets.len());
+        let mut used = 0;
+
+        for b in self.buckets.iter().take(sample) {
+            for s in b.slots.iter() {
+                let (_, data) = s.load();
+
+                if data != 0 && unpack_age(data) == age {
+                    used += 1;
+                }
+            }
+        }
+
+        used * 1000 / (sample * 2).max(1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tt_store_probe_works() {
+        let tt = TranspositionTable::new(1);
+        let m = Move::from_uci(&"e2e4".to_string());
+
+        tt.store(0xdeadbeef, 5, Bound::LOWER, -123, m);
+
+        assert_eq!(
+            tt.probe(0xdeadbeef),
+            Some(Entry {
+                mv: m,
+                score: -123,
+                depth: 5,
+                bound: Bound::LOWER,
+            })
+        );
+
+        assert_eq!(tt.probe(0xdeadbeee), None);
+    }
+
+    #[test]
+    fn tt_mate_scores_are_ply_adjusted() {
+        let s = MATE - 5;
+
+        assert_eq!(score_to_tt(s, 3), MATE - 2);
+        assert_eq!(score_from_tt(score_to_tt(s, 3), 3), s);
+        assert_eq!(score_from_tt(score_to_tt(-s, 3), 3), -s);
+        assert_eq!(score_to_tt(250, 3), 250);
+    }
+
+    #[test]
+    fn tt_replacement_prefers_depth() {
+        let tt = TranspositionTable::new(1);
+        let n = tt.buckets.len() as u128;
+
+        /* Two keys mapping to the same bucket */
+        let k1 = 1u64 << 63;
+        let k2 = k1 + 1;
+        assert_eq!((k1 as u128 * n) >> 64, (k2 as u128 * n) >> 64);
+
+        tt.store(k1, 10, Bound::EXACT, 1, None);
+        tt.store(k2, 2, Bound::EXACT, 2, None);
+
+        /* Shallow entry goes to the always-replace slot */
+        assert_eq!(tt.probe(k1).unwrap().depth, 10);
+        assert_eq!(tt.probe(k2).unwrap().depth, 2);
+
+        /* A key already in the always-replace slot is updated there, keeping its move */
+        let m = Move::from_uci(&"e2e4".to_string());
+
+        tt.store(k2, 3, Bound::LOWER, 4, m);
+        tt.store(k2, 4, Bound::UPPER, 5, None);
+        assert_eq!(tt.probe(k1).unwrap().depth, 10);
+        assert_eq!(
+            tt.probe(k2),
+            Some(Entry {
+                mv: m,
+                score: 5,
+                depth: 4,
+                bound: Bound::UPPER,
+            })
+        );
+
+        /* Even a deeper result must not leave a second copy in the depth-preferred slot */
+        tt.store(k2, 12, Bound::EXACT, 6, None);
+        assert_eq!(tt.probe(k1).unwrap().depth, 10);
+        assert_eq!(tt.probe(k2).unwrap().mv, m);
+        assert_eq!(
+            tt.bucket(k2)
+                .slots
+                .iter()
+                .filter(|s| s.load().0 == k2)
+                .count(),
+            1
+        );
+
+        /* Stale entries lose their depth preference */
+        let k3 = k1 + 2;
+
+        tt.new_search();
+        tt.store(k3, 1, Bound::UPPER, 3, None);
+        assert_eq!(tt.probe(k1), None);
+        assert_eq!(tt.probe(k3).unwrap().score, 3);
+    }
+
+    #[test]
+    fn tt_clear_and_hashfull_work() {
+        let tt = TranspositionTable::new(1);
+
+        assert_eq!(tt.hashfull(), 0);
+
+        for i in 0..(tt.buckets.len() as u64 * 4) {
+            tt.store(i.wrapping_mul(0x9e3779b97f4a7c15), 1, Bound::EXACT, 0, None);
+        }
+
+        assert!(tt.hashfull() > 400);
+
+        tt.clear();
+        assert_eq!(tt.hashfull(), 0);
+    }
+
+    #[test]
+    fn tt_resize_works() {
+        let mut tt = TranspositionTable::new(1);
+        let len = tt.buckets.len();
+
+        tt.resize(2);
+        assert_eq!(tt.buckets.len(), len * 2);
+    }
+}
@@ -0,0 +1,570 @@
+use crate::eval::{self, Score, MAX_PHASE, TEMPO};
+use crate::gmove::*;
+use crate::movepick::SEE_VALUE;
+use crate::params::{self, Accumulator, Params};
+use crate::pawns::PawnTable;
+use crate::piece::*;
+use crate::position::*;
+use crate::search::INFINITY;
+
+use std::fs;
+use std::io;
+use std::thread;
+
+/* Deepest capture sequence followed when quieting a position */
+pub const QUIET_PLY: usize = 8;
+
+/* Captures that cannot lift the score to within this margin of alpha are skipped */
+pub const DELTA_MARGIN: i32 = 200;
+
+#[derive(Copy, Clone, Debug)]
+pub struct Config {
+    pub epochs: usize,
+    pub rate: f64,     /* Adam step size, in centipawns */
+    pub qsearch: bool, /* Resolve captures before extracting terms */
+    pub threads: usize,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            epochs: 500,
+            rate: 1.0,
+            qsearch: true,
+            threads: thread::available_parallelism().map_or(1, |n| n.get()),
+        }
+    }
+}
+
+/* A labelled position reduced to its evaluation terms */
+#[derive(Clone, Debug, PartialEq)]
+pub struct Sample {
+    pub result: f64, /* 1 for a white win, 0.5 for a draw, 0 for a black win */
+    pub phase: i32,
+    pub tempo: i32,             /* TEMPO from white's perspective */
+    pub constant: Score,        /* Terms not linear in any weight */
+    pub terms: Vec<(u16, i16)>, /* Weight index and white-relative count */
+}
+
+impl Accumulator for Sample {
+    fn add(&mut self, index: usize, count: i32) {
+        if count != 0 {
+            self.terms.push((index as u16, count as i16));
+        }
+    }
+
+    fn constant(&mut self, score: Score) {
+        self.constant += score;
+    }
+}
+
+impl Sample {
+    pub fn new(p: &Position, result: f64) -> Sample {
+        let b = p.board();
+        let mut s = Sample {
+            result,
+            phase: eval::phase(b),
+            tempo: match p.ctm() {
+                Color::WHITE => TEMPO,
+                Color::BLACK => -TEMPO,
+            },
+            constant: Score::default(),
+            terms: Vec::new(),
+        };
+
+        eval::accumulate(b, &mut s);
+
+        /* Merge repeated indices so each weight appears once */
+        s.terms.sort_unstable_by_key(|t| t.0);
+        s.terms.dedup_by(|a, b| {
+            if a.0 == b.0 {
+                b.1 += a.1;
+                true
+            } else {
+                false
+            }
+        });
+        s.terms.retain(|t| t.1 != 0);
+        s
+    }
+
+    /* White-relative evaluation with `weights` holding an (mg, eg) pair per parameter */
+    pub fn evaluate(&self, weights: &[f64]) -> f64 {
+        let mut mg = self.constant.mg as f64;
+        let mut eg = self.constant.eg as f64;
+
+        for (i, n) in self.terms.iter() {
+            mg += weights[2 * *i as usize] * *n as f64;
+            eg += weights[2 * *i as usize + 1] * *n as f64;
+        }
+
+        let phase = self.phase as f64 / MAX_PHASE as f64;
+
+        mg * phase + eg * (1.0 - phase) + self.tempo as f64
+    }
+}
+
+/*
+ * Splits a training line into a FEN and a white-relative result. The result
+ * may be a PGN result anywhere on the line ("1-0", "0-1", "1/2-1/2", as in
+ * EPD `c9` opcodes) or a trailing number, optionally in brackets ("[0.5]").
+ * EPD lines without move counters are completed with "0 1".
+ */
+pub fn parse_line(line: &str) -> Option<(String, f64)> {
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    let counters = fields.len() >= 6 && fields[4..6].iter().all(|f| f.parse::<u32>().is_ok());
+    let fen_len = if counters { 6 } else { 4 };
+
+    if fields.len() <= fen_len {
+        return None;
+    }
+
+    let rest = fields[fen_len..].join(" ");
+    let result = if rest.contains("1/2-1/2") {
+        0.5
+    } else if rest.contains("1-0") {
+        1.0
+    } else if rest.contains("0-1") {
+        0.0
+    } else {
+        let last = fields
+            .last()?
+            .trim_matches(|c| c == '[' || c == ']' || c == ';' || c == '"');
+
+        last.parse::<f64>()
+            .ok()
+            .filter(|r| (0.0..=1.0).contains(r))?
+    };
+
+    let fen = if counters {
+        fields[..6].join(" ")
+    } else {
+        format!("{} 0 1", fields[..4].join(" "))
+    };
+
+    Some((fen, result))
+}
+
+/* Follows captures and promotions from `p`, returning the score and the line reaching the quiet leaf */
+fn quiet(
+    p: &mut Position,
+    mut alpha: i32,
+    beta: i32,
+    ply: usize,
+    pawns: &mut PawnTable,
+) -> (i32, Vec<Move>) {
+    let stand_pat = eval::evaluate(p, pawns);
+
+    if stand_pat >= beta || ply >= QUIET_PLY {
+        return (stand_pat, Vec::new());
+    }
+
+    alpha = alpha.max(stand_pat);
+
+    let mut best = (stand_pat, Vec::new());
+    let legal = p.gen_legal_moves();
+
+    /* Most valuable victims first, skipping captures that cannot reach alpha */
+    let mut moves: Vec<(i32, Move)> = legal
+        .into_iter()
+        .map(|m| {
+            let victim = p.captured_type(m).map_or(0, |t| SEE_VALUE[t as usize]);
+            let promotion = m.ptype().map_or(0, |t| SEE_VALUE[t as usize]);
+
+            (victim + promotion, m)
+        })
+        .filter(|(g, _)| *g > 0 && stand_pat + g + DELTA_MARGIN > alpha)
+        .collect();
+
+    moves.sort_by_key(|(g, _)| -g);
+
+    for (_, m) in moves {
+        p.make_move(m);
+        let (score, line) = quiet(p, -beta, -alpha, ply + 1, pawns);
+        p.unmake_move(m);
+
+        if -score > best.0 {
+            best = (-score, std::iter::once(m).chain(line).collect());
+            alpha = alpha.max(-score);
+
+            if alpha >= beta {
+                break;
+            }
+        }
+    }
+
+    best
+}
+
+/* Builds a sample from a training line, quieting the position first if requested */
+pub fn sample(line: &str, qsearch: bool, pawns: &mut PawnTable) -> Option<Sample> {
+    let (fen, result) = parse_line(line)?;
+    let mut p = Position::new(fen)?;
+
+    if qsearch {
+        let (_, moves) = quiet(&mut p, -INFINITY, INFINITY, 0, pawns);
+
+        for m in moves {
+            p.make_move(m);
+        }
+    }
+
+    Some(Sample::new(&p, result))
+}
+
+/* Reads labelled positions from `path`, skipping lines that cannot be parsed */
+pub fn load(path: &str, config: &Config) -> io::Result<Vec<Sample>> {
+    let text = fs::read_to_string(path)?;
+    let lines: Vec<&str> = text
+        .lines()
+        .map(|l| l.trim())
+        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+        .collect();
+
+    let chunk = lines.len() / config.threads.max(1) + 1;
+    let samples: Vec<Sample> = thread::scope(|s| {
+        let workers: Vec<_> = lines
+            .chunks(chunk)
+            .map(|lines| {
+                s.spawn(move || {
+                    let mut pawns = PawnTable::default();
+
+                    lines
+                        .iter()
+                        .filter_map(|l| sample(l, config.qsearch, &mut pawns))
+                        .collect::<Vec<Sample>>()
+                })
+            })
+            .collect();
+
+        workers
+            .into_iter()
+            .flat_map(|w| w.join().unwrap())
+            .collect()
+    });
+
+    if samples.len() < lines.len() {
+        warn!(
+            "Skipped {} unreadable lines in '{}'",
+            lines.len() - samples.len(),
+            path
+        );
+    }
+
+    if samples.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("no labelled positions in '{}'", path),
+        ));
+    }
+
+    Ok(samples)
+}
+
+/* Expected score for white of a white-relative evaluation */
+pub fn sigmoid(k: f64, eval: f64) -> f64 {
+    1.0 / (1.0 + 10f64.powf(-k * eval / 400.0))
+}
+
+/* Runs `f` over slices of `samples` on `threads` threads, summing the results */
+fn parallel<F>(samples: &[Sample], threads: usize, f: F) -> Vec<f64>
+where
+    F: Fn(&[Sample]) -> Vec<f64> + Sync,
+{
+    let chunk = samples.len() / threads.max(1) + 1;
+
+    thread::scope(|s| {
+        let workers: Vec<_> = samples
+            .chunks(chunk)
+            .map(|c| {
+                let f = &f;
+                s.spawn(move || f(c))
+            })
+            .collect();
+
+        workers
+            .into_iter()
+            .map(|w| w.join().unwrap())
+            .reduce(|a, b| a.iter().zip(b.iter()).map(|(x, y)| x + y).collect())
+            .unwrap_or_default()
+    })
+}
+
+/* Mean squared error between results and predicted scores */
+pub fn loss(samples: &[Sample], weights: &[f64], k: f64, threads: usize) -> f64 {
+    let total = parallel(samples, threads, |chunk| {
+        vec![chunk
+            .iter()
+            .map(|s| (s.result - sigmoid(k, s.evaluate(weights))).powi(2))
+            .sum()]
+    });
+
+    total[0] / samples.len() as f64
+}
+
+/* Finds the scaling constant that best maps evaluations to results */
+pub fn fit_k(samples: &[Sample], weights: &[f64], threads: usize) -> f64 {
+    let (mut lo, mut hi, mut step) = (0.0, 10.0, 1.0);
+    let mut best = 1.0;
+
+    for _ in 0..5 {
+        let mut k = lo;
+        let mut best_loss = f64::MAX;
+
+        while k <= hi + 1e-9 {
+            let l = loss(samples, weights, k, threads);
+
+            if l < best_loss {
+                best_loss = l;
+                best = k;
+            }
+
+            k += step;
+        }
+
+        lo = (best - step).max(0.0);
+        hi = best + step;
+        step /= 10.0;
+    }
+
+    best
+}
+
+/* Gradient of the loss with respect to every weight */
+fn gradient(samples: &[Sample], weights: &[f64], k: f64, threads: usize) -> Vec<f64> {
+    let scale = k * std::f64::consts::LN_10 / 400.0;
+    let mut grad = parallel(samples, threads, |chunk| {
+        let mut grad = vec![0.0; weights.len()];
+
+        for s in chunk {
+            let p = sigmoid(k, s.evaluate(weights));
+            let g = -2.0 * (s.result - p) * p * (1.0 - p) * scale;
+            let phase = s.phase as f64 / MAX_PHASE as f64;
+
+            for (i, n) in s.terms.iter() {
+                grad[2 * *i as usize] += g * *n as f64 * phase;
+                grad[2 * *i as usize + 1] += g * *n as f64 * (1.0 - phase);
+            }
+        }
+
+        grad
+    });
+
+    grad.iter_mut().for_each(|g| *g /= samples.len() as f64);
+    grad
+}
+
+/* Fits every weight in `start` to the samples with Adam, returning the rounded result */
+pub fn tune(samples: &[Sample], start: &Params, config: &Config) -> Params {
+    let mut weights: Vec<f64> = start
+        .values
+        .iter()
+        .flat_map(|s| vec![s.mg as f64, s.eg as f64])
+        .collect();
+
+    let k = fit_k(samples, &weights, config.threads);
+    let (beta1, beta2) = (0.9, 0.999);
+    let mut m = vec![0.0; weights.len()];
+    let mut v = vec![0.0; weights.len()];
+
+    info!(
+        "Tuning {} weights on {} positions, K = {:.3}, loss {:.6}",
+        weights.len(),
+        samples.len(),
+        k,
+        loss(samples, &weights, k, config.threads)
+    );
+
+    for epoch in 1..=config.epochs {
+        let grad = gradient(samples, &weights, k, config.threads);
+
+        for i in 0..weights.len() {
+            m[i] = beta1 * m[i] + (1.0 - beta1) * grad[i];
+            v[i] = beta2 * v[i] + (1.0 - beta2) * grad[i] * grad[i];
+
+            let m_hat = m[i] / (1.0 - beta1.powi(epoch as i32));
+            let v_hat = v[i] / (1.0 - beta2.powi(epoch as i32));
+
+            weights[i] -= config.rate * m_hat / (v_hat.sqrt() + 1e-8);
+        }
+
+        if epoch % 50 == 0 || epoch == config.epochs {
+            info!(
+                "Epoch {}: loss {:.6}",
+                epoch,
+                loss(samples, &weights, k, config.threads)
+            );
+        }
+    }
+
+    Params {
+        values: weights
+            .chunks(2)
+            .map(|w| Score::new(w[0].round() as i32, w[1].round() as i32))
+            .collect(),
+        generation: 0,
+    }
+}
+
+/* Tunes the parameters in use on the positions in `input` and writes them to `output` */
+pub fn run(input: &str, output: &str, config: &Config) -> io::Result<Params> {
+    let samples = load(input, config)?;
+    let params = tune(&samples, &params::current(), config);
+
+    params.save(output)?;
+    Ok(params)
+}
+
+/* Entry point for `nc tune <positions> <output> [epochs] [static]` */
+pub fn cli(args: &[String]) {
+    let epochs = args.get(2).map(|s| s.parse::<usize>());
+    let mode = args.get(3).map(|s| s.as_str());
+
+    if args.len() < 2
+        || args.len() > 4
+        || epochs.as_ref().is_some_and(|e| e.is_err())
+        || mode.is_some_and(|m| m != "static" && m != "qsearch")
+    {
+        error!("usage: nc tune <positions> <output> [epochs] [static|qsearch]");
+        return;
+    }
+
+    let mut config = Config::default();
+
+    if let Some(Ok(e)) = epochs {
+        config.epochs = e;
+    }
+
+    config.qsearch = mode != Some("static");
+
+    match run(&args[0], &args[1], &config) {
+        Ok(_) => info!("Wrote tuned parameters to '{}'", args[1]),
+        Err(e) => error!("Tuning failed: {}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tuner_parses_results() {
+        let fen = "4k3/8/8/8/8/8/8/3QK3 w - - 0 1";
+
+        assert_eq!(
+            parse_line(&format!("{} [1.0]", fen)),
+            Some((fen.to_string(), 1.0))
+        );
+        assert_eq!(
+            parse_line(&format!("{} 0.5", fen)),
+            Some((fen.to_string(), 0.5))
+        );
+        assert_eq!(
+            parse_line("4k3/8/8/8/8/8/8/3QK3 w - - c9 \"0-1\";"),
+            Some((fen.to_string(), 0.0))
+        );
+        assert_eq!(
+            parse_line("4k3/8/8/8/8/8/8/3QK3 w - - 1/2-1/2"),
+            Some((fen.to_string(), 0.5))
+        );
+        assert_eq!(parse_line(&format!("{} 2.0", fen)), None);
+        assert_eq!(parse_line(fen), None);
+    }
+
+    #[test]
+    fn tuner_samples_match_eval() {
+        let params = params::current();
+        let weights: Vec<f64> = params
+            .values
+            .iter()
+            .flat_map(|s| vec![s.mg as f64, s.eg as f64])
+            .collect();
+
+        for fen in [
+            STARTPOS,
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "r1bq1rk1/ppp2ppp/2n5/3np1N1/2B5/8/PPPP1PPP/RNBQK2R b KQ - 0 7",
+        ]
+        .iter()
+        {
+            let p = Position::new(fen.to_string()).unwrap();
+            let s = Sample::new(&p, 0.5);
+            let white = match p.ctm() {
+                Color::WHITE => 1,
+                Color::BLACK => -1,
+            } * eval::evaluate(&p, &mut PawnTable::default());
+
+            assert!((s.evaluate(&weights) - white as f64).abs() < 1.0, "{}", fen);
+
+            /* Each weight appears once */
+            assert!(s.terms.windows(2).all(|w| w[0].0 < w[1].0));
+        }
+    }
+
+    #[test]
+    fn tuner_quiets_captures() {
+        let mut pawns = PawnTable::default();
+        let s = sample("4k3/8/8/3q4/8/8/8/3RK3 w - - 0 1 [0.5]", true, &mut pawns).unwrap();
+        let p = Position::new("4k3/8/8/3R4/8/8/8/4K3 b - - 0 1".to_string()).unwrap();
+
+        assert_eq!(s.terms, Sample::new(&p, 0.5).terms);
+        assert_ne!(
+            sample("4k3/8/8/3q4/8/8/8/3RK3 w - - 0 1 [0.5]", false, &mut pawns)
+                .unwrap()
+                .terms,
+            s.terms
+        );
+    }
+
+    #[test]
+    fn tuner_reduces_loss() {
+        let path = std::env::temp_dir().join("nc_tuner_positions.epd");
+        let output = std::env::temp_dir().join("nc_tuner_params.txt");
+        let (path, output) = (path.to_str().unwrap(), output.to_str().unwrap());
+
+        /* Material decides every game, but the knight is claimed to win more often than a bishop */
+        fs::write(
+            path,
+            "4k3/8/8/8/8/8/8/3QK3 w - - 0 1 [1.0]\n\
+             3qk3/8/8/8/8/8/8/4K3 w - - 0 1 [0.0]\n\
+             4k3/8/8/8/8/8/8/2N1K3 b - - 0 1 [1.0]\n\
+             2n1k3/8/8/8/8/8/8/4K3 w - - 0 1 [0.0]\n\
+             4k3/8/8/8/8/8/8/2B1K3 w - - 0 1 [0.5]\n\
+             2b1k3/8/8/8/8/8/8/4K3 b - - 0 1 [0.5]\n\
+             not a position\n",
+        )
+        .unwrap();
+
+        let config = Config {
+            epochs: 50,
+            threads: 2,
+            ..Config::default()
+        };
+
+        let samples = load(path, &config).unwrap();
+        assert_eq!(samples.len(), 6);
+
+        let start = params::current();
+        let weights = |p: &Params| -> Vec<f64> {
+            p.values
+                .iter()
+                .flat_map(|s| vec![s.mg as f64, s.eg as f64])
+                .collect()
+        };
+
+        let k = fit_k(&samples, &weights(&start), 2);
+        assert!(k > 0.0 && k < 10.0);
+
+        let tuned = run(path, output, &config).unwrap();
+
+        assert!(loss(&samples, &weights(&tuned), k, 2) < loss(&samples, &weights(&start), k, 2));
+        assert!(
+            tuned.values[params::MATERIAL + Type::KNIGHT as usize].eg
+                > tuned.values[params::MATERIAL + Type::BISHOP as usize].eg
+        );
+        assert_eq!(Params::load(output).unwrap(), tuned);
+
+        fs::remove_file(path).unwrap();
+        fs::remove_file(output).unwrap();
+    }
+}
@@ -0,0 +1,643 @@
+use crate::eval::Classical;
+use crate::gmove::*;
+use crate::mcts::*;
+use crate::nn::*;
+use crate::nnue;
+use crate::params::{self, Params};
+use crate::position::*;
+use crate::search::*;
+use crate::smp;
+use crate::timeman::*;
+use crate::trace;
+use crate::tt::*;
+
+use std::io::{self, BufRead};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+pub struct Uci {
+    pos: Position,
+    stop: Arc<AtomicBool>,
+    tt: Arc<TranspositionTable>,
+    overhead: u64,
+    threads: usize,
+    use_mcts: bool,
+    tree: Arc<Mutex<Mcts>>,
+    weights: String,
+    network: Option<Arc<nnue::Network>>, /* Attached to every position for alpha-beta */
+    worker: Option<thread::JoinHandle<()>>,
+}
+
+impl Uci {
+    pub fn new() -> Uci {
+        Uci {
+            pos: Position::new(STARTPOS.to_string()).unwrap(),
+            stop: Arc::new(AtomicBool::new(false)),
+            tt: Arc::new(TranspositionTable::new(DEFAULT_MB)),
+            overhead: DEFAULT_OVERHEAD,
+            threads: smp::DEFAULT_THREADS,
+            use_mcts: false,
+            tree: Arc::new(Mutex::new(Mcts::new(
+                Arc::new(Classical::new()),
+                Config::default(),
+            ))),
+            weights: String::new(),
+            network: None,
+            worker: None,
+        }
+    }
+
+    pub fn run(&mut self) {
+        let stdin = io::stdin();
+
+        for line in stdin.lock().lines() {
+            match line {
+                Ok(l) => {
+                    if !self.command(&l) {
+                        break;
+                    }
+                }
+                Err(e) => {
+                    error!("Failed to read from stdin: {}", e);
+                    break;
+                }
+            }
+        }
+
+        self.stop_search();
+    }
+
+    /* Executes a single command. Returns false when the engine should quit. */
+    pub fn command(&mut self, line: &str) -> bool {
+        let args: Vec<&str> = line.split_whitespace().collect();
+
+        if args.is_empty() {
+            return true;
+        }
+
+        match args[0] {
+            "uci" => {
+                println!("id name Neocortex");
+                println!("id author codeandkey");
+                println!(
+                    "option name Hash type spin default {} min 1 max {}",
+                    DEFAULT_MB, MAX_MB
+                );
+                println!(
+                    "option name Move Overhead type spin default {} min 0 max {}",
+                    DEFAULT_OVERHEAD, MAX_OVERHEAD
+                );
+                println!(
+                    "option name Threads type spin default {} min 1 max {}",
+                    smp::DEFAULT_THREADS,
+                    smp::MAX_THREADS
+                );
+                println!("option name UseMCTS type check default false");
+                println!("option name WeightsFile type string default <empty>");
+                println!("option name ParamsFile type string default <empty>");
+                println!("option name EvalFile type string default <empty>");
+                println!("uciok");
+            }
+            "isready" => println!("readyok"),
+            "ucinewgame" => {
+                self.stop_search();
+                self.pos = Position::new(STARTPOS.to_string()).unwrap();
+                self.pos.set_network(self.network.clone());
+                self.tt.clear();
+                self.tree.lock().unwrap().set_root(&self.pos);
+            }
+            "setoption" => {
+                self.stop_search();
+
+                match parse_option(&args[1..]) {
+                    Some((name, value)) => self.set_option(&name, &value),
+                    None => error!("Invalid setoption command '{}'", line),
+                }
+            }
+            "position" => {
+                self.stop_search();
+
+                match parse_position(&args[1..]) {
+                    Some(p) => {
+                        self.pos = p;
+                        self.pos.set_network(self.network.clone());
+                    }
+                    None => error!("Invalid position command '{}'", line),
+                }
+            }
+            "go" => self.go(parse_limits(&args[1..])),
+            "eval" => {
+                self.stop_search();
+                print!("{}", trace::trace(&self.pos));
+            }
+            "stop" => self.stop_search(),
+            "quit" => return false,
+            _ => warn!("Unknown command '{}'", args[0]),
+        }
+
+        true
+    }
+
+    fn set_option(&mut self, name: &str, value: &str) {
+        match name.to_lowercase().as_str() {
+            "hash" => match value.parse::<usize>() {
+                Ok(mb) if (1..=MAX_MB).contains(&mb) => match Arc::get_mut(&mut self.tt) {
+                    Some(tt) => tt.resize(mb),
+                    None => self.tt = Arc::new(TranspositionTable::new(mb)),
+                },
+                _ => error!("Invalid Hash value '{}'", value),
+            },
+            "move overhead" => match value.parse::<u64>() {
+                Ok(ms) if ms <= MAX_OVERHEAD => self.overhead = ms,
+                _ => error!("Invalid Move Overhead value '{}'", value),
+            },
+            "threads" => match value.parse::<usize>() {
+                Ok(n) if (1..=smp::MAX_THREADS).contains(&n) => self.threads = n,
+                _ => error!("Invalid Threads value '{}'", value),
+            },
+            "usemcts" => match value.parse::<bool>() {
+                Ok(b) => self.use_mcts = b,
+                _ => error!("Invalid UseMCTS value '{}'", value),
+            },
+            "weightsfile" => self.set_weights(value),
+            "paramsfile" => self.set_params(value),
+            "evalfile" => self.set_eval_file(value),
+            _ => warn!("Unknown option '{}'", name),
+        }
+    }
+
+    /* Switches the MCTS evaluator to a network, or back to the classical one if `path` is empty */
+    fn set_weights(&mut self, path: &str) {
+        let path = if path == "<empty>" { "" } else { path };
+
+        let eval: Arc<dyn Evaluator> = if path.is_empty() {
+            Arc::new(Classical::new())
+        } else {
+            match Network::load(path) {
+                Ok(net) => {
+                    info!("Loaded network {:?} from '{}'", net.shape, path);
+                    Arc::new(net)
+                }
+                Err(e) => {
+                    error!("Failed to load weights from '{}': {}", path, e);
+                    return;
+                }
+            }
+        };
+
+        self.tree = Arc::new(Mutex::new(Mcts::new(eval, Config::default())));
+        self.weights = path.to_string();
+    }
+
+    /* Loads tuned evaluation parameters, or restores the defaults if `path` is empty */
+    fn set_params(&mut self, path: &str) {
+        let params = if path.is_empty() || path == "<empty>" {
+            Params::default()
+        } else {
+            match Params::load(path) {
+                Ok(p) => p,
+                Err(e) => {
+                    error!("Failed to load parameters from '{}': {}", path, e);
+                    return;
+                }
+            }
+        };
+
+        params::set(params);
+        self.tt.clear();
+    }
+
+    /* Loads an NNUE network for alpha-beta, or returns to the classical evaluation if `path` is empty */
+    fn set_eval_file(&mut self, path: &str) {
+        let network = if path.is_empty() || path == "<empty>" {
+            None
+        } else {
+            match nnue::Network::load(path) {
+                Ok(net) => {
+                    info!("Loaded NNUE {:?} from '{}'", net.shape, path);
+                    Some(Arc::new(net))
+                }
+                Err(e) => {
+                    error!("Failed to load NNUE from '{}': {}", path, e);
+                    return;
+                }
+            }
+        };
+
+        self.pos.set_network(network.clone());
+        self.network = network;
+        self.tt.clear();
+    }
+
+    fn go(&mut self, mut limits: Limits) {
+        self.stop_search();
+
+        limits.overhead = self.overhead;
+
+        let pos = self.pos.clone();
+        let stop = self.stop.clone();
+        let tt = self.tt.clone();
+        let threads = self.threads;
+        let use_mcts = self.use_mcts;
+        let tree = self.tree.clone();
+
+        self.worker = Some(thread::spawn(move || {
+            let report = |i: &Info| println!("{}", i.to_uci());
+
+            let infinite = limits.infinite;
+            let best = if use_mcts {
+                tree.lock()
+                    .unwrap()
+                    .go(&pos, limits, threads, stop.clone(), report)
+            } else {
+                smp::go(&pos, limits, threads, stop.clone(), tt, report)
+            };
+
+            /* An infinite search may run out of depth, but bestmove must wait for 'stop' */
+            while infinite && !stop.load(Ordering::Relaxed) {
+                thread::sleep(Duration::from_millis(1));
+            }
+
+            println!("bestmove {}", Move::to_uci_withnull(&best));
+        }));
+    }
+
+    fn stop_search(&mut self) {
+        if let Some(w) = self.worker.take() {
+            self.stop.store(true, Ordering::Relaxed);
+            w.join().unwrap();
+        }
+
+        self.stop.store(false, Ordering::Relaxed);
+    }
+}
+
+/* Parses the arguments to a 'position' command */
+pub fn parse_position(args: &[&str]) -> Option<Position> {
+    let moves_at = args
+        .iter()
+        .position(|a| *a == "moves")
+        .unwrap_or(args.len());
+
+    let mut p = match args.first() {
+        Some(&"startpos") => Position::new(STARTPOS.to_string())?,
+        Some(&"fen") => Position::new(args[1..moves_at].join(" "))?,
+        _ => return None,
+    };
+
+    for m in args.iter().skip(moves_at + 1) {
+        let m = Move::from_uci(&m.to_string())?;
+
+        if !p.gen_legal_moves().contains(&m) {
+            error!("Illegal move '{}' in position command", m.to_uci());
+            return None;
+        }
+
+        p.make_move(m);
+    }
+
+    Some(p)
+}
+
+/* Parses the arguments to a 'setoption' command into (name, value) */
+pub fn parse_option(args: &[&str]) -> Option<(String, String)> {
+    if args.first() != Some(&"name") {
+        return None;
+    }
+
+    let value_at = args
+        .iter()
+        .position(|a| *a == "value")
+        .unwrap_or(args.len());
+
+    if value_at < 2 {
+        return None;
+    }
+
+    let value = args.get(value_at + 1..).map_or(Vec::new(), |v| v.to_vec());
+
+    Some((args[1..value_at].join(" "), value.join(" ")))
+}
+
+/* Parses the arguments to a 'go' command */
+pub fn parse_limits(args: &[&str]) -> Limits {
+    let mut limits = Limits::default();
+    let mut i = 0;
+
+    while i < args.len() {
+        let value = args.get(i + 1).and_then(|v| v.parse::<u64>().ok());
+
+        match args[i] {
+            "depth" => limits.depth = value.map(|v| v as usize),
+            "nodes" => limits.nodes = value,
+            "movetime" => limits.movetime = value,
+            "wtime" => limits.wtime = value,
+            "btime" => limits.btime = value,
+            "winc" => limits.winc = value,
+            "binc" => limits.binc = value,
+            "movestogo" => limits.movestogo = value,
+            "infinite" => {
+                limits.infinite = true;
+                i += 1;
+                continue;
+            }
+            _ => {
+                i += 1;
+                continue;
+            }
+        }
+
+        i += 2;
+    }
+
+    limits
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn uci_parse_position_works() {
+        assert_eq!(
+            parse_position(&["startpos"]).unwrap().to_fen(),
+            STARTPOS.to_string()
+        );
+
+        assert_eq!(
+            parse_position(&["startpos", "moves", "e2e4", "e7e5", "g1f3"])
+                .unwrap()
+                .to_fen(),
+            "rnbqkbnr/pppp1ppp/8/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R b KQkq - 1 2"
+        );
+
+        let fen = "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1";
+        let args: Vec<&str> = ["fen"].iter().cloned().chain(fen.split(' ')).collect();
+
+        assert_eq!(parse_position(&args).unwrap().to_fen(), fen);
+    }
+
+    #[test]
+    fn uci_parse_position_rejects_illegal() {
+        assert!(parse_position(&["startpos", "moves", "e2e5"]).is_none());
+        assert!(parse_position(&["nonsense"]).is_none());
+    }
+
+    #[test]
+    fn uci_parse_option_works() {
+        assert_eq!(
+            parse_option(&["name", "Hash", "value", "64"]),
+            Some(("Hash".to_string(), "64".to_string()))
+        );
+        assert_eq!(
+            parse_option(&["name", "Move", "Overhead", "value", "30"]),
+            Some(("Move Overhead".to_string(), "30".to_string()))
+        );
+        assert_eq!(parse_option(&["value", "3"]), None);
+    }
+
+    #[test]
+    fn uci_setoption_hash_resizes_table() {
+        let mut u = Uci::new();
+        u.tt.store(1, 1, Bound::EXACT, 0, None);
+        assert!(u.tt.probe(1).is_some());
+
+        /* Table is reallocated and empty */
+        u.command("setoption name Hash value 1");
+        assert_eq!(u.tt.probe(1), None);
+    }
+
+    #[test]
+    fn uci_setoption_move_overhead_works() {
+        let mut u = Uci::new();
+
+        u.command("setoption name Move Overhead value 120");
+        assert_eq!(u.overhead, 120);
+
+        u.command("setoption name Move Overhead value banana");
+        assert_eq!(u.overhead, 120);
+    }
+
+    #[test]
+    fn uci_setoption_threads_works() {
+        let mut u = Uci::new();
+
+        u.command("setoption name Threads value 4");
+        assert_eq!(u.threads, 4);
+
+        u.command("setoption name Threads value 0");
+        assert_eq!(u.threads, 4);
+    }
+
+    #[test]
+    fn uci_setoption_usemcts_works() {
+        let mut u = Uci::new();
+
+        u.command("setoption name UseMCTS value true");
+        assert!(u.use_mcts);
+
+        u.command("go nodes 50");
+        u.worker.take().unwrap().join().unwrap();
+
+        assert_eq!(u.tree.lock().unwrap().playouts(), 50);
+
+        /* A plain depth limit must terminate without a stop */
+        u.command("ucinewgame");
+        u.command("go depth 2");
+        u.worker.take().unwrap().join().unwrap();
+
+        assert_eq!(u.tree.lock().unwrap().playouts() as u64, depth_playouts(2));
+    }
+
+    #[test]
+    fn uci_go_infinite_waits_for_stop() {
+        for mcts in ["false", "true"].iter() {
+            let mut u = Uci::new();
+
+            /* Black is mated, so both searches end at once */
+            u.command(&format!("setoption name UseMCTS value {}", mcts));
+            u.command("position fen 7k/6Q1/6K1/8/8/8/8/8 b - - 0 1");
+            u.command("go infinite");
+
+            thread::sleep(Duration::from_millis(500));
+            assert!(!u.worker.as_ref().unwrap().is_finished());
+
+            u.command("stop");
+            assert!(u.worker.is_none());
+        }
+    }
+
+    #[test]
+    fn uci_mcts_reuses_tree_across_positions() {
+        let mut u = Uci::new();
+
+        u.command("setoption name UseMCTS value true");
+        u.command("position startpos");
+        u.command("go nodes 200");
+        u.worker.take().unwrap().join().unwrap();
+
+        let (best, visits) = {
+            let tree = u.tree.lock().unwrap();
+            let best = tree.best_move().unwrap();
+
+            (
+                best,
+                tree.root_visits().iter().find(|v| v.0 == best).unwrap().1,
+            )
+        };
+
+        u.command(&format!("position startpos moves {}", best.to_uci()));
+        u.command("go nodes 50");
+        u.worker.take().unwrap().join().unwrap();
+
+        assert_eq!(u.tree.lock().unwrap().playouts(), visits + 50);
+
+        u.command("ucinewgame");
+        assert_eq!(u.tree.lock().unwrap().playouts(), 0);
+    }
+
+    #[test]
+    fn uci_setoption_paramsfile_works() {
+        let mut u = Uci::new();
+        let path = std::env::temp_dir().join("nc_uci_params.txt");
+        let path = path.to_str().unwrap();
+        let before = params::current().generation;
+
+        /* Loading the defaults leaves every evaluation unchanged */
+        Params::default().save(path).unwrap();
+        u.command(&format!("setoption name ParamsFile value {}", path));
+
+        assert!(params::current().generation > before);
+        assert_eq!(params::current().values, Params::default().values);
+
+        u.command("setoption name ParamsFile value /nonexistent/params.txt");
+        u.command("go depth 2");
+        u.worker.take().unwrap().join().unwrap();
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn uci_eval_command_works() {
+        let mut u = Uci::new();
+
+        u.command("position startpos moves e2e4");
+        assert!(u.command("eval"));
+        assert_eq!(u.pos.last_move().unwrap().to_uci(), "e2e4");
+    }
+
+    #[test]
+    fn uci_setoption_evalfile_works() {
+        let mut u = Uci::new();
+        let path = std::env::temp_dir().join("nc_uci_eval.nnue");
+        let path = path.to_str().unwrap();
+
+        let net = nnue::Network::new(nnue::Shape {
+            l1: 16,
+            l2: 32,
+            l3: 32,
+        });
+
+        std::fs::write(path, nnue::tests::to_bytes(&net)).unwrap();
+
+        u.command(&format!("setoption name EvalFile value {}", path));
+        assert_eq!(u.pos.board().nnue(u.pos.ctm()), Some(0));
+
+        /* The network follows new positions, and a bad file keeps it */
+        u.command("position startpos moves e2e4");
+        u.command("setoption name EvalFile value /nonexistent/eval.nnue");
+        assert_eq!(u.pos.board().nnue(u.pos.ctm()), Some(0));
+
+        u.command("go depth 2");
+        u.worker.take().unwrap().join().unwrap();
+
+        u.command("setoption name EvalFile value <empty>");
+        assert_eq!(u.pos.board().nnue(u.pos.ctm()), None);
+
+        u.command("ucinewgame");
+        assert_eq!(u.pos.board().nnue(u.pos.ctm()), None);
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn uci_setoption_weightsfile_works() {
+        let mut u = Uci::new();
+        let path = std::env::temp_dir().join("nc_uci_weights.nn");
+        let path = path.to_str().unwrap();
+
+        Network::new(Shape {
+            filters: 4,
+            blocks: 1,
+            policy_channels: 1,
+            value_channels: 1,
+            value_hidden: 4,
+        })
+        .save(path)
+        .unwrap();
+
+        u.command(&format!("setoption name WeightsFile value {}", path));
+        assert_eq!(u.weights, path);
+
+        /* A bad file keeps the current evaluator */
+        u.command("setoption name WeightsFile value /nonexistent/weights.nn");
+        assert_eq!(u.weights, path);
+
+        u.command("setoption name UseMCTS value true");
+        u.command("go nodes 20");
+        u.worker.take().unwrap().join().unwrap();
+
+        assert_eq!(u.tree.lock().unwrap().playouts(), 20);
+
+        u.command("setoption name WeightsFile value <empty>");
+        assert_eq!(u.weights, "");
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn uci_parse_limits_works() {
+        assert_eq!(
+            parse_limits(&["depth", "5", "nodes", "1000"]),
+            Limits {
+                depth: Some(5),
+                nodes: Some(1000),
+                ..Limits::default()
+            }
+        );
+
+        assert_eq!(
+            parse_limits(&[
+                "wtime",
+                "60000",
+                "btime",
+                "55000",
+                "winc",
+                "1000",
+                "binc",
+                "900",
+                "movestogo",
+                "20",
+            ]),
+            Limits {
+                wtime: Some(60000),
+                btime: Some(55000),
+                winc: Some(1000),
+                binc: Some(900),
+                movestogo: Some(20),
+                ..Limits::default()
+            }
+        );
+
+        assert_eq!(
+            parse_limits(&["infinite", "movetime", "250"]),
+            Limits {
+                movetime: Some(250),
+                infinite: true,
+                ..Limits::default()
+            }
+        );
+    }
+}
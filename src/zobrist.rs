@@ -1,17 +1,28 @@
-use rand::Rng;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+/*
+ * Keys are drawn from fixed seeds, one stream per table, so hashes (and with
+ * them TT indexing and search results) are identical in every process.
+ */
+const SEED: u64 = 0x6e63_7a6f_6272_6973;
+
+fn rng(stream: u64) -> StdRng {
+    StdRng::seed_from_u64(SEED ^ stream)
+}
 
 fn gen_ep_keys() -> [u64; 8] {
-    let mut rng = rand::thread_rng();
+    let mut rng = rng(1);
     arr![rng.gen::<u64>(); 8]
 }
 
 fn gen_castle_keys() -> [[u64; 2]; 2] {
-    let mut rng = rand::thread_rng();
+    let mut rng = rng(2);
     [arr![rng.gen::<u64>(); 2], arr![rng.gen::<u64>(); 2]]
 }
 
 fn gen_piece_keys() -> [[u64; 12]; 64] {
-    let mut rng = rand::thread_rng();
+    let mut rng = rng(3);
     let mut out: [[u64; 12]; 64] = [[0; 12]; 64];
 
     for s in 0..64 {
@@ -22,7 +33,7 @@ fn gen_piece_keys() -> [[u64; 12]; 64] {
 }
 
 lazy_static! {
-    pub static ref BLACK_TO_MOVE: u64 = rand::thread_rng().gen::<u64>();
+    pub static ref BLACK_TO_MOVE: u64 = rng(0).gen::<u64>();
     pub static ref EN_PASSANT: [u64; 8] = gen_ep_keys();
     pub static ref CASTLE: [[u64; 2]; 2] = gen_castle_keys();
     pub static ref PIECE: [[u64; 12]; 64] = gen_piece_keys();